@@ -1,5 +1,56 @@
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
 fn main() {
+    // The host tools are linked as usual.
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("none") {
+        return;
+    }
+
+    // Our own memory.x, with the bootloader partitions.
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::copy("memory.x", out.join("memory.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=memory.x");
+
+    // The keys, one set per deployment.
+    key(
+        "OTA_PUBLIC_KEY",
+        "the public key the firmware updates are verified with. Make one with \
+         `lora2traffic-ota public-key <secret key> <public key>`",
+        32,
+        &out.join("ota.pub"),
+    );
+    key(
+        "CONFIG_AUTH_KEY",
+        "the key config changes are authenticated with, shared by all the nodes and \
+         `lora2traffic-cli`. Make one with `head -c 16 /dev/urandom > config.key`",
+        16,
+        &out.join("config.key"),
+    );
+    key(
+        "PREEMPT_AUTH_KEY",
+        "the key pre-emption requests are authenticated with, shared by the controllers and the \
+         nodes allowed to pre-empt them. Make one with `head -c 16 /dev/urandom > preempt.key`",
+        16,
+        &out.join("preempt.key"),
+    );
+
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 }
+
+/// Copy the `len` bytes long key from the file given by the `var` environment variable to `to`,
+/// for the firmware to include it.
+fn key(var: &str, what: &str, len: usize, to: &Path) {
+    println!("cargo:rerun-if-env-changed={var}");
+    let Some(path) = env::var_os(var) else {
+        panic!("Set {var} to the file of {what}.");
+    };
+    let key =
+        fs::read(&path).unwrap_or_else(|e| panic!("Failed to read {var} {}: {e}", path.display()));
+    assert_eq!(key.len(), len, "{var} must be {len} bytes long");
+    fs::write(to, key).unwrap();
+    println!("cargo:rerun-if-changed={}", PathBuf::from(path).display());
+}
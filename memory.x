@@ -0,0 +1,16 @@
+/* STM32WLE5JC: 256K of flash, 64K of RAM. */
+MEMORY
+{
+  BOOTLOADER                        : ORIGIN = 0x08000000, LENGTH = 24K
+  BOOTLOADER_STATE                  : ORIGIN = 0x08006000, LENGTH = 4K
+  FLASH                             : ORIGIN = 0x08007000, LENGTH = 108K
+  DFU                               : ORIGIN = 0x08022000, LENGTH = 110K
+  /* 2K unused, then the 4K of the event log and the 4K of the config store, at the end. */
+  RAM                         (rwx) : ORIGIN = 0x20000000, LENGTH = 64K
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOTLOADER);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOTLOADER);
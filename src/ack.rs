@@ -0,0 +1,167 @@
+use defmt::warn;
+use heapless::Vec;
+
+use crate::{time_on_air_us, Address, OperatingMode};
+
+/// The receivers yet to ACK a command.
+#[derive(defmt::Format, Clone)]
+pub struct AckTracker {
+    pending: Vec<Address, MAX_RECEIVERS>,
+}
+
+impl AckTracker {
+    pub fn new(receivers: impl IntoIterator<Item = Address>) -> Self {
+        let mut pending = Vec::new();
+        for receiver in receivers {
+            if pending.contains(&receiver) {
+                continue;
+            }
+            if pending.push(receiver).is_err() {
+                warn!(
+                    "Too many receivers, not expecting an ACK from {:?}",
+                    receiver
+                );
+            }
+        }
+
+        Self { pending }
+    }
+
+    /// An ACK from `src`. Returns whether it was expected.
+    pub fn ack(&mut self, src: Address) -> bool {
+        match self.pending.iter().position(|receiver| *receiver == src) {
+            Some(i) => {
+                self.pending.swap_remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn pending(&self) -> &[Address] {
+        &self.pending
+    }
+
+    pub fn is_pending(&self, receiver: Address) -> bool {
+        self.pending.contains(&receiver)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// What the controller does when a receiver doesn't ACK a signal change.
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub enum AckPolicy {
+    /// Carry on. The receiver's heartbeat tells what it shows, and gets it corrected.
+    Proceed,
+    /// Stay in the phase, under manual control, until the operator or the manual timeout moves on.
+    Hold,
+    /// Flash yellow on all the heads, until the operator gives control back.
+    FailSafe,
+}
+
+impl AckPolicy {
+    /// The operating mode to switch to, if any.
+    pub fn operating_mode(self) -> Option<OperatingMode> {
+        match self {
+            Self::Proceed => None,
+            Self::Hold => Some(OperatingMode::Manual),
+            Self::FailSafe => Some(OperatingMode::Flash),
+        }
+    }
+}
+
+/// How long a receiver waits before ACKing a broadcast, for the receivers to take turns.
+///
+/// The slot is given by the address, so the receivers of a controller must have addresses that
+/// differ modulo [`MAX_RECEIVERS`], e.g consecutive ones.
+pub fn ack_delay_ms(address: Address) -> u64 {
+    (address.0 as usize % MAX_RECEIVERS) as u64 * ACK_SLOT_MS
+}
+
+/// Receivers of a controller, e.g one per signal head of the intersection.
+pub const MAX_RECEIVERS: usize = 4;
+/// How long the ACKs to a broadcast take once it's sent, when it may be forwarded `ttl` times.
+///
+/// Beyond the round of the receivers in range, every hop adds a round for the repeater to wait
+/// for their ACKs, a slot to forward the broadcast, and a round to bring back the ACKs of the
+/// receivers it reached, see [`crate::Repeater`].
+pub const fn ack_window_ms(ttl: u8) -> u64 {
+    ACK_ROUND_MS + ttl as u64 * (2 * ACK_ROUND_MS + ACK_SLOT_MS)
+}
+
+/// How much longer the reply to a packet for a single node takes, when they may be forwarded `ttl`
+/// times: at every hop, both ways, a slot waited by the repeater and one on air.
+pub const fn forwarding_delay_ms(ttl: u8) -> u64 {
+    ttl as u64 * 4 * ACK_SLOT_MS
+}
+
+/// Each receiver's turn to ACK: the time on air of the longest command or ACK, and a guard time.
+pub const ACK_SLOT_MS: u64 = time_on_air_us(LONGEST_FRAME_LEN).div_ceil(1000) + ACK_GUARD_MS;
+/// The slots of all the receivers, that a broadcast opens wherever it's heard.
+pub const ACK_ROUND_MS: u64 = MAX_RECEIVERS as u64 * ACK_SLOT_MS;
+/// The frame of a `TimeSync`, the longest of the broadcast commands and their ACKs.
+const LONGEST_FRAME_LEN: usize = 17;
+/// For the receivers' clocks and their radios to switch between RX and TX.
+const ACK_GUARD_MS: u64 = 500;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Message, Packet, PedestrianSignal, Signal, SyncState, SyncStatus, DEFAULT_TTL};
+
+    fn frame_len(msg: Message) -> usize {
+        let packet = Packet {
+            src: Address(2),
+            dst: Address(1),
+            seq: Some(0),
+            ttl: DEFAULT_TTL,
+            msg,
+        };
+
+        packet.to_bytes().len()
+    }
+
+    #[test]
+    fn longest_frame() {
+        assert_eq!(frame_len(Message::TimeSync(0)), LONGEST_FRAME_LEN);
+
+        let status = SyncStatus {
+            state: SyncState::Synced,
+            offset_ms: 0,
+            drift_ppm: 0,
+        };
+        let frames = [
+            Message::SyncStatus(status),
+            Message::Signal(Signal::Green),
+            Message::PedestrianSignal {
+                signal: PedestrianSignal::Walk,
+                countdown_s: 0,
+            },
+            Message::OperatingMode(OperatingMode::Flash),
+        ];
+        for frame in frames {
+            assert!(frame_len(frame) <= LONGEST_FRAME_LEN);
+        }
+    }
+
+    #[test]
+    fn slots_fit_acks() {
+        // 48.25 symbols of 65.536 ms.
+        assert_eq!(time_on_air_us(LONGEST_FRAME_LEN), 3_162_112);
+        assert!(ACK_SLOT_MS * 1000 > time_on_air_us(LONGEST_FRAME_LEN));
+        assert_eq!(ack_delay_ms(Address(5)), ACK_SLOT_MS);
+        assert_eq!(ack_delay_ms(Address(3)) + ACK_SLOT_MS, ACK_ROUND_MS);
+    }
+
+    #[test]
+    fn window_scales_with_hops() {
+        assert_eq!(ack_window_ms(0), ACK_ROUND_MS);
+        // Two repeaters: the round of each hop, the forwarding of the broadcast twice, then the
+        // ACKs back from each hop.
+        assert_eq!(ack_window_ms(2), 5 * ACK_ROUND_MS + 2 * ACK_SLOT_MS);
+        assert_eq!(forwarding_delay_ms(0), 0);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::Signal;
+
+/// Settings for fully-actuated control, where vehicle detectors decide how long red and green last.
+///
+/// Green serves the main road, while red serves the side road (and pedestrians). Both are extended
+/// while their detectors see traffic and end when there's a gap in it (gap-out) or their maximum
+/// is reached (max-out), but only if the other phase is wanted. Otherwise, the phase rests.
+#[derive(defmt::Format, Clone, Copy)]
+pub struct Actuation {
+    pub green: PhaseTiming,
+    pub red: PhaseTiming,
+    /// The phase (green or red) each detector calls and extends, indexed by detector number.
+    pub detectors: &'static [Signal],
+}
+
+impl Actuation {
+    /// The timing of `signal`, if it's actuated.
+    pub fn timing(&self, signal: Signal) -> Option<&PhaseTiming> {
+        match signal {
+            Signal::Green => Some(&self.green),
+            Signal::Red => Some(&self.red),
+            Signal::Yellow | Signal::Off => None,
+        }
+    }
+}
+
+#[derive(defmt::Format, Clone, Copy)]
+pub struct PhaseTiming {
+    pub min_s: u16,
+    /// Counted from when the other phase is wanted.
+    pub max_s: u16,
+    /// How long after the last vehicle left a detector, the phase ends.
+    pub passage_s: u16,
+    pub recall: Recall,
+}
+
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub enum Recall {
+    /// The phase is only served if there's traffic wanting it.
+    None,
+    /// The phase is always served, at least for its minimum time.
+    Min,
+    /// The phase is always served for its maximum time.
+    Max,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ControlMode, Controller};
+
+    const ACTUATION: Actuation = Actuation {
+        green: PhaseTiming {
+            min_s: 10,
+            max_s: 40,
+            passage_s: 3,
+            recall: Recall::None,
+        },
+        red: PhaseTiming {
+            min_s: 5,
+            max_s: 30,
+            passage_s: 3,
+            recall: Recall::None,
+        },
+        detectors: &[Signal::Green, Signal::Red],
+    };
+    const MAIN: u8 = 0;
+    const SIDE: u8 = 1;
+
+    /// A controller starting `signal` at 0, fed the detector `trace` of (time, detector, occupied).
+    fn run(actuation: Actuation, signal: Signal, trace: &[(u64, u8, bool)]) -> Controller {
+        let mut controller = Controller::new(ControlMode::Actuated(actuation), None);
+        controller.start(signal, 0, None);
+        for &(uptime_ms, detector, occupied) in trace {
+            controller.detection(detector, occupied, uptime_ms);
+        }
+
+        controller
+    }
+
+    #[test]
+    fn rests_without_demand() {
+        let trace = [(1_000, MAIN, true), (3_000, MAIN, false)];
+        let controller = run(ACTUATION, Signal::Green, &trace);
+
+        assert_eq!(controller.phase_end_ms(), u64::MAX);
+    }
+
+    #[test]
+    fn gap_out() {
+        // The side road waits from 5 s, the main road empties at 15 s.
+        let trace = [
+            (1_000, MAIN, true),
+            (5_000, SIDE, true),
+            (15_000, MAIN, false),
+        ];
+        let mut controller = run(ACTUATION, Signal::Green, &trace);
+        assert_eq!(controller.phase_end_ms(), 18_000);
+
+        // Another vehicle before the gap is over, the phase goes on.
+        controller.detection(MAIN, true, 17_000);
+        assert_eq!(controller.phase_end_ms(), 45_000);
+        controller.detection(MAIN, false, 20_000);
+        assert_eq!(controller.phase_end_ms(), 23_000);
+    }
+
+    #[test]
+    fn gap_out_after_min() {
+        let trace = [
+            (1_000, MAIN, true),
+            (2_000, MAIN, false),
+            (3_000, SIDE, true),
+        ];
+        let controller = run(ACTUATION, Signal::Green, &trace);
+
+        assert_eq!(controller.phase_end_ms(), 10_000);
+    }
+
+    #[test]
+    fn max_out() {
+        // A vehicle every 2 s on the main road, the side road waiting from 5 s.
+        let mut controller = run(ACTUATION, Signal::Green, &[(5_000, SIDE, true)]);
+        for i in 0..22 {
+            controller.detection(MAIN, true, 1_000 + i * 2_000);
+            controller.detection(MAIN, false, 2_000 + i * 2_000);
+        }
+        assert_eq!(controller.phase_end_ms(), 45_000);
+
+        assert_eq!(controller.advance(45_000, None), Signal::Off);
+    }
+
+    #[test]
+    fn min_recall() {
+        assert_eq!(run(ACTUATION, Signal::Red, &[]).phase_end_ms(), u64::MAX);
+
+        // Green is served without traffic wanting it, once red is over.
+        let mut actuation = ACTUATION;
+        actuation.green.recall = Recall::Min;
+        let controller = run(actuation, Signal::Red, &[]);
+        assert_eq!(controller.phase_end_ms(), 5_000);
+    }
+
+    #[test]
+    fn max_recall() {
+        let mut actuation = ACTUATION;
+        actuation.red.recall = Recall::Min;
+        assert_eq!(run(actuation, Signal::Green, &[]).phase_end_ms(), 10_000);
+
+        // Green lasts its maximum, even without traffic.
+        actuation.green.recall = Recall::Max;
+        let controller = run(actuation, Signal::Green, &[]);
+        assert_eq!(controller.phase_end_ms(), 40_000);
+    }
+}
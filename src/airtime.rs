@@ -0,0 +1,25 @@
+/// Time on air, in microseconds, of a packet with a payload of `payload_len` bytes, with the
+/// modulation and packet parameters used by `LoraHw`: SF12, 62.5 kHz and a 4/8 coding rate.
+pub const fn time_on_air_us(payload_len: usize) -> u64 {
+    // 2^SF / BW = 4096 / 62.5 kHz.
+    const SYMBOL_US: u64 = 65_536;
+    const SF: i64 = 12;
+    // 4/8 coding rate.
+    const CR: u64 = 4;
+
+    // Explicit header and CRC on. Low data rate optimization is always on for SF12 @ 62.5 kHz.
+    let payload_bits = 8 * payload_len as i64 - 4 * SF + 28 + 16;
+    let payload_bits = if payload_bits > 0 {
+        payload_bits as u64
+    } else {
+        0
+    };
+    let payload_symbols = 8 + payload_bits.div_ceil(4 * (SF as u64 - 2)) * (CR + 4);
+    // The preamble is followed by 4.25 symbols of sync word.
+    let preamble_quarter_symbols = PREAMBLE_LEN as u64 * 4 + 17;
+
+    preamble_quarter_symbols * SYMBOL_US / 4 + payload_symbols * SYMBOL_US
+}
+
+/// In symbols.
+pub const PREAMBLE_LEN: u16 = 4;
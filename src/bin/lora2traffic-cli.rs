@@ -0,0 +1,666 @@
+//! Talks to the LoRa network from the host, through the serial gateway (`lora2traffic-gateway`).
+//!
+//! Build and run it on the host, e.g:
+//!
+//! ```sh
+//! cargo run --no-default-features --features std --target x86_64-unknown-linux-gnu \
+//!     --bin lora2traffic-cli -- /dev/ttyUSB0 status 2
+//! ```
+//!
+//! The serial port must be set up first, e.g with `stty -F /dev/ttyUSB0 115200 raw -echo`.
+//!
+//! The nodes are given by their address. The receivers only take `set-signal` in maintenance
+//! (with the jumper on), the controller would fight it otherwise. Config changes, and the start
+//! of updates, are authenticated with the key the nodes were built with, from the file given by
+//! the `CONFIG_AUTH_KEY` environment variable. Config changes are confirmed right away:
+//!
+//! ```sh
+//! CONFIG_AUTH_KEY=config.key cargo run --no-default-features --features std \
+//!     --target x86_64-unknown-linux-gnu --bin lora2traffic-cli -- \
+//!     /dev/ttyUSB0 set-config 2 red-time 45
+//! ```
+//!
+//! Make the key once per deployment, with `head -c 16 /dev/urandom > config.key`, and keep it out
+//! of the repository: whoever has it can reconfigure the nodes, and erase their updates.
+//!
+//! `sniff` follows the capture of `lora2traffic-sniffer` instead, on its own serial port, and
+//! saves it for Wireshark if given a file.
+//!
+//! Without a gateway, `emulate` emulates one on a pseudo-terminal, e.g from
+//! `socat -d -d pty,raw,echo=0 pty,raw,echo=0`: run `emulate` on one end and the other commands on
+//! the other end. The emulated nodes answer most commands.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{env, process};
+
+use lora2traffic::*;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["emulate", port] => emulate(port),
+        [port, "gateway", command @ ..] => gateway(port, command),
+        [port, "monitor"] => monitor(port),
+        [port, "sniff"] => sniff(port, None),
+        [port, "sniff", file] => sniff(port, Some(file)),
+        [port, "log", "dump", node] => match node.parse() {
+            Ok(node) => open(port).and_then(|mut gateway| dump_log(&mut gateway, Address(node))),
+            Err(_) => Err(format!("Invalid node address: {node}")),
+        },
+        [port, command, node, args @ ..] => match node.parse() {
+            Ok(node) => node_command(port, command, Address(node), args),
+            Err(_) => Err(format!("Invalid node address: {node}")),
+        },
+        _ => Err(USAGE.to_string()),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
+
+fn node_command(port: &str, command: &str, node: Address, args: &[&str]) -> Result<(), String> {
+    match (command, args) {
+        ("status", []) => status(&mut open(port)?, node),
+        ("set-signal", [signal]) => set_signal(&mut open(port)?, node, parse_signal(signal)?),
+        ("get-config", [key]) => get_config(&mut open(port)?, node, config_key(key)?),
+        ("set-config", [key, value]) => {
+            let (key, value) = (config_key(key)?, config_value(value)?);
+            set_config_cmd(&mut open(port)?, node, key, value)
+        }
+        ("ota", [update]) => ota(&mut open(port)?, node, update),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn status(gateway: &mut Gateway, node: Address) -> Result<(), String> {
+    let status = request(
+        gateway,
+        node,
+        || Message::QueryStatus,
+        |msg| match msg {
+            Message::Status(status) => Some(status),
+            _ => None,
+        },
+    )?;
+    println!("Node {}: {status:#?}", node.0);
+
+    Ok(())
+}
+
+fn set_signal(gateway: &mut Gateway, node: Address, signal: Signal) -> Result<(), String> {
+    let ack = |msg| matches!(msg, Message::Signal(s) if s == signal).then_some(());
+    request(gateway, node, || Message::Signal(signal), ack)
+        .map_err(|e| format!("{e}, is it in maintenance?"))?;
+    println!("Node {} shows {signal:?}", node.0);
+
+    Ok(())
+}
+
+fn get_config(gateway: &mut Gateway, node: Address, key: ConfigKey) -> Result<(), String> {
+    let value = request(gateway, node, || Message::GetConfig(key), config_reply(key))?;
+    println!("{}", format_config(key, value));
+
+    Ok(())
+}
+
+/// Set `key` to `value` on `node`, and confirm the change. The gateway follows the node to another
+/// frequency.
+fn set_config_cmd(
+    gateway: &mut Gateway,
+    node: Address,
+    key: ConfigKey,
+    value: u32,
+) -> Result<(), String> {
+    let auth_key = config_auth_key()?;
+    let gateway_address = gateway.status().map_err(|e| e.to_string())?.address;
+    let mut counter = config_counter();
+    let msg = || {
+        counter += 1;
+        set_config(&auth_key, gateway_address, node, key, value, counter)
+    };
+    let applied = request(gateway, node, msg, config_reply(key))?;
+    if applied != value {
+        return Err(format!(
+            "Node {} kept {}, the value must be invalid",
+            node.0,
+            format_config(key, applied)
+        ));
+    }
+
+    // The node runs with the change already.
+    let node = match key {
+        ConfigKey::Address => Address(value as u8),
+        _ => node,
+    };
+    let frequency = match key {
+        ConfigKey::Frequency => {
+            let frequency = gateway.get_config(key).map_err(|e| e.to_string())?;
+            gateway
+                .set_config(key, value)
+                .map_err(|e| format!("{e}, the change is rolled back within a minute"))?;
+            Some(frequency)
+        }
+        _ => None,
+    };
+    let msg = || {
+        counter += 1;
+        confirm_config(&auth_key, gateway_address, node, counter)
+    };
+    let ack = |msg| matches!(msg, Message::ConfirmConfig { .. }).then_some(());
+    if let Err(e) = request(gateway, node, msg, ack) {
+        if let Some(frequency) = frequency {
+            let _ = gateway.set_config(ConfigKey::Frequency, frequency);
+        }
+        return Err(format!("{e}, the change is rolled back within a minute"));
+    }
+    println!("{}, confirmed", format_config(key, value));
+
+    Ok(())
+}
+
+fn dump_log(gateway: &mut Gateway, node: Address) -> Result<(), String> {
+    let mut from = 0;
+    loop {
+        let page = request(
+            gateway,
+            node,
+            || Message::GetEvents { from },
+            |msg| match msg {
+                Message::Events(page) => Some(page),
+                _ => None,
+            },
+        )?;
+        for event in page.events() {
+            let time = chrono::DateTime::from_timestamp(event.time_s as i64, 0)
+                .map_or(event.time_s.to_string(), |time| time.to_string());
+            println!("{:>6} {time} {:?}", event.seq, event.kind);
+        }
+
+        match page.next() {
+            Some(next) if page.events().len() == EVENTS_PER_PAGE => from = next,
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Send the update made by `lora2traffic-ota prepare` to `node`, as the simulation of
+/// `lora2traffic-ota` does.
+fn ota(gateway: &mut Gateway, node: Address, update: &str) -> Result<(), String> {
+    let update = fs::read(update).map_err(|e| format!("Failed to read {update}: {e}"))?;
+    let (firmware, signature) = update
+        .split_last_chunk::<SIGNATURE_LEN>()
+        .ok_or("The update is too short")?;
+    let image = OtaImage::new(firmware, *signature);
+    println!(
+        "Sending {} bytes of firmware in {} fragments, about {} minutes on air",
+        image.len(),
+        image.fragments(),
+        image.fragments() as u64 * OtaImage::fragment_time_on_air_us() / 60_000_000
+    );
+
+    let auth_key = config_auth_key()?;
+    let gateway_address = gateway.status().map_err(|e| e.to_string())?.address;
+    let len = image.len();
+    let mut counter = config_counter();
+    let msg = || {
+        counter += 1;
+        ota_start(&auth_key, gateway_address, node, len, counter)
+    };
+    let ack = |msg| matches!(msg, Message::OtaStart { len: l, .. } if l == len).then_some(());
+    request(gateway, node, msg, ack)?;
+    let mut to_send: Vec<u16> = (0..image.fragments()).collect();
+    loop {
+        for (i, &index) in to_send.iter().enumerate() {
+            print!("\rFragment {}/{}", i + 1, to_send.len());
+            let _ = io::stdout().flush();
+            let fragment = image.fragment(index);
+            let msg = Message::OtaFragment { index, fragment };
+            gateway.send(node, msg).map_err(|e| e.to_string())?;
+        }
+        println!();
+
+        let missing = |msg| match msg {
+            Message::OtaMissing { first, bitmap } => Some((first, bitmap)),
+            _ => None,
+        };
+        let (first, bitmap) = request(gateway, node, || Message::OtaStatus, missing)?;
+        if bitmap == 0 {
+            break;
+        }
+        to_send = (0..32)
+            .filter(|i| bitmap & 1 << i != 0)
+            .map(|i| first + i)
+            .collect();
+        println!("{} fragments missing", to_send.len());
+    }
+
+    let ack = |msg| matches!(msg, Message::OtaFinish).then_some(());
+    request(gateway, node, || Message::OtaFinish, ack)
+        .map_err(|e| format!("{e}, the update must have been rejected"))?;
+    println!("Update verified, node {} restarts into it", node.0);
+
+    Ok(())
+}
+
+/// Print the packets the gateway receives, those for it and the broadcasts.
+fn monitor(port: &str) -> Result<(), String> {
+    let mut gateway = open(port)?;
+    loop {
+        match gateway.receive(Duration::from_secs(3600)) {
+            Ok(Some(reception)) => {
+                println!(
+                    "{} {} dBm, SNR {} dB: {}",
+                    format_time(now_us()),
+                    reception.rssi_dbm,
+                    reception.snr_db,
+                    format_packet(&reception.packet)
+                );
+            }
+            Ok(None) => {}
+            Err(e @ HostError::Serial(_)) => eprintln!("{e}"),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+/// Print the captures of `lora2traffic-sniffer`, with the host's time, and save them to `file`.
+fn sniff(port: &str, file: Option<&str>) -> Result<(), String> {
+    let read_error = |e| format!("Failed to read {port}: {e}");
+    let mut stream = BufReader::new(File::open(port).map_err(read_error)?);
+    let mut file = file
+        .map(|file| File::create(file).map_err(|e| format!("Failed to create {file}: {e}")))
+        .transpose()?;
+    let write_error = |e| format!("Failed to write the capture: {e}");
+    if let Some(file) = &mut file {
+        file.write_all(&PCAP_HEADER).map_err(write_error)?;
+    }
+
+    // The records can only be told apart from the start.
+    println!("Waiting for the capture to start, reset the sniffer");
+    let mut header = [0; PCAP_HEADER.len()];
+    while header != PCAP_HEADER {
+        header.rotate_left(1);
+        stream
+            .read_exact(&mut header[PCAP_HEADER.len() - 1..])
+            .map_err(read_error)?;
+    }
+
+    let mut offset_us = None;
+    loop {
+        let mut record = vec![0; RECORD_HEADER_LEN];
+        stream.read_exact(&mut record).map_err(read_error)?;
+        let len = record_len(&record).ok_or(OUT_OF_SYNC)?;
+        record.resize(len, 0);
+        stream
+            .read_exact(&mut record[RECORD_HEADER_LEN..])
+            .map_err(read_error)?;
+        let mut capture = Capture::from_record(&record).ok_or(OUT_OF_SYNC)?;
+        // The sniffer's time is since its startup.
+        capture.at_us += *offset_us.get_or_insert(now_us().saturating_sub(capture.at_us));
+
+        let time = format_time(capture.at_us);
+        let (rssi_dbm, snr_db) = (capture.rssi_dbm, capture.snr_db);
+        match Packet::from_bytes(&capture.bytes) {
+            _ if capture.is_corrupt() => println!("{time} corrupt frame"),
+            Ok(packet) => println!(
+                "{time} {rssi_dbm} dBm, SNR {snr_db} dB: {}",
+                format_packet(&packet)
+            ),
+            Err(e) => println!(
+                "{time} {rssi_dbm} dBm, SNR {snr_db} dB: undecodable frame, {e:?}: {:02x?}",
+                &capture.bytes[..]
+            ),
+        }
+        if let Some(file) = &mut file {
+            file.write_all(&capture.to_record())
+                .and_then(|()| file.flush())
+                .map_err(write_error)?;
+        }
+    }
+}
+
+fn gateway(port: &str, command: &[&str]) -> Result<(), String> {
+    let mut gateway = open(port)?;
+    match command {
+        ["status"] => {
+            let status = gateway.status().map_err(|e| e.to_string())?;
+            println!(
+                "Gateway {}, protocol version {:#x}, up for {} s",
+                status.address.0, status.version, status.uptime_s
+            );
+            println!("{} Hz, {} dBm", status.frequency_hz, status.tx_power_dbm);
+            println!(
+                "{} packets received, {} sent, {} radio errors",
+                status.received, status.sent, status.errors
+            );
+        }
+        ["get-config", key] => {
+            let key = config_key(key)?;
+            let value = gateway.get_config(key).map_err(|e| e.to_string())?;
+            println!("{}", format_config(key, value));
+        }
+        ["set-config", key, value] => {
+            let key = config_key(key)?;
+            let value = config_value(value)?;
+            let value = gateway.set_config(key, value).map_err(|e| e.to_string())?;
+            println!("{}", format_config(key, value));
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+
+    Ok(())
+}
+
+/// Send the message `msg` gives to `node`, until the reply `reply` picks comes.
+fn request<T>(
+    gateway: &mut Gateway,
+    node: Address,
+    mut msg: impl FnMut() -> Message,
+    reply: impl Fn(Message) -> Option<T>,
+) -> Result<T, String> {
+    for attempt in 1..=ATTEMPTS {
+        gateway.send(node, msg()).map_err(|e| e.to_string())?;
+
+        let deadline = Instant::now() + NODE_REPLY_TIMEOUT;
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            match gateway.receive(timeout) {
+                Ok(Some(reception)) if reception.packet.src == node => {
+                    if let Some(reply) = reply(reception.packet.msg) {
+                        return Ok(reply);
+                    }
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(e @ HostError::Serial(_)) => eprintln!("{e}"),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        eprintln!("No reply from node {} ({attempt}/{ATTEMPTS})", node.0);
+    }
+
+    Err(format!("Node {} didn't reply", node.0))
+}
+
+fn config_reply(key: ConfigKey) -> impl Fn(Message) -> Option<u32> {
+    move |msg| match msg {
+        Message::ConfigValue { key: k, value } if k == key => Some(value),
+        _ => None,
+    }
+}
+
+/// Config changes must be numbered in increasing order. Eighths of seconds since 2024 are, at a
+/// change a second and until 2041.
+/// The key from the file given by `CONFIG_AUTH_KEY`.
+fn config_auth_key() -> Result<AuthKey, String> {
+    let path = env::var("CONFIG_AUTH_KEY")
+        .map_err(|_| "Set CONFIG_AUTH_KEY to the file of the key the nodes were built with")?;
+    let key = fs::read(&path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+
+    key.try_into()
+        .map_err(|_| format!("{path} must be {} bytes long", size_of::<AuthKey>()))
+}
+
+fn config_counter() -> u32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+
+    (now.saturating_sub(COUNTER_EPOCH_S) * 8) as u32
+}
+
+/// Emulate a gateway on `port`. The emulated nodes echo the commands back as their ACK, and
+/// answer the queries.
+fn emulate(port: &str) -> Result<(), String> {
+    let mut reader = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(port)
+        .map_err(|e| format!("Failed to open {port}: {e}"))?;
+    let mut writer = reader
+        .try_clone()
+        .map_err(|e| format!("Failed to open {port}: {e}"))?;
+    let mut config = Config {
+        address: GATEWAY_ADDRESS,
+        // `LORA_FREQUENCY_IN_HZ`, of the firmware.
+        frequency_hz: 434_000_000,
+        tx_power_dbm: 20,
+        failsafe_timeout_s: 0,
+        red_s: 30,
+        green_s: 30,
+    };
+    let started = Instant::now();
+    let mut status = GatewayStatus {
+        version: PROTOCOL_VERSION,
+        address: config.address,
+        frequency_hz: config.frequency_hz,
+        tx_power_dbm: config.tx_power_dbm,
+        uptime_s: 0,
+        received: 0,
+        sent: 0,
+        errors: 0,
+    };
+    println!("Emulating a gateway on {port}");
+
+    let mut decoder = SlipDecoder::new();
+    let mut buf = [0; 256];
+    loop {
+        let len = reader
+            .read(&mut buf)
+            .map_err(|e| format!("Failed to read {port}: {e}"))?;
+        if len == 0 {
+            return Ok(());
+        }
+
+        for byte in &buf[..len] {
+            let Some(frame) = decoder.push(*byte) else {
+                continue;
+            };
+            let mut replies = vec![];
+            match HostMessage::from_frame(frame) {
+                Ok(HostMessage::Send(packet)) => {
+                    println!("Sending to {}: {:?}", packet.dst.0, packet.msg);
+                    status.sent += 1;
+                    replies.push(GatewayMessage::Sent);
+                    let uptime_s = started.elapsed().as_secs() as u32;
+                    let reply = emulated_reply(packet.dst, packet.msg, uptime_s);
+                    if let (false, Some(msg)) = (packet.dst == Address::BROADCAST, reply) {
+                        status.received += 1;
+                        let packet = Packet {
+                            src: packet.dst,
+                            dst: config.address,
+                            seq: Some(status.received as u16),
+                            ttl: DEFAULT_TTL,
+                            msg,
+                        };
+                        replies.push(GatewayMessage::Received(Reception {
+                            rssi_dbm: -80,
+                            snr_db: 10,
+                            packet,
+                        }));
+                    }
+                }
+                Ok(HostMessage::QueryStatus) => {
+                    status.uptime_s = started.elapsed().as_secs() as u32;
+                    replies.push(GatewayMessage::Status(status));
+                }
+                Ok(HostMessage::GetConfig(key)) => replies.push(GatewayMessage::ConfigValue {
+                    key,
+                    value: config.get(key),
+                }),
+                Ok(HostMessage::SetConfig { key, value }) => match config.with(key, value) {
+                    Some(new_config) => {
+                        println!("Setting {}", format_config(key, value));
+                        config = new_config;
+                        status.address = config.address;
+                        status.frequency_hz = config.frequency_hz;
+                        status.tx_power_dbm = config.tx_power_dbm;
+                        replies.push(GatewayMessage::ConfigValue { key, value });
+                    }
+                    None => replies.push(GatewayMessage::Error(GatewayError::InvalidConfig)),
+                },
+                Err(e) => {
+                    println!("Undecodable frame from the host: {e:?}");
+                    replies.push(GatewayMessage::Error(GatewayError::Malformed));
+                }
+            }
+            for reply in replies {
+                writer
+                    .write_all(&reply.to_frame())
+                    .map_err(|e| format!("Failed to write {port}: {e}"))?;
+            }
+        }
+    }
+}
+
+/// The reply of the emulated `node` to `msg`, if any.
+fn emulated_reply(node: Address, msg: Message, uptime_s: u32) -> Option<Message> {
+    let reply = match msg {
+        Message::QuerySignal => Message::Signal(Signal::Red),
+        Message::QueryStatus => Message::Status(Status {
+            supply_mv: 3300,
+            vbat_mv: 3000,
+            temperature_c: 25,
+            uptime_s,
+            reset_cause: ResetCause::PowerOn,
+            rssi_dbm: -80,
+            snr_db: 10,
+            faults: Faults::NONE,
+            signal: Signal::Red,
+            mode: OperatingMode::Automatic,
+        }),
+        Message::GetConfig(ConfigKey::Address) => Message::ConfigValue {
+            key: ConfigKey::Address,
+            value: node.0 as u32,
+        },
+        Message::GetConfig(key) => Message::ConfigValue { key, value: 0 },
+        Message::SetConfig { key, value, .. } => Message::ConfigValue { key, value },
+        Message::GetEvents { from } => {
+            let mut page = EventPage::EMPTY;
+            let reset = Event {
+                seq: 0,
+                time_s: 0,
+                kind: EventKind::Reset(ResetCause::PowerOn),
+            };
+            if from == 0 {
+                let _ = page.push(reset);
+            }
+            Message::Events(page)
+        }
+        Message::OtaFragment { .. } => return None,
+        Message::OtaStatus => Message::OtaMissing {
+            first: 0,
+            bitmap: 0,
+        },
+        msg => msg,
+    };
+
+    Some(reply)
+}
+
+fn format_packet(packet: &Packet) -> String {
+    format!(
+        "{} -> {}, seq {:?}, TTL {}: {:?}",
+        packet.src.0, packet.dst.0, packet.seq, packet.ttl, packet.msg
+    )
+}
+
+/// `time_us` is in microseconds since the UNIX epoch.
+fn format_time(time_us: u64) -> String {
+    let time_s = (time_us / 1_000_000) as i64;
+    let time_ns = (time_us % 1_000_000) as u32 * 1_000;
+    chrono::DateTime::from_timestamp(time_s, time_ns)
+        .map_or(time_s.to_string(), |time| time.to_string())
+}
+
+fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_micros() as u64)
+}
+
+fn open(port: &str) -> Result<Gateway, String> {
+    Gateway::open(port).map_err(|e| format!("Failed to open {port}: {e}"))
+}
+
+fn parse_signal(name: &str) -> Result<Signal, String> {
+    match name {
+        "red" => Ok(Signal::Red),
+        "yellow" => Ok(Signal::Yellow),
+        "green" => Ok(Signal::Green),
+        "off" => Ok(Signal::Off),
+        _ => Err(format!(
+            "Unknown signal {name}, expected one of: red, yellow, green, off"
+        )),
+    }
+}
+
+fn config_key(name: &str) -> Result<ConfigKey, String> {
+    CONFIG_KEYS
+        .iter()
+        .find(|(_, key_name)| *key_name == name)
+        .map(|(key, _)| *key)
+        .ok_or_else(|| {
+            let names: Vec<&str> = CONFIG_KEYS.iter().map(|(_, name)| *name).collect();
+            format!(
+                "Unknown setting {name}, expected one of: {}",
+                names.join(", ")
+            )
+        })
+}
+
+/// Negative values are sent as their two's complement, as the TX power is.
+fn config_value(value: &str) -> Result<u32, String> {
+    value
+        .parse::<u32>()
+        .or_else(|_| value.parse::<i32>().map(|value| value as u32))
+        .map_err(|_| format!("Invalid value: {value}"))
+}
+
+fn config_name(key: ConfigKey) -> &'static str {
+    CONFIG_KEYS
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map_or("?", |(_, name)| name)
+}
+
+fn format_config(key: ConfigKey, value: u32) -> String {
+    let name = config_name(key);
+    match key {
+        ConfigKey::TxPower => format!("{name} = {}", value as i32),
+        _ => format!("{name} = {value}"),
+    }
+}
+
+const CONFIG_KEYS: [(ConfigKey, &str); 6] = [
+    (ConfigKey::Address, "address"),
+    (ConfigKey::Frequency, "frequency"),
+    (ConfigKey::TxPower, "tx-power"),
+    (ConfigKey::FailsafeTimeout, "failsafe-timeout"),
+    (ConfigKey::RedTime, "red-time"),
+    (ConfigKey::GreenTime, "green-time"),
+];
+/// Requests sent to a node before giving up.
+const ATTEMPTS: u32 = 3;
+/// A node replies within seconds, once it has the request. Replies take a few seconds on air.
+const NODE_REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+/// 2024-01-01.
+const COUNTER_EPOCH_S: u64 = 1_704_067_200;
+const OUT_OF_SYNC: &str = "The capture is corrupt, bytes were lost";
+const USAGE: &str = "Usage:
+    lora2traffic-cli <port> status <node>
+    lora2traffic-cli <port> set-signal <node> red|yellow|green|off
+    lora2traffic-cli <port> get-config <node> <setting>
+    lora2traffic-cli <port> set-config <node> <setting> <value>
+    lora2traffic-cli <port> log dump <node>
+    lora2traffic-cli <port> ota <node> <update>
+    lora2traffic-cli <port> monitor
+    lora2traffic-cli <port> sniff [<file>]
+    lora2traffic-cli <port> gateway status
+    lora2traffic-cli <port> gateway get-config <setting>
+    lora2traffic-cli <port> gateway set-config <setting> <value>
+    lora2traffic-cli emulate <port>";
@@ -0,0 +1,198 @@
+//! Bridges the LoRa network to a host, e.g a PC or a SCADA system, over the LPUART of a STM32WL
+//! board.
+//!
+//! The host sends [`HostMessage`]s and the gateway answers with [`GatewayMessage`]s, SLIP framed,
+//! at [`GATEWAY_BAUD_RATE`]. The packets received are passed on to the host as they come. TX is
+//! PA2 and RX is PA3.
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+
+use defmt::{info, warn};
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_stm32::flash::Flash;
+use embassy_stm32::gpio::{Level, Output, Pin, Speed};
+use embassy_stm32::mode::Async;
+use embassy_stm32::spi::Spi;
+use embassy_stm32::usart::{self, Uart, UartTx};
+use embassy_stm32::{bind_interrupts, peripherals};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
+use {defmt_rtt as _, panic_probe as _};
+
+use lora2traffic::*;
+
+bind_interrupts!(struct UartIrqs {
+    LPUART1 => usart::InterruptHandler<peripherals::LPUART1>;
+});
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let config = create_stm32_config();
+    let p = embassy_stm32::init(config);
+
+    // Set CTRL1 and CTRL3 for high-power transmission, while CTRL2 acts as an RF switch between tx and rx
+    let ctrl1 = Output::new(p.PC4.degrade(), Level::Low, Speed::High);
+    let ctrl2 = Output::new(p.PC5.degrade(), Level::Low, Speed::High);
+    let ctrl3 = Output::new(p.PC3.degrade(), Level::High, Speed::High);
+
+    let flash = Mutex::new(RefCell::new(Flash::new_blocking(p.FLASH)));
+    let mut store = ConfigStore::new(FlashStorage::new(&flash));
+    let mut config = Config::load(&mut store, DEFAULT_CONFIG);
+
+    let spi = Spi::new_subghz(p.SUBGHZSPI, p.DMA1_CH1, p.DMA1_CH2);
+    let mut lora = LoraHw::new(
+        config.address,
+        config.frequency_hz,
+        config.tx_power_dbm,
+        CAPABILITIES,
+        ctrl1,
+        ctrl2,
+        ctrl3,
+        spi,
+    )
+    .await;
+
+    let mut uart_config = usart::Config::default();
+    uart_config.baudrate = GATEWAY_BAUD_RATE;
+    let uart = Uart::new(
+        p.LPUART1,
+        p.PA3,
+        p.PA2,
+        UartIrqs,
+        p.DMA1_CH3,
+        p.DMA1_CH4,
+        uart_config,
+    )
+    .unwrap();
+    let (mut tx, rx) = uart.split();
+    let mut dma_buf = [0; UART_RX_BUF_LEN];
+    let mut rx = rx.into_ring_buffered(&mut dma_buf);
+    info!("Gateway ready, address = {:?}", config.address);
+
+    let mut counters = Counters::default();
+    let mut decoder = SlipDecoder::new();
+    let mut buf = [0; 64];
+    loop {
+        match select(lora.receive(), rx.read(&mut buf)).await {
+            Either::First(Ok(packet)) => {
+                counters.received += 1;
+                let (rssi_dbm, snr_db) = lora.rx_quality();
+                let reception = Reception {
+                    rssi_dbm,
+                    snr_db,
+                    packet,
+                };
+                write(&mut tx, GatewayMessage::Received(reception)).await;
+            }
+            Either::First(Err(())) => counters.errors += 1,
+            Either::Second(Ok(len)) => {
+                for byte in &buf[..len] {
+                    let Some(frame) = decoder.push(*byte) else {
+                        continue;
+                    };
+                    let reply = match HostMessage::from_frame(frame) {
+                        Ok(msg) => {
+                            info!("Host message = {:?}", msg);
+                            handle(msg, &mut lora, &mut store, &mut config, &mut counters).await
+                        }
+                        Err(e) => {
+                            warn!("Undecodable frame from the host = {}", e);
+                            GatewayMessage::Error(GatewayError::Malformed)
+                        }
+                    };
+                    write(&mut tx, reply).await;
+                }
+            }
+            // Bytes were lost, the frame they're in is dropped for its CRC.
+            Either::Second(Err(e)) => warn!("UART error = {}", e),
+        }
+    }
+}
+
+async fn handle(
+    msg: HostMessage,
+    lora: &mut LoraHw,
+    store: &mut ConfigStore<FlashStorage<'_, '_>>,
+    config: &mut Config,
+    counters: &mut Counters,
+) -> GatewayMessage {
+    match msg {
+        HostMessage::Send(packet) => match lora.send(packet.dst, packet.msg).await {
+            Ok(()) => {
+                counters.sent += 1;
+                GatewayMessage::Sent
+            }
+            Err(e) => {
+                warn!("tx failed: {}", e);
+                counters.errors += 1;
+                GatewayMessage::Error(GatewayError::Radio)
+            }
+        },
+        HostMessage::QueryStatus => GatewayMessage::Status(GatewayStatus {
+            version: PROTOCOL_VERSION,
+            address: config.address,
+            frequency_hz: config.frequency_hz,
+            tx_power_dbm: config.tx_power_dbm,
+            uptime_s: Instant::now().as_secs() as u32,
+            received: counters.received,
+            sent: counters.sent,
+            errors: counters.errors,
+        }),
+        HostMessage::GetConfig(key) => GatewayMessage::ConfigValue {
+            key,
+            value: config.get(key),
+        },
+        HostMessage::SetConfig { key, value } => {
+            let Some(new_config) = config.with(key, value) else {
+                warn!("Invalid {:?} = {}", key, value);
+                return GatewayMessage::Error(GatewayError::InvalidConfig);
+            };
+            let (address, frequency_hz) = (new_config.address, new_config.frequency_hz);
+            if let Err(e) = lora.reconfigure(address, frequency_hz, new_config.tx_power_dbm) {
+                warn!("Radio error = {}", e);
+                return GatewayMessage::Error(GatewayError::Radio);
+            }
+            *config = new_config;
+            if let Err(e) = config.save(store) {
+                warn!("Failed to save the config: {}", e);
+                return GatewayMessage::Error(GatewayError::Store);
+            }
+
+            GatewayMessage::ConfigValue {
+                key,
+                value: config.get(key),
+            }
+        }
+    }
+}
+
+async fn write(tx: &mut UartTx<'_, Async>, msg: GatewayMessage) {
+    if let Err(e) = tx.write(&msg.to_frame()).await {
+        warn!("UART error = {}", e);
+    }
+}
+
+/// Since startup.
+#[derive(Default)]
+struct Counters {
+    received: u32,
+    sent: u32,
+    errors: u32,
+}
+
+/// Used until changed by the host. The timing settings aren't used.
+const DEFAULT_CONFIG: Config = Config {
+    address: GATEWAY_ADDRESS,
+    frequency_hz: LORA_FREQUENCY_IN_HZ,
+    tx_power_dbm: 20,
+    failsafe_timeout_s: 0,
+    red_s: 30,
+    green_s: 30,
+};
+/// The gateway only passes messages on, the host handles them.
+const CAPABILITIES: Capabilities = Capabilities::NONE;
+/// Enough for a few frames, while the radio is busy sending.
+const UART_RX_BUF_LEN: usize = 1024;
@@ -0,0 +1,185 @@
+//! Prepares firmware updates for over-the-air delivery, and simulates their transfer.
+//!
+//! Build and run it on the host, e.g:
+//!
+//! ```sh
+//! cargo run --no-default-features --features std --target x86_64-unknown-linux-gnu \
+//!     --bin lora2traffic-ota -- prepare ota.key firmware.bin firmware.update
+//! ```
+//!
+//! The secret key is 32 random bytes (e.g from `head -c 32 /dev/urandom`), and the firmware a raw
+//! binary (e.g from `cargo objcopy --release --bin lora2traffic-rcv -- -O binary firmware.bin`).
+//!
+//! Every deployment has its own key pair, and the firmware is built with its public key, which
+//! comes from `public-key`:
+//!
+//! ```sh
+//! head -c 32 /dev/urandom > ota.key
+//! cargo run --no-default-features --features std --target x86_64-unknown-linux-gnu \
+//!     --bin lora2traffic-ota -- public-key ota.key ota.pub
+//! OTA_PUBLIC_KEY=ota.pub cargo build --release
+//! ```
+//!
+//! Keep the secret key out of the repository, and safe: whoever has it can update the nodes.
+
+use std::{env, fs, process};
+
+use ed25519_dalek::{Signer, SigningKey};
+use sha2::{Digest, Sha512};
+
+use lora2traffic::*;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["public-key", key, public_key] => public_key_cmd(key, public_key),
+        ["prepare", key, firmware, update] => prepare(key, firmware, update),
+        ["simulate", update, public_key] => simulate(update, public_key, 10),
+        ["simulate", update, public_key, loss] => match loss.parse() {
+            Ok(loss) if loss < 100 => simulate(update, public_key, loss),
+            _ => Err(format!("Invalid loss percentage: {loss}")),
+        },
+        _ => Err(USAGE.to_string()),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
+
+fn public_key_cmd(key: &str, public_key: &str) -> Result<(), String> {
+    let key = read_key(key)?;
+    write(public_key, key.verifying_key().as_bytes())
+}
+
+/// Sign the firmware, and write the update: the firmware followed by its signature.
+fn prepare(key: &str, firmware: &str, update: &str) -> Result<(), String> {
+    let key = read_key(key)?;
+    let firmware = read(firmware)?;
+    if firmware.is_empty() || firmware.len() > MAX_UPDATE_LEN as usize {
+        return Err(format!(
+            "The firmware is {} bytes long, the maximum is {MAX_UPDATE_LEN}",
+            firmware.len()
+        ));
+    }
+
+    // The signature scheme of embassy-boot.
+    let digest = Sha512::digest(&firmware);
+    let signature = key.sign(&digest).to_bytes();
+    let image = OtaImage::new(&firmware, signature);
+    println!(
+        "{} bytes of firmware, in {} fragments (with parity)",
+        image.len(),
+        image.fragments()
+    );
+
+    let mut bytes = firmware.clone();
+    bytes.extend_from_slice(&signature);
+    write(update, &bytes)
+}
+
+/// Send the update to an [`OtaReceiver`] over a channel losing `loss` percent of the fragments,
+/// as the over-the-air sender does, and verify it. The status requests are never lost.
+fn simulate(update: &str, public_key: &str, loss: u32) -> Result<(), String> {
+    let update = read(update)?;
+    let public_key: [u8; 32] = read(public_key)?
+        .try_into()
+        .map_err(|_| "The public key must be 32 bytes long")?;
+    let (firmware, signature) = update
+        .split_last_chunk::<SIGNATURE_LEN>()
+        .ok_or("The update is too short")?;
+    let image = OtaImage::new(firmware, *signature);
+
+    let mut dfu = Box::new(MemFlash::<DFU_LEN>::new());
+    let mut receiver = OtaReceiver::new();
+    receiver
+        .start(image.len(), dfu.as_mut())
+        .map_err(|e| format!("Failed to start: {e:?}"))?;
+
+    let mut channel = Channel::new(loss);
+    let mut to_send: Vec<u16> = (0..image.fragments()).collect();
+    let mut sent = 0;
+    let mut rounds = 0;
+    loop {
+        rounds += 1;
+        for &index in &to_send {
+            sent += 1;
+            if channel.lose() {
+                continue;
+            }
+            receiver
+                .fragment(index, &image.fragment(index), dfu.as_mut())
+                .map_err(|e| format!("Fragment {index} rejected: {e:?}"))?;
+        }
+
+        let (first, bitmap) = receiver.missing().ok_or("The session ended")?;
+        if bitmap == 0 {
+            break;
+        }
+        to_send = (0..32)
+            .filter(|i| bitmap & 1 << i != 0)
+            .map(|i| first + i)
+            .collect();
+    }
+
+    let len = receiver
+        .finish(dfu.as_mut(), &public_key)
+        .map_err(|e| format!("Update rejected: {e:?}"))?;
+    println!(
+        "{len} bytes verified, after {sent} fragments ({}% overhead) and {rounds} status requests, \
+        about {} hours on air",
+        (sent * 100 / image.fragments() as usize).saturating_sub(100),
+        sent as u64 * OtaImage::fragment_time_on_air_us() / 3_600_000_000
+    );
+
+    Ok(())
+}
+
+/// Loses packets at random, reproducibly.
+struct Channel {
+    loss: u32,
+    state: u32,
+}
+
+impl Channel {
+    fn new(loss: u32) -> Self {
+        Self {
+            loss,
+            state: 0x6c32_7463,
+        }
+    }
+
+    fn lose(&mut self) -> bool {
+        // xorshift32
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+
+        self.state % 100 < self.loss
+    }
+}
+
+fn read_key(path: &str) -> Result<SigningKey, String> {
+    let key: [u8; 32] = read(path)?
+        .try_into()
+        .map_err(|_| "The secret key must be 32 bytes long")?;
+
+    Ok(SigningKey::from_bytes(&key))
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("Failed to read {path}: {e}"))
+}
+
+fn write(path: &str, bytes: &[u8]) -> Result<(), String> {
+    fs::write(path, bytes).map_err(|e| format!("Failed to write {path}: {e}"))
+}
+
+/// The size of the DFU partition in `memory.x`.
+const DFU_LEN: usize = 110 * 1024;
+const USAGE: &str = "Usage:
+    lora2traffic-ota public-key <secret key> <public key>
+    lora2traffic-ota prepare <secret key> <firmware> <update>
+    lora2traffic-ota simulate <update> <public key> [loss %]";
@@ -3,19 +3,31 @@
 #![no_std]
 #![no_main]
 
+use core::cell::RefCell;
+
 use defmt::{info, warn};
+use embassy_boot::AlignedBuffer;
 use embassy_executor::Spawner;
-use embassy_stm32::gpio::{AnyPin, Level, Output, Pin, Speed};
+use embassy_futures::select::{self, Either, Either4};
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::flash::{Flash, WRITE_SIZE};
+use embassy_stm32::gpio::{AnyPin, Input, Level, Output, Pin, Pull, Speed};
+use embassy_stm32::rtc::{Rtc, RtcConfig};
 use embassy_stm32::spi::Spi;
-use embassy_time::Timer;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Ticker, Timer};
 use {defmt_rtt as _, panic_probe as _};
 
 use lora2traffic::*;
 
 #[embassy_executor::main]
-async fn main(_spawner: Spawner) {
+async fn main(spawner: Spawner) {
     let config = create_stm32_config();
     let p = embassy_stm32::init(config);
+    let reset_cause = reset_cause();
+    info!("Reset cause = {:?}", reset_cause);
 
     // Set CTRL1 and CTRL3 for high-power transmission, while CTRL2 acts as an RF switch between tx and rx
     let ctrl1 = Output::new(p.PC4.degrade(), Level::Low, Speed::High);
@@ -26,35 +38,628 @@ async fn main(_spawner: Spawner) {
         p.PC6.degrade(), // Pin 12 on the board.
         p.PC0.degrade(), // Pin 14 on the board.
         p.PA8.degrade(), // Pin 16 on the board.
+    );
+    // Jumper to ground for maintenance.
+    let maintenance = Input::new(p.PA10, Pull::Up).is_low();
+    let pedestrian_control = PedestrianSignalControl::new(
+        p.PB5.degrade(), // Walk
+        p.PB8.degrade(), // Don't walk
+    );
+    spawner
+        .spawn(drive_pedestrian_head(pedestrian_control))
+        .unwrap();
+    let flash = Mutex::new(RefCell::new(Flash::new_blocking(p.FLASH)));
+    let store = ConfigStore::new(FlashStorage::new(&flash));
+    let mut config_manager = ConfigManager::new(store, DEFAULT_CONFIG, CONFIG_AUTH_KEY);
+    let mut config = *config_manager.config();
+    let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+    let mut updater = Updater::new(&flash, &mut aligned);
+    let mut event_log = EventLog::new(FlashStorage::event_log(&flash));
+
+    let spi = Spi::new_subghz(p.SUBGHZSPI, p.DMA1_CH1, p.DMA1_CH2);
+    let mut lora = LoraHw::new(
+        config.address,
+        config.frequency_hz,
+        config.tx_power_dbm,
+        CAPABILITIES,
+        ctrl1,
+        ctrl2,
+        ctrl3,
+        spi,
     )
     .await;
-    let spi = Spi::new_subghz(p.SUBGHZSPI, p.DMA1_CH1, p.DMA1_CH2);
-    let mut lora = LoraHw::new(ctrl1, ctrl2, ctrl3, spi).await;
+    if let Some(routes) = REPEATER_ROUTES {
+        lora.repeat(routes);
+    }
+    if let Err(e) = lora.say_hello(CONTROLLER).await {
+        info!("tx failed: {}", e);
+    }
+
+    let mut health = Health::new(p.ADC);
+    let mut rtc = Rtc::new(p.RTC, RtcConfig::default());
+    let clock = Clock::new(read_rtc(&rtc).unwrap_or(0), Instant::now().as_millis());
+    let mut time_sync = TimeSync::new(clock);
+    event_log.log(now_s(&time_sync), EventKind::Reset(reset_cause));
 
+    let mut pedestrian_button = PedestrianButton::new(
+        ExtiInput::new(p.PA1, p.EXTI1, Pull::Up), // B2 on the board.
+        p.PB15.degrade(),                         // Blue LED as the "wait" indicator.
+    );
+    let mut pedestrian_call = PedestrianCall::Idle;
+
+    // Contact closures from loop detectors or presence sensors.
+    let detectors = [
+        ExtiInput::new(p.PB12, p.EXTI12, Pull::Up),
+        ExtiInput::new(p.PB13, p.EXTI13, Pull::Up),
+    ];
+    for (id, input) in detectors.into_iter().enumerate() {
+        spawner.spawn(watch_detector(id as u8, input)).unwrap();
+    }
+
+    // When to toggle the yellow light next, while flashing.
+    let mut flash_at = None;
+    let saved_state = load_state(&rtc);
+    info!("Saved state = {:?}", saved_state);
+    let mut operating_mode = saved_state.map_or(OperatingMode::Automatic, |state| state.mode);
+    if operating_mode == OperatingMode::Flash {
+        flash_at = Some(Instant::now());
+    } else {
+        // Never come back dark, nor straight to what was shown before the reset.
+        signal_control.start_up(&STARTUP, maintenance).await;
+    }
+    let mode = operating_mode;
+    save_state(&rtc, SavedState { mode });
+    let mut last_contact = Instant::now();
+    let mut heartbeat_at = Instant::now();
+    let mut link_state = LinkState::Lost;
+    let mut status_at = Instant::now() + Duration::from_secs(STATUS_INTERVAL_S);
+    // The faults the measurements don't tell.
+    let mut faults = Faults::NONE;
+    let mut logged_faults = Faults::NONE;
     loop {
-        match lora.receive().await {
-            Ok(Message::QuerySignal) => {
-                info!("rx query signal");
+        if faults != logged_faults {
+            event_log.log(now_s(&time_sync), EventKind::Faults(faults));
+            logged_faults = faults;
+        }
+        let retry_at = match pedestrian_call {
+            PedestrianCall::Requested { sent_at, .. } => sent_at + RECEIVE_TIMEOUT,
+            _ => Instant::MAX,
+        };
+        let rollback_at = config_manager
+            .rollback_ms()
+            .map_or(Instant::MAX, Instant::from_millis);
+        // Lost contact with the controller, flash rather than keep showing a stale signal.
+        let failsafe_at = match config.failsafe_timeout_s {
+            0 => Instant::MAX,
+            _ if flash_at.is_some() => Instant::MAX,
+            timeout_s => last_contact + Duration::from_secs(timeout_s as u64),
+        };
+        let wake_at = retry_at
+            .min(flash_at.unwrap_or(Instant::MAX))
+            .min(rollback_at)
+            .min(failsafe_at)
+            .min(heartbeat_at)
+            .min(status_at);
+        let rx = match select::select4(
+            lora.receive(),
+            pedestrian_button.wait_for_press(),
+            Timer::at(wake_at),
+            DETECTIONS.receive(),
+        )
+        .await
+        {
+            Either4::First(rx) => rx,
+            Either4::Second(()) => {
+                if pedestrian_call == PedestrianCall::Idle {
+                    pedestrian_call = request_pedestrian_call(&mut lora, 1).await;
+                } else {
+                    info!("Pedestrian call already latched");
+                }
+                continue;
+            }
+            Either4::Third(()) => {
+                let now = Instant::now();
+                if now >= heartbeat_at {
+                    let signal = signal_control.state;
+                    if let Err(e) = lora
+                        .send_heartbeat(CONTROLLER, signal, operating_mode)
+                        .await
+                    {
+                        info!("tx failed: {}", e);
+                    }
+                    heartbeat_at = now + HEARTBEAT_INTERVAL;
+                    let time_s = now_s(&time_sync);
+                    update_link_state(&lora, &mut link_state, &mut event_log, time_s);
+                }
+                if now >= status_at {
+                    let unconfirmed = config_manager.rollback_ms().is_some();
+                    faults.set(Faults::UNCONFIRMED_CONFIG, unconfirmed);
+                    let signal = signal_control.state;
+                    let (mode, cause) = (operating_mode, reset_cause);
+                    send_status(
+                        &mut lora,
+                        CONTROLLER,
+                        &mut health,
+                        cause,
+                        signal,
+                        mode,
+                        faults,
+                    )
+                    .await;
+                    status_at = now + Duration::from_secs(STATUS_INTERVAL_S);
+                }
+                if config_manager.roll_back_if_due(now.as_millis()) {
+                    apply_config(&mut lora, config_manager.config(), &mut config);
+                }
+                if now >= failsafe_at {
+                    warn!("No contact with the controller, flashing yellow");
+                    faults.set(Faults::FAILSAFE, true);
+                    signal_control.set(Signal::Off);
+                    flash_at = Some(now);
+                }
+                if let Some(at) = flash_at.filter(|at| Instant::now() >= *at) {
+                    signal_control.toggle_yellow();
+                    flash_at = Some(at + FLASH_PERIOD);
+                }
+                match pedestrian_call {
+                    PedestrianCall::Requested { attempts, .. } if Instant::now() >= retry_at => {
+                        if attempts < 3 {
+                            pedestrian_call =
+                                request_pedestrian_call(&mut lora, attempts + 1).await;
+                        } else {
+                            warn!("Pedestrian call not acknowledged, dropping it");
+                            pedestrian_call = PedestrianCall::Idle;
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            Either4::Fourth((detector, occupied)) => {
+                let msg = Message::Detection { detector, occupied };
+                if let Err(e) = lora.send(CONTROLLER, msg).await {
+                    info!("tx failed: {}", e);
+                }
+                continue;
+            }
+        };
+
+        if rx.as_ref().is_ok_and(|packet| packet.src == CONTROLLER) {
+            last_contact = Instant::now();
+            // A new firmware that hears from the controller is good.
+            updater.mark_booted();
+        }
+        faults.set(Faults::RADIO, rx.is_err());
+        let broadcast = rx
+            .as_ref()
+            .is_ok_and(|packet| packet.dst == Address::BROADCAST);
+        match rx {
+            Ok(
+                packet @ Packet {
+                    msg:
+                        Message::GetConfig(_)
+                        | Message::SetConfig { .. }
+                        | Message::ConfirmConfig { .. },
+                    ..
+                },
+            ) => {
+                let uptime = Instant::now().as_millis();
+                let reply = config_manager.handle(packet.src, packet.dst, packet.msg, uptime);
+                if let Some(reply) = reply {
+                    // Before the radio settings change.
+                    if let Err(e) = lora.send(packet.src, reply).await {
+                        info!("tx failed: {}", e);
+                    }
+                }
+                apply_config(&mut lora, config_manager.config(), &mut config);
+            }
+            Ok(
+                packet @ Packet {
+                    msg:
+                        Message::OtaStart { .. }
+                        | Message::OtaFragment { .. }
+                        | Message::OtaStatus
+                        | Message::OtaFinish,
+                    ..
+                },
+            ) => {
+                // Starting erases the DFU partition, so only an authenticated start.
+                let authenticated = !matches!(packet.msg, Message::OtaStart { .. })
+                    || config_manager.authenticate_ota_start(packet.src, packet.dst, &packet.msg);
+                let reply = authenticated.then(|| updater.handle(packet.msg)).flatten();
+                if let Some(reply) = reply {
+                    if let Err(e) = lora.send(packet.src, reply).await {
+                        info!("tx failed: {}", e);
+                    }
+                }
+                updater.restart_if_updated();
+            }
+            Ok(Packet {
+                src: CONTROLLER,
+                msg: Message::Heartbeat { signal, mode, .. },
+                ..
+            }) => {
+                info!("rx heartbeat, signal = {:?}, mode = {:?}", signal, mode);
+                let time_s = now_s(&time_sync);
+                update_link_state(&lora, &mut link_state, &mut event_log, time_s);
+            }
+            Ok(Packet {
+                src,
+                msg: Message::GetEvents { from },
+                ..
+            }) => {
+                info!("rx get events from {}", from);
+                let msg = Message::Events(event_log.page(from));
+                if let Err(e) = lora.send(src, msg).await {
+                    info!("tx failed: {}", e);
+                }
+            }
+            Ok(Packet {
+                src,
+                msg: Message::QueryStatus,
+                ..
+            }) => {
+                info!("rx query status from {:?}", src);
+                let unconfirmed = config_manager.rollback_ms().is_some();
+                faults.set(Faults::UNCONFIRMED_CONFIG, unconfirmed);
+                let signal = signal_control.state;
+                let (mode, cause) = (operating_mode, reset_cause);
+                send_status(&mut lora, src, &mut health, cause, signal, mode, faults).await;
+                if src == CONTROLLER {
+                    status_at = Instant::now() + Duration::from_secs(STATUS_INTERVAL_S);
+                }
+            }
+            Ok(Packet {
+                src,
+                msg: Message::QuerySignal,
+                ..
+            }) => {
+                info!("rx query signal from {:?}", src);
                 let signal = signal_control.state;
                 let msg = Message::Signal(signal);
-                if let Err(e) = lora.send(msg).await {
+                if let Err(e) = lora.send(src, msg).await {
                     info!("tx failed: {}", e);
                 }
             }
-            Ok(Message::Signal(signal)) => {
-                info!("rx signal = {:?}", signal);
+            Ok(Packet {
+                src,
+                msg: Message::Signal(signal),
+                ..
+            }) if src == CONTROLLER || (maintenance && src == OPERATOR) => {
+                info!("rx signal = {:?} from {:?}", signal, src);
+                flash_at = None;
+                faults.set(Faults::FAILSAFE, false);
+                if signal != signal_control.state {
+                    event_log.log(now_s(&time_sync), EventKind::Signal(signal));
+                }
                 signal_control.set(signal);
-                // ACK
-                let msg = Message::Signal(signal);
-                if let Err(e) = lora.send(msg).await {
-                    info!("tx failed: {}", e);
+                if signal == Signal::Red && pedestrian_call == PedestrianCall::Acknowledged {
+                    info!("Pedestrian call served");
+                    pedestrian_call = PedestrianCall::Idle;
+                    pedestrian_button.set_waiting(false);
                 }
+                ack(&mut lora, src, broadcast, Message::Signal(signal)).await;
             }
+            Ok(Packet {
+                src: CONTROLLER,
+                msg: Message::TimeSync(time),
+                ..
+            }) => {
+                let uptime = Instant::now().as_millis();
+                let status = time_sync.update(uptime, time);
+                info!("rx time sync = {}, status = {:?}", time, status);
+                set_rtc(&mut rtc, time_sync.clock().now(uptime));
+
+                ack(
+                    &mut lora,
+                    CONTROLLER,
+                    broadcast,
+                    Message::SyncStatus(status),
+                )
+                .await;
+            }
+            Ok(Packet {
+                src: CONTROLLER,
+                msg:
+                    msg @ Message::PedestrianSignal {
+                        signal,
+                        countdown_s,
+                    },
+                ..
+            }) => {
+                info!(
+                    "rx pedestrian signal = {:?}, countdown = {}s",
+                    signal, countdown_s
+                );
+                PEDESTRIAN_SIGNAL.signal((signal, countdown_s));
+                ack(&mut lora, CONTROLLER, broadcast, msg).await;
+            }
+            Ok(Packet {
+                src: CONTROLLER,
+                msg: Message::OperatingMode(mode),
+                ..
+            }) => {
+                match mode {
+                    OperatingMode::Manual => warn!("Lights under manual control"),
+                    OperatingMode::Flash => {
+                        warn!("Flashing yellow");
+                        signal_control.set(Signal::Off);
+                        flash_at = Some(Instant::now());
+                    }
+                    OperatingMode::Automatic => info!("Lights back under automatic control"),
+                }
+                if mode != OperatingMode::Flash && flash_at.take().is_some() {
+                    // Stop flashing, on all-red until the controller sends the signal.
+                    signal_control.set(Signal::Red);
+                }
+                if mode != operating_mode {
+                    event_log.log(now_s(&time_sync), EventKind::OperatingMode(mode));
+                }
+                operating_mode = mode;
+                save_state(&rtc, SavedState { mode });
+                ack(
+                    &mut lora,
+                    CONTROLLER,
+                    broadcast,
+                    Message::OperatingMode(mode),
+                )
+                .await;
+            }
+            Ok(Packet {
+                src: CONTROLLER,
+                msg: Message::PedestrianCall,
+                ..
+            }) => {
+                info!("rx pedestrian call ACK");
+                if pedestrian_call != PedestrianCall::Idle {
+                    pedestrian_call = PedestrianCall::Acknowledged;
+                    pedestrian_button.set_waiting(true);
+                }
+            }
+            Ok(packet) => warn!("rx unexpected packet = {:?}", packet),
             Err(err) => warn!("rx failed: {}", err),
         }
     }
 }
 
+/// Apply the `new` config, if it's different from the `applied` one.
+fn apply_config(lora: &mut LoraHw, new: &Config, applied: &mut Config) {
+    if new == applied {
+        return;
+    }
+
+    if let Err(e) = lora.reconfigure(new.address, new.frequency_hz, new.tx_power_dbm) {
+        warn!("Failed to reconfigure the radio: {}", e);
+    }
+    *applied = *new;
+}
+
+/// Log the changes of the link with the controller.
+fn update_link_state(
+    lora: &LoraHw,
+    state: &mut LinkState,
+    event_log: &mut EventLog<FlashStorage<'_, '_>, EVENT_LOG_LEN>,
+    time_s: u32,
+) {
+    let new = lora.link_state(CONTROLLER);
+    if new == *state {
+        return;
+    }
+    let peer = CONTROLLER;
+    event_log.log(time_s, EventKind::Link { peer, state: new });
+
+    match (new, lora.link(CONTROLLER)) {
+        (LinkState::Good, _) => info!("Link with the controller is good"),
+        (_, Some(link)) => warn!(
+            "Link with the controller is {:?}: {}% lost, RSSI = {} dBm (trend {} dB)",
+            new,
+            link.loss_percent(),
+            link.rssi_dbm(),
+            link.rssi_trend_db()
+        ),
+        (_, None) => warn!("Link with the controller is {:?}", new),
+    }
+    *state = new;
+}
+
+/// ACK a command of `dst`. The ACKs of a broadcast go in turn, see [`ack_delay_ms`].
+async fn ack(lora: &mut LoraHw, dst: Address, broadcast: bool, msg: Message) {
+    if broadcast {
+        Timer::after_millis(ack_delay_ms(lora.address())).await;
+    }
+    if let Err(e) = lora.send(dst, msg).await {
+        info!("tx failed: {}", e);
+    }
+}
+
+/// The time for the event log.
+fn now_s(time_sync: &TimeSync) -> u32 {
+    (time_sync.clock().now(Instant::now().as_millis()) / 1000) as u32
+}
+
+async fn send_status(
+    lora: &mut LoraHw,
+    dst: Address,
+    health: &mut Health<'_>,
+    reset_cause: ResetCause,
+    signal: Signal,
+    mode: OperatingMode,
+    faults: Faults,
+) {
+    let measurements = health.measure();
+    let link = lora.link(CONTROLLER);
+    let status = Status {
+        supply_mv: measurements.supply_mv,
+        vbat_mv: measurements.vbat_mv,
+        temperature_c: measurements.temperature_c,
+        uptime_s: Instant::now().as_secs() as u32,
+        reset_cause,
+        rssi_dbm: link.map_or(0, |link| link.rssi_dbm()),
+        snr_db: link.map_or(0, |link| link.snr_db() as i8),
+        faults: Faults::from_health(
+            measurements.supply_mv,
+            measurements.temperature_c,
+            reset_cause,
+        )
+        .union(faults),
+        signal,
+        mode,
+    };
+    if status.faults == Faults::NONE {
+        info!("Status = {:?}", status);
+    } else {
+        warn!("Status = {:?}", status);
+    }
+    if let Err(e) = lora.send(dst, Message::Status(status)).await {
+        info!("tx failed: {}", e);
+    }
+}
+
+async fn request_pedestrian_call(lora: &mut LoraHw, attempts: u8) -> PedestrianCall {
+    if let Err(e) = lora.send(CONTROLLER, Message::PedestrianCall).await {
+        info!("tx failed: {}", e);
+    }
+
+    PedestrianCall::Requested {
+        attempts,
+        sent_at: Instant::now(),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PedestrianCall {
+    Idle,
+    /// Sent to the controller, waiting for the ACK.
+    Requested {
+        attempts: u8,
+        sent_at: Instant,
+    },
+    /// Latched by the controller, until the next red phase.
+    Acknowledged,
+}
+
+struct PedestrianButton {
+    button: ExtiInput<'static>,
+    wait: Output<'static>,
+}
+
+impl PedestrianButton {
+    fn new(button: ExtiInput<'static>, wait: AnyPin) -> Self {
+        Self {
+            button,
+            wait: Output::new(wait, Level::Low, Speed::Low),
+        }
+    }
+
+    async fn wait_for_press(&mut self) {
+        loop {
+            self.button.wait_for_falling_edge().await;
+            Timer::after(DEBOUNCE_TIME).await;
+            if self.button.is_low() {
+                info!("Pedestrian button pressed");
+                return;
+            }
+        }
+    }
+
+    fn set_waiting(&mut self, waiting: bool) {
+        self.wait.set_level(waiting.into());
+    }
+}
+
+/// Detector number and whether it's occupied.
+static DETECTIONS: Channel<CriticalSectionRawMutex, (u8, bool), 4> = Channel::new();
+
+#[embassy_executor::task(pool_size = 2)]
+async fn watch_detector(id: u8, mut input: ExtiInput<'static>) {
+    let mut occupied = false;
+    loop {
+        // The detector closes the contact while a vehicle is present.
+        if input.is_low() != occupied {
+            occupied = !occupied;
+            info!("Detector {} occupied = {}", id, occupied);
+            DETECTIONS.send((id, occupied)).await;
+        }
+
+        input.wait_for_any_edge().await;
+        Timer::after(DEBOUNCE_TIME).await;
+    }
+}
+
+static PEDESTRIAN_SIGNAL: embassy_sync::signal::Signal<
+    CriticalSectionRawMutex,
+    (PedestrianSignal, u8),
+> = embassy_sync::signal::Signal::new();
+
+#[embassy_executor::task]
+async fn drive_pedestrian_head(mut control: PedestrianSignalControl) {
+    control.run().await
+}
+
+struct PedestrianSignalControl {
+    walk: Output<'static>,
+    dont_walk: Output<'static>,
+
+    state: PedestrianSignal,
+    countdown_s: u8,
+}
+
+impl PedestrianSignalControl {
+    fn new(walk: AnyPin, dont_walk: AnyPin) -> Self {
+        let mut control = Self {
+            walk: Output::new(walk, Level::High, Speed::Low),
+            dont_walk: Output::new(dont_walk, Level::High, Speed::Low),
+            state: PedestrianSignal::default(),
+            countdown_s: 0,
+        };
+        control.set(PedestrianSignal::default(), 0);
+
+        control
+    }
+
+    /// Drive the head according to `PEDESTRIAN_SIGNAL`, flashing and counting down as needed.
+    async fn run(&mut self) {
+        let mut ticker = Ticker::every(FLASH_PERIOD);
+        let mut ticks = 0u32;
+        loop {
+            match select::select(PEDESTRIAN_SIGNAL.wait(), ticker.next()).await {
+                Either::First((signal, countdown_s)) => {
+                    self.set(signal, countdown_s);
+                    ticker.reset();
+                    ticks = 0;
+                }
+                Either::Second(()) => {
+                    ticks += 1;
+                    if self.state == PedestrianSignal::FlashingDontWalk {
+                        self.dont_walk.toggle();
+                    }
+                    // The flash period is half a second.
+                    if ticks % 2 == 0 && self.countdown_s > 0 {
+                        self.countdown_s -= 1;
+                        info!("Pedestrian countdown = {}s", self.countdown_s);
+                    }
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, signal: PedestrianSignal, countdown_s: u8) {
+        info!("Setting pedestrian signal = {:?}", signal);
+        self.state = signal;
+        self.countdown_s = countdown_s;
+        match signal {
+            PedestrianSignal::Walk => {
+                self.walk.set_low();
+                self.dont_walk.set_high();
+            }
+            PedestrianSignal::FlashingDontWalk | PedestrianSignal::DontWalk => {
+                self.walk.set_high();
+                self.dont_walk.set_low();
+            }
+        }
+    }
+}
+
 struct SignalControl {
     red: Output<'static>,
     yellow: Output<'static>,
@@ -64,27 +669,31 @@ struct SignalControl {
 }
 
 impl SignalControl {
-    async fn new(red: AnyPin, yellow: AnyPin, green: AnyPin) -> Self {
-        let state = Signal::default();
-        let mut control = Self {
-            red: Output::new(red, Level::Low, Speed::High),
-            yellow: Output::new(yellow, Level::Low, Speed::High),
-            green: Output::new(green, Level::Low, Speed::High),
-            state,
-        };
-
-        // Startup checks.
-        let mut signal = Signal::Red;
-        for _ in 0..3 {
-            control.set(signal);
-            signal.rotate();
-            Timer::after(embassy_time::Duration::from_secs(1)).await;
+    fn new(red: AnyPin, yellow: AnyPin, green: AnyPin) -> Self {
+        Self {
+            red: Output::new(red, Level::High, Speed::High),
+            yellow: Output::new(yellow, Level::High, Speed::High),
+            green: Output::new(green, Level::High, Speed::High),
+            state: Signal::Off,
         }
+    }
 
-        // Set the initial state.
-        control.set(state);
+    /// Run the startup sequence of `policy`, with the lamp test if asked for.
+    async fn start_up(&mut self, policy: &StartupPolicy, lamp_test: bool) {
+        info!("Starting up, lamp test = {}", lamp_test);
+        let start = Instant::now();
+        let mut ticker = Ticker::every(STARTUP_TICK);
+        while let Some(signal) = policy.lights(lamp_test, start.elapsed().as_millis()) {
+            if signal != self.state {
+                self.set(signal);
+            }
+            ticker.next().await;
+        }
+    }
 
-        control
+    /// Flash the yellow light, while the signal is off.
+    fn toggle_yellow(&mut self) {
+        self.yellow.toggle();
     }
 
     fn set(&mut self, signal: Signal) {
@@ -114,3 +723,42 @@ impl SignalControl {
         }
     }
 }
+
+/// Used until changed remotely. The timing settings are only used by the controller.
+const DEFAULT_CONFIG: Config = Config {
+    address: Address(2),
+    frequency_hz: LORA_FREQUENCY_IN_HZ,
+    tx_power_dbm: 20,
+    // The controller is heard from at least every heartbeat.
+    failsafe_timeout_s: 3 * HEARTBEAT_INTERVAL.as_secs() as u16,
+    red_s: 30,
+    green_s: 30,
+};
+const CONTROLLER: Address = Address(1);
+/// The serial gateway, through which the operator sets the signal in maintenance, see
+/// `lora2traffic-cli`. The controller would fight it otherwise.
+const OPERATOR: Address = GATEWAY_ADDRESS;
+/// Advertised to the controller, which doesn't use what's missing.
+const CAPABILITIES: Capabilities = Capabilities::TIME_SYNC
+    .union(Capabilities::PEDESTRIAN_HEAD)
+    .union(Capabilities::PEDESTRIAN_CALL)
+    .union(Capabilities::DETECTORS)
+    .union(Capabilities::OPERATING_MODE)
+    .union(Capabilities::REMOTE_CONFIG)
+    .union(Capabilities::OTA)
+    .union(Capabilities::HEARTBEAT)
+    .union(Capabilities::STATUS)
+    .union(Capabilities::EVENT_LOG);
+/// Events kept in RAM, the flash keeps fewer.
+const EVENT_LOG_LEN: usize = 64;
+/// Forward the packets of other nodes too, e.g `Some(&[Route(CONTROLLER, Address(3))])` for a
+/// receiver out of the controller's range, or `Some(&[])` for all packets.
+const REPEATER_ROUTES: Option<&[Route]> = None;
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(10);
+const DEBOUNCE_TIME: Duration = Duration::from_millis(50);
+const FLASH_PERIOD: Duration = Duration::from_millis(500);
+const STARTUP: StartupPolicy = StartupPolicy {
+    flash_s: 5,
+    all_red_s: 10,
+};
+const STARTUP_TICK: Duration = Duration::from_millis(100);
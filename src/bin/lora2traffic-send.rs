@@ -3,13 +3,24 @@
 #![no_std]
 #![no_main]
 
+use core::cell::RefCell;
+use core::mem::discriminant;
+
 use defmt::{info, warn};
+use embassy_boot::AlignedBuffer;
 use embassy_executor::Spawner;
-use embassy_futures::select::{self, Either};
+use embassy_futures::select::{self, Either, Either3};
 use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::flash::{Flash, WRITE_SIZE};
 use embassy_stm32::gpio::{AnyPin, Level, Output, Pin, Pull, Speed};
+use embassy_stm32::rtc::{Rtc, RtcConfig};
 use embassy_stm32::spi::Spi;
-use embassy_time::{Duration, Timer};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+use lora_phy::mod_params::RadioError;
 use {defmt_rtt as _, panic_probe as _};
 
 use lora2traffic::*;
@@ -18,11 +29,14 @@ use lora2traffic::*;
 async fn main(_spawner: Spawner) {
     let config = create_stm32_config();
     let p = embassy_stm32::init(config);
+    let reset_cause = reset_cause();
+    info!("Reset cause = {:?}", reset_cause);
 
-    let mut button = ExtiInput::new(p.PA0, p.EXTI0, Pull::Up);
+    let mut button = Button::new(ExtiInput::new(p.PA0, p.EXTI0, Pull::Up));
     let mut indicator = SignalIndicator::new(
         p.PB11.degrade(), // Red LED
         p.PB9.degrade(),  // Green LED
+        p.PB15.degrade(), // Blue LED
     );
 
     // Set CTRL1 and CTRL3 for high-power transmission, while CTRL2 acts as an RF switch between tx and rx
@@ -30,91 +44,796 @@ async fn main(_spawner: Spawner) {
     let ctrl2 = Output::new(p.PC5.degrade(), Level::Low, Speed::High);
     let ctrl3 = Output::new(p.PC3.degrade(), Level::High, Speed::High);
 
+    let flash = Mutex::new(RefCell::new(Flash::new_blocking(p.FLASH)));
+    let store = ConfigStore::new(FlashStorage::new(&flash));
+    let mut config_manager = ConfigManager::new(store, DEFAULT_CONFIG, CONFIG_AUTH_KEY);
+    let mut config = *config_manager.config();
+    let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+    let mut updater = Updater::new(&flash, &mut aligned);
+    let mut event_log = EventLog::new(FlashStorage::event_log(&flash));
+
     let spi = Spi::new_subghz(p.SUBGHZSPI, p.DMA1_CH1, p.DMA1_CH2);
-    let mut lora = LoraHw::new(ctrl1, ctrl2, ctrl3, spi).await;
+    let mut lora = LoraHw::new(
+        config.address,
+        config.frequency_hz,
+        config.tx_power_dbm,
+        CAPABILITIES,
+        ctrl1,
+        ctrl2,
+        ctrl3,
+        spi,
+    )
+    .await;
+    for receiver in RECEIVERS {
+        if let Err(e) = lora.say_hello(*receiver).await {
+            warn!("Radio error = {}", e);
+        }
+    }
 
-    // Query the signal state.
-    lora.send(Message::QuerySignal).await.unwrap();
-    let mut signal = match lora.receive().await.unwrap() {
-        Message::Signal(signal) => signal,
-        _ => {
+    let rtc = Rtc::new(p.RTC, RtcConfig::default());
+    let clock = Clock::new(read_rtc(&rtc).unwrap_or(0), Instant::now().as_millis());
+    let mut time_sync = TimeSync::new(clock);
+    event_log.log(now_s(&time_sync), EventKind::Reset(reset_cause));
+    let mut controller = Controller::new(CONTROL_MODE, TRANSIT_PRIORITY);
+    controller.set_timing(config.red_s, config.green_s);
+    if let Some(coordination) = controller.coordination() {
+        query_cycle(&mut lora, &mut time_sync, &coordination).await;
+    }
+
+    let saved_state = load_state(&rtc);
+    info!("Saved state = {:?}", saved_state);
+
+    // Query the signal state, from the first receiver to answer.
+    let mut signal = None;
+    for receiver in RECEIVERS {
+        signal = query_signal(&mut lora, *receiver).await;
+        if signal.is_some() {
+            break;
+        }
+    }
+    let signal = match signal {
+        Some(signal) => {
+            // A new firmware that hears from a receiver is good.
+            updater.mark_booted();
+            signal
+        }
+        None => {
             info!("No signal received, defaulting to red");
-            Signal::default()
+            Signal::Red
         }
     };
     info!("Initial signal = {:?}", signal);
     indicator.set(signal);
+    // The receivers report on their own after, every `STATUS_INTERVAL_S`.
+    for receiver in RECEIVERS {
+        if lora.check(*receiver, &Message::QueryStatus).is_ok() {
+            if let Err(e) = lora.send(*receiver, Message::QueryStatus).await {
+                warn!("Radio error = {}", e);
+            }
+        }
+    }
+    let uptime = Instant::now().as_millis();
+    let time = coordinated_time(&controller, &time_sync, uptime);
+    controller.start(signal, uptime, time);
+    broadcast_time(&mut lora, time_sync.clock()).await;
+    let mut last_time_sync = Instant::now();
+    let mut pedestrian_signal = PedestrianSignal::DontWalk;
+    let mut operating_mode = OperatingMode::Automatic;
+    if let Some(saved_state) = saved_state {
+        // An operator who was in control, still is.
+        controller.set_operating_mode(saved_state.mode, Instant::now().as_millis());
+        if let Err(err) =
+            update_operating_mode(&mut lora, &mut controller, &mut operating_mode).await
+        {
+            info!("Radio error = {}", err);
+            return;
+        }
+        indicator.set_mode(operating_mode);
+    }
+    let mode = operating_mode;
+    save_state(&rtc, SavedState { mode });
+    let mut heartbeat_at = Instant::now();
+    let mut link_states = [LinkState::Lost; RECEIVERS.len()];
+    let mut logged_mode = OperatingMode::Automatic;
     loop {
-        let duration = signal.duration();
-        // Wait for either timeout or button press.
-        select::select(
-            Timer::after_secs(duration),
-            wait_for_button_press(&mut button),
-        )
-        .await;
+        // Wait for either the end of the phase or button press, while driving the pedestrian
+        // signal and handling messages from other nodes. A short press skips the phase, a long
+        // press toggles manual control and a double press toggles flashing.
+        loop {
+            if operating_mode != logged_mode {
+                event_log.log(now_s(&time_sync), EventKind::OperatingMode(operating_mode));
+                logged_mode = operating_mode;
+            }
+            // Resting phases have no end.
+            let next_event_ms = controller
+                .next_event_ms()
+                .min(config_manager.rollback_ms().unwrap_or(u64::MAX))
+                .min(heartbeat_at.as_millis())
+                .min(Instant::MAX.as_millis());
+            let next_event = Instant::from_millis(next_event_ms);
+            // The packets held while waiting for ACKs go first.
+            let (event, received_ms) = match HELD.try_receive() {
+                Ok((packet, received_ms)) => (Either3::Third(Ok(packet)), received_ms),
+                Err(_) => {
+                    let event = select::select3(
+                        Timer::at(next_event),
+                        button.wait_for_gesture(),
+                        lora.receive(),
+                    )
+                    .await;
+                    (event, Instant::now().as_millis())
+                }
+            };
+            match event {
+                Either3::First(_) => {
+                    if Instant::now() >= heartbeat_at {
+                        let signal = controller.signal();
+                        let mode = controller.operating_mode();
+                        let dst = Address::BROADCAST;
+                        if let Err(e) = lora.send_heartbeat(dst, signal, mode).await {
+                            warn!("Radio error = {}", e);
+                        }
+                        heartbeat_at = Instant::now() + HEARTBEAT_INTERVAL;
+                        let time_s = now_s(&time_sync);
+                        for (receiver, state) in RECEIVERS.iter().zip(&mut link_states) {
+                            update_link_state(&lora, *receiver, state, &mut event_log, time_s);
+                        }
+                    }
+                    let uptime = Instant::now().as_millis();
+                    if config_manager.roll_back_if_due(uptime) {
+                        let new = config_manager.config();
+                        apply_config(&mut lora, &mut controller, new, &mut config);
+                    }
+                    if controller.phase_over(uptime) {
+                        break;
+                    }
+                    controller.update_pedestrian(uptime);
+                    if let Err(err) =
+                        update_pedestrian_signal(&mut lora, &mut controller, &mut pedestrian_signal)
+                            .await
+                    {
+                        info!("Radio error = {}", err);
+                        return;
+                    }
+                }
+                Either3::Second(Gesture::ShortPress) => {
+                    if controller.skip(Instant::now().as_millis()) {
+                        break;
+                    }
+                    info!("Pedestrians are crossing, pre-empted or flashing, not skipping");
+                }
+                Either3::Second(gesture) => {
+                    let mode = match (gesture, controller.operating_mode()) {
+                        (Gesture::LongPress, OperatingMode::Manual)
+                        | (Gesture::DoublePress, OperatingMode::Flash) => OperatingMode::Automatic,
+                        (Gesture::LongPress, _) => OperatingMode::Manual,
+                        _ => OperatingMode::Flash,
+                    };
+                    controller.set_operating_mode(mode, Instant::now().as_millis());
+                    if let Err(err) =
+                        update_operating_mode(&mut lora, &mut controller, &mut operating_mode).await
+                    {
+                        info!("Radio error = {}", err);
+                        return;
+                    }
+                    indicator.set_mode(operating_mode);
+                    let mode = operating_mode;
+                    save_state(&rtc, SavedState { mode });
+                }
+                Either3::Third(Ok(
+                    packet @ Packet {
+                        msg:
+                            Message::GetConfig(_)
+                            | Message::SetConfig { .. }
+                            | Message::ConfirmConfig { .. },
+                        ..
+                    },
+                )) => {
+                    let uptime = Instant::now().as_millis();
+                    let reply = config_manager.handle(packet.src, packet.dst, packet.msg, uptime);
+                    if let Some(reply) = reply {
+                        // Before the radio settings change.
+                        if let Err(e) = lora.send(packet.src, reply).await {
+                            warn!("Radio error = {}", e);
+                        }
+                    }
+                    let new = config_manager.config();
+                    apply_config(&mut lora, &mut controller, new, &mut config);
+                }
+                Either3::Third(Ok(
+                    packet @ Packet {
+                        msg:
+                            Message::OtaStart { .. }
+                            | Message::OtaFragment { .. }
+                            | Message::OtaStatus
+                            | Message::OtaFinish,
+                        ..
+                    },
+                )) => {
+                    // Starting erases the DFU partition, so only an authenticated start.
+                    let authenticated = !matches!(packet.msg, Message::OtaStart { .. })
+                        || config_manager.authenticate_ota_start(
+                            packet.src,
+                            packet.dst,
+                            &packet.msg,
+                        );
+                    let reply = authenticated.then(|| updater.handle(packet.msg)).flatten();
+                    if let Some(reply) = reply {
+                        if let Err(e) = lora.send(packet.src, reply).await {
+                            warn!("Radio error = {}", e);
+                        }
+                    }
+                    updater.restart_if_updated();
+                }
+                Either3::Third(Ok(Packet {
+                    src,
+                    msg: Message::GetEvents { from },
+                    ..
+                })) => {
+                    info!("Events from {} requested by {:?}", from, src);
+                    let msg = Message::Events(event_log.page(from));
+                    if let Err(e) = lora.send(src, msg).await {
+                        warn!("Radio error = {}", e);
+                    }
+                }
+                Either3::Third(Ok(packet)) => {
+                    updater.mark_booted();
+                    handle_packet(
+                        &mut lora,
+                        &mut time_sync,
+                        &mut controller,
+                        &mut config_manager,
+                        &mut event_log,
+                        packet,
+                        received_ms,
+                    )
+                    .await;
+                    // Pre-emption may cut the walk short.
+                    if let Err(err) =
+                        update_pedestrian_signal(&mut lora, &mut controller, &mut pedestrian_signal)
+                            .await
+                    {
+                        info!("Radio error = {}", err);
+                        return;
+                    }
+                }
+                Either3::Third(Err(e)) => warn!("RX error = {}", e),
+            }
+        }
 
-        signal.rotate();
+        let uptime = Instant::now().as_millis();
+        let time = coordinated_time(&controller, &time_sync, uptime);
+        let signal = controller.advance(uptime, time);
 
         let msg = Message::Signal(signal);
-
-        for _ in 1..=3 {
-            match lora.send(msg).await {
-                Ok(()) => {
-                    info!("TX DONE");
+        match send_command(&mut lora, msg).await {
+            Ok(acks) if acks.is_complete() => info!("ACKs received"),
+            Ok(acks) => {
+                warn!("No ACK for signal = {:?} from {:?}", signal, acks.pending());
+                if let Some(mode) = ACK_POLICY.operating_mode() {
+                    warn!("Switching to {:?} mode", mode);
+                    controller.set_operating_mode(mode, Instant::now().as_millis());
                 }
-                Err(err) => {
-                    info!("Radio error = {}", err);
-                    return;
+            }
+            Err(err) => {
+                info!("Radio error = {}", err);
+                return;
+            }
+        }
+        if let Err(err) =
+            update_pedestrian_signal(&mut lora, &mut controller, &mut pedestrian_signal).await
+        {
+            info!("Radio error = {}", err);
+            return;
+        }
+        // Manual control may have timed out.
+        if let Err(err) =
+            update_operating_mode(&mut lora, &mut controller, &mut operating_mode).await
+        {
+            info!("Radio error = {}", err);
+            return;
+        }
+        indicator.set_mode(operating_mode);
+        if last_time_sync.elapsed() >= Duration::from_secs(TIME_SYNC_INTERVAL_SECS) {
+            broadcast_time(&mut lora, time_sync.clock()).await;
+            if let Some(coordination) = controller.coordination() {
+                if coordination.role == CoordinationRole::Master {
+                    send_cycle_sync(&mut lora, &time_sync, &coordination, Address::BROADCAST).await;
                 }
-            };
-            // Receive the ACK
-            match select::select(Timer::after(RECEIVE_TIMEOUT), lora.receive()).await {
+            }
+            last_time_sync = Instant::now();
+        }
+        lora.sleep().await;
+
+        indicator.set(signal);
+        event_log.log(now_s(&time_sync), EventKind::Signal(signal));
+        let mode = operating_mode;
+        save_state(&rtc, SavedState { mode });
+    }
+}
+
+/// Query the signal of `receiver`, giving it time to come up if it's (re)starting too.
+async fn query_signal(lora: &mut LoraHw, receiver: Address) -> Option<Signal> {
+    for _ in 1..=3 {
+        if let Err(e) = lora.send(receiver, Message::QuerySignal).await {
+            warn!("Radio error = {}", e);
+            continue;
+        }
+
+        let deadline = Instant::now() + RECEIVE_TIMEOUT;
+        loop {
+            match select::select(Timer::at(deadline), lora.receive()).await {
                 Either::First(_) => {
-                    info!("Timeout waiting for ACK");
-                    // Probably didn't receive our signal, so we'll try again.
-                    continue;
+                    info!("Timeout waiting for signal");
+                    break;
                 }
-                Either::Second(Err(e)) => {
-                    warn!("RX error = {}", e);
+                Either::Second(Ok(Packet {
+                    src,
+                    msg: Message::Signal(signal),
+                    ..
+                })) if src == receiver => return Some(signal),
+                Either::Second(Ok(packet)) => info!("Ignoring packet: {:?}", packet),
+                Either::Second(Err(e)) => warn!("RX error = {}", e),
+            }
+        }
+    }
 
-                    // Try again.
-                    continue;
-                }
-                Either::Second(Ok(Message::Signal(sig))) if sig == signal => info!("ACK received"),
-                Either::Second(Ok(Message::Signal(sig))) => {
-                    warn!("ACK received with different signal: {:?}", sig);
-                    signal = sig;
+    None
+}
+
+/// Send `msg` to the receivers that can handle it, and collect their ACKs, messages of the same
+/// kind. Returns the receivers that didn't ACK.
+///
+/// The first time, it's broadcast and the receivers ACK in turn. Then it's sent again to each
+/// receiver that didn't ACK. Messages from other nodes are held while waiting for the ACKs, for
+/// their replies not to collide with them, see [`HELD`].
+async fn send_command(lora: &mut LoraHw, msg: Message) -> Result<AckTracker, RadioError> {
+    let mut acks = AckTracker::new(RECEIVERS.iter().copied().filter(|receiver| {
+        let check = lora.check(*receiver, &msg);
+        if let Err(e) = check {
+            info!("{:?} can't handle {:?}: {}", receiver, msg, e);
+        }
+
+        check.is_ok()
+    }));
+
+    for attempt in 1..=3 {
+        let dsts: Vec<Address, MAX_RECEIVERS> = match acks.pending() {
+            [] => break,
+            [_, _, ..] if attempt == 1 => Vec::from_slice(&[Address::BROADCAST]).unwrap(),
+            pending => Vec::from_slice(pending).unwrap(),
+        };
+        for dst in dsts {
+            lora.send(dst, msg).await?;
+            info!("TX DONE");
+
+            // Receive the ACKs
+            let timeout = match dst {
+                Address::BROADCAST => Duration::from_millis(ack_window_ms(DEFAULT_TTL)),
+                _ => RECEIVE_TIMEOUT + Duration::from_millis(forwarding_delay_ms(DEFAULT_TTL)),
+            };
+            let deadline = Instant::now() + timeout;
+            while acks.is_pending(dst) || (dst == Address::BROADCAST && !acks.is_complete()) {
+                match select::select(Timer::at(deadline), lora.receive()).await {
+                    Either::First(_) => {
+                        info!("Timeout waiting for ACKs from {:?}", acks.pending());
+                        break;
+                    }
+                    Either::Second(Ok(packet)) => {
+                        if is_ack(&msg, &packet.msg) && acks.ack(packet.src) {
+                            info!("ACK from {:?}", packet.src);
+                        } else if HELD.try_send((packet, Instant::now().as_millis())).is_err() {
+                            warn!("Too many packets held, dropping {:?}", packet);
+                        }
+                    }
+                    Either::Second(Err(e)) => warn!("RX error = {}", e),
                 }
-                Either::Second(Ok(Message::QuerySignal)) => {
-                    warn!("Unexpected query message received")
+            }
+        }
+        // Probably didn't receive our message, so we'll try again.
+    }
+
+    Ok(acks)
+}
+
+/// Whether `ack` is the ACK of `msg`, rather than a late one of an earlier message.
+fn is_ack(msg: &Message, ack: &Message) -> bool {
+    match (msg, ack) {
+        (Message::Signal(signal), Message::Signal(acked)) => signal == acked,
+        _ => discriminant(msg) == discriminant(ack),
+    }
+}
+
+/// Send the pedestrian signal to the receivers, if it changed since it was last `sent`.
+async fn update_pedestrian_signal(
+    lora: &mut LoraHw,
+    controller: &mut Controller,
+    sent: &mut PedestrianSignal,
+) -> Result<(), RadioError> {
+    let signal = controller.pedestrian_signal();
+    if signal == *sent {
+        return Ok(());
+    }
+
+    let countdown_s = controller.pedestrian_countdown_s(Instant::now().as_millis());
+    let msg = Message::PedestrianSignal {
+        signal,
+        countdown_s,
+    };
+    // Receivers without a pedestrian head don't get it.
+    let acks = send_command(lora, msg).await?;
+    if acks.is_complete() {
+        info!("ACKs received");
+        *sent = signal;
+    } else {
+        warn!(
+            "No ACK for pedestrian signal = {:?} from {:?}",
+            signal,
+            acks.pending()
+        );
+    }
+
+    Ok(())
+}
+
+/// Let the receivers know about the operating mode, if it changed since it was last `sent`.
+async fn update_operating_mode(
+    lora: &mut LoraHw,
+    controller: &mut Controller,
+    sent: &mut OperatingMode,
+) -> Result<(), RadioError> {
+    let mode = controller.operating_mode();
+    if mode == *sent {
+        return Ok(());
+    }
+
+    let msg = Message::OperatingMode(mode);
+    let acks = send_command(lora, msg).await?;
+    if acks.is_complete() {
+        info!("ACKs received");
+    } else {
+        warn!(
+            "No ACK for operating mode = {:?} from {:?}",
+            mode,
+            acks.pending()
+        );
+    }
+    // The indicator shows the controller's mode, whether or not the receivers got it.
+    *sent = mode;
+
+    Ok(())
+}
+
+/// Apply the `new` config, if it's different from the `applied` one.
+fn apply_config(
+    lora: &mut LoraHw,
+    controller: &mut Controller,
+    new: &Config,
+    applied: &mut Config,
+) {
+    if new == applied {
+        return;
+    }
+
+    if let Err(e) = lora.reconfigure(new.address, new.frequency_hz, new.tx_power_dbm) {
+        warn!("Failed to reconfigure the radio: {}", e);
+    }
+    controller.set_timing(new.red_s, new.green_s);
+    *applied = *new;
+}
+
+/// The synchronized time, if the controller is coordinated and the time is good enough for it.
+fn coordinated_time(controller: &Controller, time_sync: &TimeSync, uptime_ms: u64) -> Option<u64> {
+    let coordination = controller.coordination()?;
+    let synced = coordination.role == CoordinationRole::Master
+        || time_sync.status(uptime_ms).state != SyncState::Unsynced;
+
+    synced.then(|| time_sync.clock().now(uptime_ms))
+}
+
+/// Handle `packet`, received at `received_ms`.
+async fn handle_packet(
+    lora: &mut LoraHw,
+    time_sync: &mut TimeSync,
+    controller: &mut Controller,
+    config_manager: &mut ConfigManager<FlashStorage<'_, '_>>,
+    event_log: &mut EventLog<FlashStorage<'_, '_>, EVENT_LOG_LEN>,
+    packet: Packet,
+    received_ms: u64,
+) {
+    match (packet.msg, controller.coordination()) {
+        (
+            Message::CycleSync {
+                time_ms,
+                cycle_length_s,
+            },
+            Some(coordination),
+        ) => {
+            if cycle_length_s != coordination.cycle_length_s {
+                warn!(
+                    "{:?} runs a different cycle length: {}s",
+                    packet.src, cycle_length_s
+                );
+            } else if coordination.role == CoordinationRole::Secondary {
+                let status = time_sync.update(received_ms, time_ms);
+                info!("Cycle synced with {:?}, status = {:?}", packet.src, status);
+            }
+        }
+        (Message::QueryCycle, Some(coordination)) => {
+            let status = time_sync.status(Instant::now().as_millis());
+            // Only share the time if it's worth sharing.
+            if coordination.role == CoordinationRole::Master || status.state == SyncState::Synced {
+                send_cycle_sync(lora, time_sync, &coordination, packet.src).await;
+            }
+        }
+        (Message::PedestrianCall, _) if RECEIVERS.contains(&packet.src) => {
+            info!("Pedestrian call from {:?}", packet.src);
+            controller.pedestrian_call(Instant::now().as_millis());
+            // ACK
+            if let Err(e) = lora.send(packet.src, Message::PedestrianCall).await {
+                warn!("Radio error = {}", e);
+            }
+        }
+        (
+            Message::Heartbeat {
+                signal,
+                mode: receiver_mode,
+                ..
+            },
+            _,
+        ) if RECEIVERS.contains(&packet.src) => {
+            let mode = controller.operating_mode();
+            let expected = controller.signal();
+            // E.g the receiver lost us for a while, and went flashing. One fix per heartbeat, as the
+            // receiver's ACK would collide with a second message.
+            let fix = if receiver_mode != mode {
+                Some(Message::OperatingMode(mode))
+            } else if signal != expected && mode != OperatingMode::Flash {
+                Some(Message::Signal(expected))
+            } else {
+                None
+            };
+            if let Some(msg) = fix {
+                warn!(
+                    "{:?} shows {:?} in {:?} mode, sending {:?}",
+                    packet.src, signal, receiver_mode, msg
+                );
+                if let Err(e) = lora.send(packet.src, msg).await {
+                    warn!("Radio error = {}", e);
                 }
             }
+        }
+        // Late ACKs.
+        (Message::Signal(_) | Message::OperatingMode(_), _) if RECEIVERS.contains(&packet.src) => {}
+        (Message::Status(status), _) if RECEIVERS.contains(&packet.src) => {
+            if status.faults == Faults::NONE {
+                info!("{:?} status = {:?}", packet.src, status);
+            } else {
+                warn!("{:?} status = {:?}", packet.src, status);
+            }
+        }
+        (Message::Detection { detector, occupied }, _) if RECEIVERS.contains(&packet.src) => {
+            info!("Detector {} occupied = {}", detector, occupied);
+            controller.detection(detector, occupied, Instant::now().as_millis());
+        }
+        (
+            msg @ Message::Preempt {
+                approach, active, ..
+            },
+            _,
+        ) if preemption_authorized(config_manager, &packet) => {
+            info!(
+                "Pre-emption request from {:?} for {:?} approach, active = {}",
+                packet.src, approach, active
+            );
+            let uptime = Instant::now().as_millis();
+            event_log.log(now_s(time_sync), EventKind::Preempt { approach, active });
+            if active {
+                controller.preempt(approach, uptime);
+            } else {
+                controller.release_preemption(approach, uptime);
+            }
+            // ACK
+            if let Err(e) = lora.send(packet.src, msg).await {
+                warn!("Radio error = {}", e);
+            }
+        }
+        (
+            msg @ Message::PriorityRequest {
+                class,
+                approach,
+                eta_s,
+            },
+            _,
+        ) => {
+            info!(
+                "Priority request from {:?}: {:?} on {:?} approach, ETA {}s",
+                packet.src, class, approach, eta_s
+            );
+            controller.priority_request(class, approach, eta_s, received_ms);
+            // ACK
+            if let Err(e) = lora.send(packet.src, msg).await {
+                warn!("Radio error = {}", e);
+            }
+        }
+        (Message::Preempt { .. }, _) => {
+            warn!("Pre-emption request from unauthorized {:?}", packet.src)
+        }
+        _ => warn!("Unexpected packet received: {:?}", packet),
+    }
+}
 
-            // Reaching here means we've received an ACK.
-            break;
+/// Whether `packet` comes from one of [`PREEMPT_SOURCES`], and passes authentication.
+fn preemption_authorized(
+    config_manager: &mut ConfigManager<FlashStorage<'_, '_>>,
+    packet: &Packet,
+) -> bool {
+    let Some(source) = PREEMPT_SOURCES.iter().position(|src| *src == packet.src) else {
+        return false;
+    };
+
+    config_manager.authenticate_preemption(
+        &PREEMPT_AUTH_KEY,
+        source,
+        packet.src,
+        packet.dst,
+        &packet.msg,
+    )
+}
+
+/// Log the changes of the link with `receiver`.
+fn update_link_state(
+    lora: &LoraHw,
+    receiver: Address,
+    state: &mut LinkState,
+    event_log: &mut EventLog<FlashStorage<'_, '_>, EVENT_LOG_LEN>,
+    time_s: u32,
+) {
+    let new = lora.link_state(receiver);
+    if new == *state {
+        return;
+    }
+    let peer = receiver;
+    event_log.log(time_s, EventKind::Link { peer, state: new });
+
+    match (new, lora.link(receiver)) {
+        (LinkState::Good, _) => info!("Link with {:?} is good", receiver),
+        (_, Some(link)) => warn!(
+            "Link with {:?} is {:?}: {}% lost, RSSI = {} dBm (trend {} dB)",
+            receiver,
+            new,
+            link.loss_percent(),
+            link.rssi_dbm(),
+            link.rssi_trend_db()
+        ),
+        (_, None) => warn!("Link with {:?} is {:?}", receiver, new),
+    }
+    *state = new;
+}
+
+/// The time for the event log.
+fn now_s(time_sync: &TimeSync) -> u32 {
+    (time_sync.clock().now(Instant::now().as_millis()) / 1000) as u32
+}
+
+/// Packets received while waiting for ACKs, and when, for the main loop to handle after them.
+static HELD: Channel<CriticalSectionRawMutex, (Packet, u64), 4> = Channel::new();
+
+/// Get the time from the other coordinated controllers, after a (re)start.
+///
+/// This is also done by the master, so that the controllers along the road keep their common time.
+async fn query_cycle(lora: &mut LoraHw, time_sync: &mut TimeSync, coordination: &Coordination) {
+    if let Err(e) = lora.send(Address::BROADCAST, Message::QueryCycle).await {
+        warn!("Radio error = {}", e);
+        return;
+    }
+
+    let deadline = Instant::now() + RECEIVE_TIMEOUT;
+    loop {
+        match select::select(Timer::at(deadline), lora.receive()).await {
+            Either::First(_) => {
+                info!("No coordinated controller around, using our own time");
+                return;
+            }
+            Either::Second(Ok(Packet {
+                src,
+                msg:
+                    Message::CycleSync {
+                        time_ms,
+                        cycle_length_s,
+                    },
+                ..
+            })) if cycle_length_s == coordination.cycle_length_s => {
+                let status = time_sync.update(Instant::now().as_millis(), time_ms);
+                info!("Cycle synced with {:?}, status = {:?}", src, status);
+                return;
+            }
+            Either::Second(Ok(packet)) => info!("Ignoring packet: {:?}", packet),
+            Either::Second(Err(e)) => warn!("RX error = {}", e),
         }
-        lora.sleep().await;
+    }
+}
 
-        indicator.set(signal);
+async fn send_cycle_sync(
+    lora: &mut LoraHw,
+    time_sync: &TimeSync,
+    coordination: &Coordination,
+    dst: Address,
+) {
+    let cycle_sync = |time_ms| Message::CycleSync {
+        time_ms,
+        cycle_length_s: coordination.cycle_length_s,
+    };
+    let time = time_at_end_of_tx(lora, time_sync.clock(), dst, cycle_sync(0));
+    if let Err(e) = lora.send(dst, cycle_sync(time)).await {
+        warn!("Radio error = {}", e);
     }
 }
 
-async fn wait_for_button_press(button: &mut ExtiInput<'_>) {
-    button.wait_for_falling_edge().await;
-    info!("Button pressed");
-    button.wait_for_rising_edge().await;
+async fn broadcast_time(lora: &mut LoraHw, clock: &Clock) {
+    let time = time_at_end_of_tx(lora, clock, Address::BROADCAST, Message::TimeSync(0));
+    if let Err(e) = lora.send(Address::BROADCAST, Message::TimeSync(time)).await {
+        warn!("Radio error = {}", e);
+        return;
+    }
+
+    // The receivers reply in turn.
+    let mut replies = AckTracker::new(RECEIVERS.iter().copied());
+    let deadline = Instant::now() + Duration::from_millis(ack_window_ms(DEFAULT_TTL));
+    while !replies.is_complete() {
+        match select::select(Timer::at(deadline), lora.receive()).await {
+            Either::First(_) => {
+                warn!(
+                    "Timeout waiting for time sync status of {:?}",
+                    replies.pending()
+                );
+                break;
+            }
+            Either::Second(Ok(Packet {
+                src,
+                msg: Message::SyncStatus(status),
+                ..
+            })) if replies.ack(src) => info!("{:?} time sync status = {:?}", src, status),
+            Either::Second(Ok(packet)) => warn!("Unexpected packet received: {:?}", packet),
+            Either::Second(Err(e)) => warn!("RX error = {}", e),
+        }
+    }
+}
+
+/// The time at which the receiving nodes get `msg`, so they can compensate for the time on air.
+fn time_at_end_of_tx(lora: &LoraHw, clock: &Clock, dst: Address, msg: Message) -> u64 {
+    let packet = Packet {
+        src: lora.address(),
+        dst,
+        seq: Some(0),
+        ttl: DEFAULT_TTL,
+        msg,
+    };
+    let airtime = time_on_air(packet.to_bytes().len());
+
+    clock.now(Instant::now().as_millis()) + airtime.as_millis()
 }
 
 struct SignalIndicator {
     red: Output<'static>,
     green: Output<'static>,
+    manual: Output<'static>,
 }
 
 impl SignalIndicator {
-    fn new(red: AnyPin, green: AnyPin) -> Self {
+    fn new(red: AnyPin, green: AnyPin, manual: AnyPin) -> Self {
         Self {
             red: Output::new(red, Level::High, Speed::Low),
             green: Output::new(green, Level::High, Speed::Low),
+            manual: Output::new(manual, Level::Low, Speed::Low),
+        }
+    }
+
+    fn set_mode(&mut self, mode: OperatingMode) {
+        self.manual
+            .set_level((mode == OperatingMode::Manual).into());
+        if mode == OperatingMode::Flash {
+            self.set(Signal::Yellow);
         }
     }
 
@@ -125,7 +844,7 @@ impl SignalIndicator {
                 self.green.set_low();
             }
             Signal::Yellow => {
-                // We don't use the blue LED (on PB15) but rather both red and green simultaneously.
+                // The blue LED (on PB15) shows manual control, so use both red and green instead.
                 self.red.set_high();
                 self.green.set_high();
             }
@@ -142,3 +861,38 @@ impl SignalIndicator {
 }
 
 const RECEIVE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Events kept in RAM, the flash keeps fewer.
+const EVENT_LOG_LEN: usize = 64;
+/// Used until changed remotely.
+const DEFAULT_CONFIG: Config = Config {
+    address: Address(1),
+    frequency_hz: LORA_FREQUENCY_IN_HZ,
+    tx_power_dbm: 20,
+    failsafe_timeout_s: 0,
+    red_s: 30,
+    green_s: 30,
+};
+/// One per signal head, with consecutive addresses for their ACKs to take turns.
+const RECEIVERS: &[Address] = &[Address(2)];
+/// What to do when a receiver doesn't ACK a phase change.
+const ACK_POLICY: AckPolicy = AckPolicy::FailSafe;
+const CAPABILITIES: Capabilities = Capabilities::TIME_SYNC
+    .union(Capabilities::PEDESTRIAN_CALL)
+    .union(Capabilities::DETECTORS)
+    .union(Capabilities::OPERATING_MODE)
+    .union(Capabilities::REMOTE_CONFIG)
+    .union(Capabilities::OTA)
+    .union(Capabilities::HEARTBEAT)
+    .union(Capabilities::EVENT_LOG);
+/// Use `ControlMode::Coordinated` to coordinate the signal cycle with the other controllers along
+/// the road, or `ControlMode::Actuated` to let the receiver's vehicle detectors drive it.
+const CONTROL_MODE: ControlMode = ControlMode::FixedTime;
+/// Set to `None` to disable transit signal priority.
+const TRANSIT_PRIORITY: Option<TransitPriority> = Some(TransitPriority {
+    classes: &[VehicleClass::Bus, VehicleClass::Tram],
+    max_extension_s: 15,
+    max_reduction_s: 10,
+});
+/// Nodes allowed to pre-empt the signal, e.g emergency vehicles or the fire station. Their requests
+/// are signed with [`PREEMPT_AUTH_KEY`], see [`preempt`].
+const PREEMPT_SOURCES: &[Address] = &[Address(3)];
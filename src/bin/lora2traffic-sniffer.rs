@@ -0,0 +1,84 @@
+//! Monitors the channel, on a STM32WL board: every frame heard is decoded and logged, and streamed
+//! as a PCAP capture over the LPUART, whoever it's for.
+//!
+//! The capture is written at [`GATEWAY_BAUD_RATE`] on PA2, starting with the PCAP header at
+//! startup. Capture it with `lora2traffic-cli <port> sniff`, which follows it live and saves it for
+//! Wireshark. The timestamps are the time since startup.
+#![no_std]
+#![no_main]
+
+use defmt::{info, warn};
+use embassy_executor::Spawner;
+use embassy_stm32::gpio::{Level, Output, Pin, Speed};
+use embassy_stm32::mode::Async;
+use embassy_stm32::spi::Spi;
+use embassy_stm32::usart::{self, UartTx};
+use embassy_time::Timer;
+use {defmt_rtt as _, panic_probe as _};
+
+use lora2traffic::*;
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let config = create_stm32_config();
+    let p = embassy_stm32::init(config);
+
+    // Set CTRL1 and CTRL3 for high-power transmission, while CTRL2 acts as an RF switch between tx and rx
+    let ctrl1 = Output::new(p.PC4.degrade(), Level::Low, Speed::High);
+    let ctrl2 = Output::new(p.PC5.degrade(), Level::Low, Speed::High);
+    let ctrl3 = Output::new(p.PC3.degrade(), Level::High, Speed::High);
+
+    let spi = Spi::new_subghz(p.SUBGHZSPI, p.DMA1_CH1, p.DMA1_CH2);
+    // The sniffer never sends, its address and TX power don't matter.
+    let mut lora = LoraHw::new(
+        Address::BROADCAST,
+        FREQUENCY_HZ,
+        0,
+        Capabilities::NONE,
+        ctrl1,
+        ctrl2,
+        ctrl3,
+        spi,
+    )
+    .await;
+
+    let mut uart_config = usart::Config::default();
+    uart_config.baudrate = GATEWAY_BAUD_RATE;
+    let mut tx = UartTx::new(p.LPUART1, p.PA2, p.DMA1_CH3, uart_config).unwrap();
+    write(&mut tx, &PCAP_HEADER).await;
+    info!("Sniffing on {} Hz", FREQUENCY_HZ);
+
+    loop {
+        let capture = match lora.sniff().await {
+            Ok(capture) => capture,
+            Err(e) => {
+                warn!("Radio error = {}", e);
+                Timer::after_secs(1).await;
+
+                continue;
+            }
+        };
+        let (rssi_dbm, snr_db) = (capture.rssi_dbm, capture.snr_db);
+        match Packet::from_bytes(&capture.bytes) {
+            _ if capture.is_corrupt() => warn!("Corrupt frame"),
+            Ok(packet) => info!("{} dBm, SNR {} dB: {:?}", rssi_dbm, snr_db, packet),
+            Err(e) => info!(
+                "{} dBm, SNR {} dB: undecodable frame = {}, {=[u8]:x}",
+                rssi_dbm,
+                snr_db,
+                e,
+                &capture.bytes[..]
+            ),
+        }
+        write(&mut tx, &capture.to_record()).await;
+    }
+}
+
+async fn write(tx: &mut UartTx<'_, Async>, bytes: &[u8]) {
+    if let Err(e) = tx.write(bytes).await {
+        warn!("UART error = {}", e);
+    }
+}
+
+/// The frequency of the network to monitor.
+const FREQUENCY_HZ: u32 = LORA_FREQUENCY_IN_HZ;
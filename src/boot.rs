@@ -0,0 +1,101 @@
+use cortex_m::peripheral::SCB;
+use defmt::{info, warn};
+use embassy_boot::{AlignedBuffer, BlockingFirmwareState, FirmwareUpdaterConfig, State};
+use embassy_stm32::flash::WRITE_SIZE;
+
+use crate::{FlashPartition, Message, OtaReceiver, SharedFlash};
+
+/// Firmware updates over the air, received into the DFU partition and swapped in by the bootloader
+/// on the next reset.
+///
+/// A new firmware must be confirmed with [`Updater::mark_booted`] once it's known to work, e.g when
+/// it hears from its peer. Otherwise, the bootloader swaps the previous one back on the next reset.
+pub struct Updater<'a, 'd> {
+    dfu: FlashPartition<'a, 'd>,
+    state: BlockingFirmwareState<'a, FlashPartition<'a, 'd>>,
+    ota: OtaReceiver,
+    booted: bool,
+    updated: bool,
+}
+
+impl<'a, 'd> Updater<'a, 'd> {
+    pub fn new(flash: &'a SharedFlash<'d>, aligned: &'a mut AlignedBuffer<WRITE_SIZE>) -> Self {
+        let config = FirmwareUpdaterConfig::from_linkerfile_blocking(flash, flash);
+
+        Self {
+            dfu: config.dfu,
+            state: BlockingFirmwareState::new(config.state, &mut aligned.0),
+            ota: OtaReceiver::new(),
+            booted: false,
+            updated: false,
+        }
+    }
+
+    /// Confirm the running firmware, if it was just swapped in.
+    pub fn mark_booted(&mut self) {
+        if self.booted {
+            return;
+        }
+
+        match self.state.get_state() {
+            Ok(State::Swap) => match self.state.mark_booted() {
+                Ok(()) => info!("New firmware confirmed"),
+                Err(e) => warn!("Failed to confirm the new firmware: {}", e),
+            },
+            Ok(_) => {}
+            Err(e) => warn!("Failed to read the bootloader state: {}", e),
+        }
+        self.booted = true;
+    }
+
+    /// Handle an OTA message, returning the reply, if any.
+    ///
+    /// Once an update is verified, the device restarts into it right after the reply is sent, see
+    /// [`Updater::restart_if_updated`].
+    pub fn handle(&mut self, msg: Message) -> Option<Message> {
+        match msg {
+            Message::OtaStart { len, .. } => {
+                self.ota.start(len, &mut self.dfu).ok()?;
+
+                // ACK
+                Some(msg)
+            }
+            Message::OtaFragment { index, fragment } => {
+                if let Err(e) = self.ota.fragment(index, &fragment, &mut self.dfu) {
+                    warn!("OTA fragment {} dropped: {}", index, e);
+                }
+
+                None
+            }
+            Message::OtaStatus => {
+                let (first, bitmap) = self.ota.missing()?;
+
+                Some(Message::OtaMissing { first, bitmap })
+            }
+            Message::OtaFinish => {
+                let len = self.ota.finish(&mut self.dfu, &OTA_PUBLIC_KEY).ok()?;
+                if let Err(e) = self.state.mark_updated() {
+                    warn!("Failed to mark the update of {} bytes: {}", len, e);
+                    return None;
+                }
+                self.updated = true;
+
+                // ACK
+                Some(msg)
+            }
+            _ => None,
+        }
+    }
+
+    /// Restart into the new firmware, if an update was just verified.
+    pub fn restart_if_updated(&self) {
+        if self.updated {
+            warn!("Restarting into the new firmware");
+            SCB::sys_reset();
+        }
+    }
+}
+
+/// Key the updates are verified with, from the file given by `OTA_PUBLIC_KEY` at build time. See
+/// `lora2traffic-ota`.
+pub const OTA_PUBLIC_KEY: [u8; 32] = *include_bytes!(concat!(env!("OUT_DIR"), "/ota.pub"));
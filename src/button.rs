@@ -0,0 +1,65 @@
+use defmt::info;
+use embassy_futures::select::{select, Either};
+use embassy_stm32::exti::ExtiInput;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::{Gesture, GestureDetector};
+
+/// A push button, pulling its (pulled up) input low while pressed.
+pub struct Button<'d> {
+    input: ExtiInput<'d>,
+    gestures: GestureDetector,
+}
+
+impl<'d> Button<'d> {
+    pub fn new(input: ExtiInput<'d>) -> Self {
+        Self {
+            input,
+            gestures: GestureDetector::new(),
+        }
+    }
+
+    /// Wait for the next gesture.
+    ///
+    /// Safe to cancel: a gesture in progress carries on in the next call.
+    pub async fn wait_for_gesture(&mut self) -> Gesture {
+        // Catch up on any timeout, then edge, missed while not waiting.
+        let now = Instant::now().as_millis();
+        if let Some(gesture) = self.gestures.timeout(now) {
+            return log(gesture);
+        }
+        if let Some(gesture) = self.gestures.edge(self.input.is_low(), now) {
+            return log(gesture);
+        }
+
+        loop {
+            let deadline = self
+                .gestures
+                .deadline_ms()
+                .map_or(Instant::MAX, Instant::from_millis);
+            match select(self.input.wait_for_any_edge(), Timer::at(deadline)).await {
+                Either::First(()) => {
+                    let at = Instant::now().as_millis();
+                    Timer::after(DEBOUNCE_TIME).await;
+                    if let Some(gesture) = self.gestures.edge(self.input.is_low(), at) {
+                        return log(gesture);
+                    }
+                }
+                Either::Second(()) => {
+                    let now = Instant::now().as_millis();
+                    if let Some(gesture) = self.gestures.timeout(now) {
+                        return log(gesture);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn log(gesture: Gesture) -> Gesture {
+    info!("Button gesture = {:?}", gesture);
+
+    gesture
+}
+
+const DEBOUNCE_TIME: Duration = Duration::from_millis(50);
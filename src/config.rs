@@ -0,0 +1,132 @@
+use defmt::warn;
+
+use crate::{Address, ConfigStore, Storage, StoreError, MAX_VALUE_LEN};
+
+/// Settings of a node, kept in the [`ConfigStore`].
+///
+/// Any setting missing from the store, or unreadable, has its compiled-in default.
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub struct Config {
+    pub address: Address,
+    pub frequency_hz: u32,
+    pub tx_power_dbm: i8,
+    /// How long the receiver waits for the controller, before it starts flashing. 0 disables it.
+    pub failsafe_timeout_s: u16,
+    /// Red and green durations, in fixed-time mode.
+    pub red_s: u16,
+    pub green_s: u16,
+}
+
+impl Config {
+    pub fn load<S: Storage>(store: &mut ConfigStore<S>, defaults: Self) -> Self {
+        let mut config = defaults;
+        for key in ConfigKey::ALL {
+            let mut buf = [0; MAX_VALUE_LEN];
+            let value = store
+                .get(key as u8, CONFIG_VERSION, &mut buf)
+                .and_then(|value| value.try_into().ok())
+                .map(u32::from_le_bytes);
+            if let Some(value) = value {
+                match config.with(key, value) {
+                    Some(c) => config = c,
+                    None => warn!("Invalid stored {:?} = {}", key, value),
+                }
+            }
+        }
+        if config != defaults {
+            warn!("Using stored config = {:?}", config);
+        }
+
+        config
+    }
+
+    pub fn save<S: Storage>(&self, store: &mut ConfigStore<S>) -> Result<(), StoreError> {
+        for key in ConfigKey::ALL {
+            store.set(key as u8, CONFIG_VERSION, &self.get(key).to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// The value of the `key` setting, as sent over the air.
+    pub fn get(&self, key: ConfigKey) -> u32 {
+        match key {
+            ConfigKey::Address => self.address.0 as u32,
+            ConfigKey::Frequency => self.frequency_hz,
+            ConfigKey::TxPower => self.tx_power_dbm as i32 as u32,
+            ConfigKey::FailsafeTimeout => self.failsafe_timeout_s as u32,
+            ConfigKey::RedTime => self.red_s as u32,
+            ConfigKey::GreenTime => self.green_s as u32,
+        }
+    }
+
+    /// This config with `key` set to `value`, if it's a valid value.
+    pub fn with(&self, key: ConfigKey, value: u32) -> Option<Self> {
+        let mut config = *self;
+        match key {
+            ConfigKey::Address => {
+                config.address = Address(u8::try_from(value).ok()?);
+                if config.address == Address::BROADCAST {
+                    return None;
+                }
+            }
+            ConfigKey::Frequency => {
+                // The EU 433 MHz band.
+                if !(433_050_000..=434_790_000).contains(&value) {
+                    return None;
+                }
+                config.frequency_hz = value;
+            }
+            ConfigKey::TxPower => {
+                config.tx_power_dbm = i8::try_from(value as i32).ok()?;
+                if !(-9..=22).contains(&config.tx_power_dbm) {
+                    return None;
+                }
+            }
+            ConfigKey::FailsafeTimeout => {
+                config.failsafe_timeout_s = u16::try_from(value).ok()?;
+            }
+            ConfigKey::RedTime | ConfigKey::GreenTime => {
+                let duration = u16::try_from(value)
+                    .ok()
+                    .filter(|d| (5..=300).contains(d))?;
+                match key {
+                    ConfigKey::RedTime => config.red_s = duration,
+                    _ => config.green_s = duration,
+                }
+            }
+        }
+
+        Some(config)
+    }
+}
+
+/// The settings, both as store keys and parameter IDs in the protocol.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum ConfigKey {
+    Address = 1,
+    Frequency = 2,
+    TxPower = 3,
+    FailsafeTimeout = 4,
+    RedTime = 5,
+    GreenTime = 6,
+}
+
+impl ConfigKey {
+    pub const ALL: [Self; 6] = [
+        Self::Address,
+        Self::Frequency,
+        Self::TxPower,
+        Self::FailsafeTimeout,
+        Self::RedTime,
+        Self::GreenTime,
+    ];
+
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|key| *key as u8 == byte)
+    }
+}
+
+/// Version of the format of the settings, to bump whenever that changes.
+const CONFIG_VERSION: u8 = 1;
@@ -0,0 +1,716 @@
+use defmt::{info, warn};
+
+use crate::{
+    Actuation, Approach, Coordination, PedestrianSignal, Recall, Signal, TransitPriority,
+    VehicleClass,
+};
+
+#[derive(defmt::Format, Clone, Copy)]
+pub enum ControlMode {
+    /// Every phase lasts its [`Signal::duration`], or the durations set by
+    /// [`Controller::set_timing`].
+    FixedTime,
+    Coordinated(Coordination),
+    Actuated(Actuation),
+}
+
+/// Whether the controller runs on its own or an operator (e.g the police) is in control.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Default)]
+#[repr(u8)]
+pub enum OperatingMode {
+    #[default]
+    Automatic = b'a',
+    /// Red and green are held until the operator steps on to the next phase.
+    Manual = b'm',
+    /// The lights flash yellow, until the operator switches back.
+    Flash = b'f',
+}
+
+impl OperatingMode {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            b'a' => Some(Self::Automatic),
+            b'm' => Some(Self::Manual),
+            b'f' => Some(Self::Flash),
+            _ => None,
+        }
+    }
+}
+
+/// The phase logic of the controller.
+///
+/// All `uptime_ms` arguments are milliseconds since boot, while `time_ms` arguments are the
+/// synchronized time (milliseconds since the UNIX epoch), if it's good enough to coordinate with.
+pub struct Controller {
+    mode: ControlMode,
+    priority: Option<TransitPriority>,
+    signal: Signal,
+    phase_start_ms: u64,
+    /// The phase can't end before this, whatever the mode.
+    min_end_ms: u64,
+    /// `u64::MAX` while resting in an actuated phase.
+    phase_end_ms: u64,
+    pedestrian_call: bool,
+    pedestrian_signal: PedestrianSignal,
+    /// When the pedestrian signal changes next, unless it's don't walk.
+    pedestrian_change_ms: u64,
+    /// Occupancy of the detectors, as a bit mask indexed by detector number.
+    occupied: u32,
+    /// When the last vehicle left the detectors of the current phase.
+    gap_start_ms: u64,
+    /// When green was called, while it's waiting to be served.
+    green_call_ms: Option<u64>,
+    /// When red was called, while it's waiting to be served.
+    red_call_ms: Option<u64>,
+    preemption: Option<Preemption>,
+    /// How much the current phase was extended for transit priority.
+    extension_ms: u64,
+    /// How much the current phase was cut short for transit priority.
+    reduction_ms: u64,
+    /// When manual control times out, unless the operator does something before.
+    manual_until_ms: Option<u64>,
+    flash: bool,
+    red_ms: u64,
+    green_ms: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Preemption {
+    approach: Approach,
+    /// Renewed by every request, so the phase isn't held forever if the vehicle never releases it.
+    hold_until_ms: u64,
+}
+
+impl Controller {
+    pub fn new(mode: ControlMode, priority: Option<TransitPriority>) -> Self {
+        Self {
+            mode,
+            priority,
+            signal: Signal::default(),
+            phase_start_ms: 0,
+            min_end_ms: 0,
+            phase_end_ms: 0,
+            pedestrian_call: false,
+            pedestrian_signal: PedestrianSignal::DontWalk,
+            pedestrian_change_ms: 0,
+            occupied: 0,
+            gap_start_ms: 0,
+            green_call_ms: None,
+            red_call_ms: None,
+            preemption: None,
+            extension_ms: 0,
+            reduction_ms: 0,
+            manual_until_ms: None,
+            flash: false,
+            red_ms: Signal::Red.duration() * 1000,
+            green_ms: Signal::Green.duration() * 1000,
+        }
+    }
+
+    pub fn mode(&self) -> ControlMode {
+        self.mode
+    }
+
+    pub fn coordination(&self) -> Option<Coordination> {
+        match self.mode {
+            ControlMode::Coordinated(coordination) => Some(coordination),
+            _ => None,
+        }
+    }
+
+    /// Set the red and green durations, from the next phase on.
+    pub fn set_timing(&mut self, red_s: u16, green_s: u16) {
+        self.red_ms = red_s as u64 * 1000;
+        self.green_ms = green_s as u64 * 1000;
+    }
+
+    pub fn signal(&self) -> Signal {
+        self.signal
+    }
+
+    /// When the current phase ends.
+    pub fn phase_end_ms(&self) -> u64 {
+        self.phase_end_ms
+    }
+
+    pub fn phase_over(&self, uptime_ms: u64) -> bool {
+        uptime_ms >= self.phase_end_ms
+    }
+
+    /// When something needs to happen next: the phase ends or the pedestrian signal changes.
+    pub fn next_event_ms(&self) -> u64 {
+        match self.pedestrian_signal {
+            PedestrianSignal::DontWalk => self.phase_end_ms,
+            _ => self.phase_end_ms.min(self.pedestrian_change_ms),
+        }
+    }
+
+    /// Start a phase showing `signal`.
+    pub fn start(&mut self, signal: Signal, uptime_ms: u64, time_ms: Option<u64>) {
+        if self.manual_until_ms.is_some_and(|until| uptime_ms >= until) {
+            warn!("Manual control timed out, returning to automatic operation");
+            self.manual_until_ms = None;
+        }
+        let duration = match (self.mode, time_ms) {
+            (ControlMode::Coordinated(coordination), Some(time)) => {
+                coordination.duration_ms(signal, time)
+            }
+            _ => self.duration_ms(signal),
+        };
+        self.signal = signal;
+        self.phase_start_ms = uptime_ms;
+        self.min_end_ms = uptime_ms;
+        self.phase_end_ms = uptime_ms + duration;
+        self.extension_ms = 0;
+        self.reduction_ms = 0;
+        self.pedestrian_signal = PedestrianSignal::DontWalk;
+
+        if let Some(preemption) = self.preemption {
+            // Only the clearance phases and the pre-empted phase run, all else waits.
+            if signal == preemption.approach.phase() {
+                info!("Holding {:?} for pre-emption", signal);
+                self.phase_end_ms = preemption.hold_until_ms;
+            } else {
+                self.phase_end_ms = uptime_ms + self.duration_ms(signal);
+            }
+            return;
+        }
+
+        if let ControlMode::Actuated(actuation) = self.mode {
+            if let Some(timing) = actuation.timing(signal) {
+                self.min_end_ms = uptime_ms + timing.min_s as u64 * 1000;
+            }
+            self.gap_start_ms = uptime_ms;
+            if let Some(call) = self.call_mut(signal) {
+                *call = None;
+            }
+            // Vehicles already waiting on the other phase's detectors call it right away.
+            for (detector, &phase) in actuation.detectors.iter().enumerate() {
+                if phase != signal && self.is_occupied(detector) {
+                    self.call(phase, uptime_ms);
+                }
+            }
+        }
+
+        if signal == Signal::Red && self.pedestrian_call {
+            info!("Serving pedestrian call");
+            self.pedestrian_call = false;
+            self.pedestrian_signal = PedestrianSignal::Walk;
+            self.pedestrian_change_ms = uptime_ms + WALK_MS;
+            // Pedestrians must be done crossing before the red phase ends.
+            let min_end = uptime_ms + WALK_MS + PEDESTRIAN_CLEARANCE_MS + PEDESTRIAN_BUFFER_MS;
+            self.min_end_ms = self.min_end_ms.max(min_end);
+        }
+        self.phase_end_ms = self.phase_end_ms.max(self.min_end_ms);
+        self.update_actuated();
+
+        if let (Some(until), Signal::Red | Signal::Green) = (self.manual_until_ms, signal) {
+            // Held until the operator steps on.
+            self.phase_end_ms = until.max(self.min_end_ms);
+        }
+    }
+
+    /// Move on to the next phase.
+    pub fn advance(&mut self, uptime_ms: u64, time_ms: Option<u64>) -> Signal {
+        let mut signal = self.signal;
+        if let Some(preemption) = self.preemption {
+            if signal == preemption.approach.phase() {
+                warn!(
+                    "Pre-emption for {:?} approach timed out, returning to normal operation",
+                    preemption.approach
+                );
+                self.preemption = None;
+            }
+        }
+        signal.rotate();
+        if let Some(preemption) = self.preemption {
+            // Skip the conflicting phase, but never the clearance phases.
+            if matches!(signal, Signal::Red | Signal::Green)
+                && signal != preemption.approach.phase()
+            {
+                signal.rotate();
+            }
+        }
+        self.start(signal, uptime_ms, time_ms);
+
+        signal
+    }
+
+    /// Whether the current phase can be cut short (e.g by the operator).
+    pub fn can_skip(&self) -> bool {
+        self.pedestrian_signal == PedestrianSignal::DontWalk
+            && self.preemption.is_none()
+            && !self.flash
+    }
+
+    /// The operator wants to move on to the next phase. Returns whether the phase can be skipped.
+    pub fn skip(&mut self, uptime_ms: u64) -> bool {
+        if let Some(until) = &mut self.manual_until_ms {
+            *until = uptime_ms + MANUAL_TIMEOUT_MS;
+        }
+
+        self.can_skip()
+    }
+
+    pub fn operating_mode(&self) -> OperatingMode {
+        match (self.flash, self.manual_until_ms) {
+            (true, _) => OperatingMode::Flash,
+            (false, Some(_)) => OperatingMode::Manual,
+            (false, None) => OperatingMode::Automatic,
+        }
+    }
+
+    pub fn set_operating_mode(&mut self, mode: OperatingMode, uptime_ms: u64) {
+        if mode == self.operating_mode() {
+            return;
+        }
+        info!("Switching to {:?} operation", mode);
+        let was_flashing = self.flash;
+        self.flash = mode == OperatingMode::Flash;
+        self.manual_until_ms =
+            (mode == OperatingMode::Manual).then_some(uptime_ms + MANUAL_TIMEOUT_MS);
+
+        if self.flash {
+            if let Some(preemption) = self.preemption.take() {
+                warn!(
+                    "Pre-emption for {:?} approach cancelled",
+                    preemption.approach
+                );
+            }
+            // Off leads to red, once the flashing is over.
+            self.signal = Signal::Off;
+            self.phase_end_ms = u64::MAX;
+            self.pedestrian_signal = PedestrianSignal::DontWalk;
+        } else if was_flashing {
+            self.phase_end_ms = uptime_ms;
+        } else if matches!(self.signal, Signal::Red | Signal::Green) && self.preemption.is_none() {
+            match self.manual_until_ms {
+                Some(until) => self.phase_end_ms = until.max(self.min_end_ms),
+                None => {
+                    // The held phase has lasted long enough.
+                    self.phase_end_ms = uptime_ms.max(self.min_end_ms);
+                    self.update_actuated();
+                }
+            }
+        }
+    }
+
+    /// Pre-empt the signal for an emergency vehicle coming from `approach`.
+    ///
+    /// The conflicting phase is cut short, once pedestrians are done crossing, and the phase
+    /// serving `approach` is then held until the pre-emption is released or no longer renewed.
+    pub fn preempt(&mut self, approach: Approach, uptime_ms: u64) {
+        if self.flash {
+            warn!(
+                "Pre-emption for {:?} approach ignored while flashing",
+                approach
+            );
+            return;
+        }
+        let hold_until_ms = uptime_ms + PREEMPT_HOLD_MS;
+        match &mut self.preemption {
+            Some(preemption) if preemption.approach == approach => {
+                preemption.hold_until_ms = hold_until_ms;
+            }
+            Some(preemption) => {
+                // First come, first served.
+                warn!(
+                    "Pre-emption for {:?} approach ignored, {:?} approach is being served",
+                    approach, preemption.approach
+                );
+                return;
+            }
+            None => {
+                info!("Pre-emption for {:?} approach started", approach);
+                self.preemption = Some(Preemption {
+                    approach,
+                    hold_until_ms,
+                });
+            }
+        }
+
+        let phase = approach.phase();
+        if self.signal == phase {
+            self.phase_end_ms = hold_until_ms.max(self.min_end_ms);
+        } else if matches!(self.signal, Signal::Red | Signal::Green) {
+            if self.pedestrian_signal == PedestrianSignal::Walk {
+                info!("Cutting the walk short for pre-emption");
+                self.pedestrian_signal = PedestrianSignal::FlashingDontWalk;
+                self.pedestrian_change_ms = uptime_ms + PEDESTRIAN_CLEARANCE_MS;
+            }
+            let clear_ms = match self.pedestrian_signal {
+                PedestrianSignal::DontWalk => uptime_ms,
+                _ => self.pedestrian_change_ms,
+            };
+            self.phase_end_ms = self.phase_end_ms.min(clear_ms);
+        }
+        // Yellow and off are clearance phases, they run their course.
+    }
+
+    /// The emergency vehicle from `approach` is through, return to normal operation.
+    pub fn release_preemption(&mut self, approach: Approach, uptime_ms: u64) {
+        match self.preemption {
+            Some(preemption) if preemption.approach == approach => {
+                info!("Pre-emption for {:?} approach released", approach);
+                self.preemption = None;
+                if self.signal == approach.phase() {
+                    self.phase_end_ms = uptime_ms.max(self.min_end_ms);
+                }
+            }
+            _ => warn!("No pre-emption for {:?} approach to release", approach),
+        }
+    }
+
+    /// A transit vehicle coming from `approach` reaches the stop line in `eta_s` seconds.
+    pub fn priority_request(
+        &mut self,
+        class: VehicleClass,
+        approach: Approach,
+        eta_s: u8,
+        uptime_ms: u64,
+    ) {
+        let Some(priority) = self.priority else {
+            return;
+        };
+        if !priority.classes.contains(&class) {
+            info!("No priority for {:?}", class);
+            return;
+        }
+        if self.overridden() {
+            info!("Ignoring priority request, automatic operation is overridden");
+            return;
+        }
+        let phase = approach.phase();
+        let arrival_ms = uptime_ms + eta_s as u64 * 1000;
+
+        if self.signal == phase {
+            // Green extension, unless the vehicle makes it anyway.
+            let end = arrival_ms + PRIORITY_MARGIN_MS;
+            if end <= self.phase_end_ms {
+                return;
+            }
+            let extension_ms = end - self.phase_end_ms;
+            if self.extension_ms + extension_ms > priority.max_extension_s as u64 * 1000 {
+                info!("{:?} arrives too late to extend {:?}", class, phase);
+                return;
+            }
+            info!(
+                "Extending {:?} by {}ms for {:?}",
+                phase, extension_ms, class
+            );
+            self.extension_ms += extension_ms;
+            self.min_end_ms = self.min_end_ms.max(end);
+            self.phase_end_ms = end;
+        } else if let ControlMode::Actuated(_) = self.mode {
+            // Early green, as soon as the current phase gaps out.
+            self.call(phase, uptime_ms);
+            self.update_actuated();
+        } else if matches!(self.signal, Signal::Red | Signal::Green) {
+            // Early green, so the vehicle's phase starts right as it arrives.
+            let mut clearance = self.signal;
+            clearance.rotate();
+            let reduction_ms =
+                (priority.max_reduction_s as u64 * 1000).saturating_sub(self.reduction_ms);
+            let end = arrival_ms
+                .saturating_sub(clearance.duration() * 1000)
+                .max(self.phase_end_ms.saturating_sub(reduction_ms))
+                .max(self.phase_start_ms + MIN_GREEN_MS)
+                .max(self.min_end_ms)
+                .max(uptime_ms);
+            if end < self.phase_end_ms {
+                info!(
+                    "Cutting {:?} short by {}ms for {:?}",
+                    self.signal,
+                    self.phase_end_ms - end,
+                    class
+                );
+                self.reduction_ms += self.phase_end_ms - end;
+                self.phase_end_ms = end;
+            }
+        }
+    }
+
+    /// The approach the signal is pre-empted for, if any.
+    pub fn preemption(&self) -> Option<Approach> {
+        self.preemption.map(|preemption| preemption.approach)
+    }
+
+    /// Register a pedestrian call, to be served in the next red phase.
+    pub fn pedestrian_call(&mut self, uptime_ms: u64) {
+        self.pedestrian_call = true;
+
+        match self.mode {
+            // Bring the pedestrian phase forward.
+            ControlMode::FixedTime if self.signal == Signal::Green && !self.overridden() => {
+                let earliest_end = (self.phase_start_ms + MIN_GREEN_MS).max(uptime_ms);
+                self.phase_end_ms = self.phase_end_ms.min(earliest_end);
+            }
+            ControlMode::Actuated(_) => {
+                self.call(Signal::Red, uptime_ms);
+                self.update_actuated();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn pedestrian_call_pending(&self) -> bool {
+        self.pedestrian_call
+    }
+
+    pub fn pedestrian_signal(&self) -> PedestrianSignal {
+        self.pedestrian_signal
+    }
+
+    /// Seconds left until don't walk, for countdown displays.
+    pub fn pedestrian_countdown_s(&self, uptime_ms: u64) -> u8 {
+        let dont_walk_ms = match self.pedestrian_signal {
+            PedestrianSignal::Walk => self.pedestrian_change_ms + PEDESTRIAN_CLEARANCE_MS,
+            PedestrianSignal::FlashingDontWalk => self.pedestrian_change_ms,
+            PedestrianSignal::DontWalk => return 0,
+        };
+
+        dont_walk_ms
+            .saturating_sub(uptime_ms)
+            .div_ceil(1000)
+            .min(u8::MAX as u64) as u8
+    }
+
+    /// Change the pedestrian signal, if it's due.
+    pub fn update_pedestrian(&mut self, uptime_ms: u64) {
+        if uptime_ms < self.pedestrian_change_ms {
+            return;
+        }
+
+        match self.pedestrian_signal {
+            PedestrianSignal::Walk => {
+                self.pedestrian_signal = PedestrianSignal::FlashingDontWalk;
+                self.pedestrian_change_ms = uptime_ms + PEDESTRIAN_CLEARANCE_MS;
+            }
+            PedestrianSignal::FlashingDontWalk => {
+                self.pedestrian_signal = PedestrianSignal::DontWalk;
+            }
+            PedestrianSignal::DontWalk => {}
+        }
+    }
+
+    /// A vehicle detector got occupied or vacated.
+    ///
+    /// Only used in actuated mode.
+    pub fn detection(&mut self, detector: u8, occupied: bool, uptime_ms: u64) {
+        let ControlMode::Actuated(actuation) = self.mode else {
+            return;
+        };
+        let (Some(&phase), Some(bit)) = (
+            actuation.detectors.get(detector as usize),
+            1u32.checked_shl(detector as u32),
+        ) else {
+            warn!("Unknown detector: {}", detector);
+            return;
+        };
+        if occupied {
+            self.occupied |= bit;
+        } else {
+            self.occupied &= !bit;
+        }
+
+        if phase == self.signal {
+            if !occupied && !self.occupied_for(&actuation, phase) {
+                self.gap_start_ms = uptime_ms;
+            }
+        } else if occupied {
+            self.call(phase, uptime_ms);
+        }
+        self.update_actuated();
+    }
+
+    /// Whether pre-emption or the operator decide when phases end.
+    fn overridden(&self) -> bool {
+        self.preemption.is_some() || self.manual_until_ms.is_some() || self.flash
+    }
+
+    fn duration_ms(&self, signal: Signal) -> u64 {
+        match signal {
+            Signal::Red => self.red_ms,
+            Signal::Green => self.green_ms,
+            _ => signal.duration() * 1000,
+        }
+    }
+
+    fn occupied_for(&self, actuation: &Actuation, phase: Signal) -> bool {
+        actuation
+            .detectors
+            .iter()
+            .enumerate()
+            .any(|(detector, &p)| p == phase && self.is_occupied(detector))
+    }
+
+    fn is_occupied(&self, detector: usize) -> bool {
+        1u32.checked_shl(detector as u32)
+            .is_some_and(|bit| self.occupied & bit != 0)
+    }
+
+    fn call_mut(&mut self, phase: Signal) -> Option<&mut Option<u64>> {
+        match phase {
+            Signal::Green => Some(&mut self.green_call_ms),
+            Signal::Red => Some(&mut self.red_call_ms),
+            Signal::Yellow | Signal::Off => None,
+        }
+    }
+
+    fn call(&mut self, phase: Signal, uptime_ms: u64) {
+        if let Some(call) = self.call_mut(phase) {
+            call.get_or_insert(uptime_ms);
+        }
+    }
+
+    /// Since when the phase after the current actuated one is wanted, if it is.
+    fn conflicting_demand_ms(&self, actuation: &Actuation) -> Option<u64> {
+        let (phase, call_ms) = match self.signal {
+            Signal::Green => (Signal::Red, self.red_call_ms),
+            Signal::Red => (Signal::Green, self.green_call_ms),
+            Signal::Yellow | Signal::Off => return None,
+        };
+        let recalled = actuation
+            .timing(phase)
+            .is_some_and(|timing| timing.recall != Recall::None);
+
+        if recalled {
+            Some(self.phase_start_ms)
+        } else {
+            call_ms
+        }
+    }
+
+    fn update_actuated(&mut self) {
+        let ControlMode::Actuated(actuation) = self.mode else {
+            return;
+        };
+        if self.overridden() {
+            return;
+        }
+        let Some(timing) = actuation.timing(self.signal) else {
+            return;
+        };
+        let Some(demand_ms) = self.conflicting_demand_ms(&actuation) else {
+            // Rest in the phase, until the other one is wanted.
+            self.phase_end_ms = u64::MAX;
+            return;
+        };
+
+        let max_end = demand_ms.max(self.phase_start_ms) + timing.max_s as u64 * 1000;
+        let end = if timing.recall == Recall::Max || self.occupied_for(&actuation, self.signal) {
+            max_end
+        } else {
+            let gap_end = self.gap_start_ms + timing.passage_s as u64 * 1000;
+
+            gap_end.min(max_end)
+        };
+        self.phase_end_ms = end.max(self.min_end_ms);
+    }
+}
+
+const MIN_GREEN_MS: u64 = 10_000;
+const WALK_MS: u64 = 7_000;
+const PEDESTRIAN_CLEARANCE_MS: u64 = 10_000;
+/// Time between the pedestrian clearance and the end of the red phase.
+const PEDESTRIAN_BUFFER_MS: u64 = 3_000;
+/// Time for a transit vehicle to clear the intersection, after reaching the stop line.
+const PRIORITY_MARGIN_MS: u64 = 5_000;
+/// How long a pre-empted phase is held after the last request.
+const PREEMPT_HOLD_MS: u64 = 30_000;
+/// Manual control ends after this long without the operator doing anything.
+const MANUAL_TIMEOUT_MS: u64 = 10 * 60_000;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIORITY: TransitPriority = TransitPriority {
+        classes: &[VehicleClass::Bus],
+        max_extension_s: 15,
+        max_reduction_s: 10,
+    };
+
+    /// A fixed time controller starting `signal` at 0, for 30s.
+    fn start(priority: TransitPriority, signal: Signal) -> Controller {
+        let mut controller = Controller::new(ControlMode::FixedTime, Some(priority));
+        controller.start(signal, 0, None);
+        assert_eq!(controller.phase_end_ms(), 30_000);
+
+        controller
+    }
+
+    #[test]
+    fn green_extension() {
+        let mut controller = start(PRIORITY, Signal::Green);
+        let bus = VehicleClass::Bus;
+        // Makes it anyway.
+        controller.priority_request(bus, Approach::Main, 20, 0);
+        assert_eq!(controller.phase_end_ms(), 30_000);
+        // No priority for trams.
+        controller.priority_request(VehicleClass::Tram, Approach::Main, 28, 0);
+        assert_eq!(controller.phase_end_ms(), 30_000);
+
+        controller.priority_request(bus, Approach::Main, 28, 0);
+        assert_eq!(controller.phase_end_ms(), 33_000);
+        // Up to the max extension, in total.
+        controller.priority_request(bus, Approach::Main, 35, 5_000);
+        assert_eq!(controller.phase_end_ms(), 45_000);
+        controller.priority_request(bus, Approach::Main, 40, 6_000);
+        assert_eq!(controller.phase_end_ms(), 45_000);
+
+        // The next phase starts with no extension.
+        controller.advance(45_000, None);
+        controller.advance(50_000, None);
+        controller.advance(65_000, None);
+        controller.advance(95_000, None);
+        assert_eq!(controller.signal(), Signal::Green);
+        controller.priority_request(bus, Approach::Main, 40, 95_000);
+        assert_eq!(controller.phase_end_ms(), 140_000);
+    }
+
+    #[test]
+    fn red_truncation() {
+        let mut controller = start(PRIORITY, Signal::Red);
+        // Green by the time the bus arrives, after yellow.
+        controller.priority_request(VehicleClass::Bus, Approach::Main, 27, 0);
+        assert_eq!(controller.phase_end_ms(), 22_000);
+        // Up to the max reduction, in total.
+        controller.priority_request(VehicleClass::Bus, Approach::Main, 10, 1_000);
+        assert_eq!(controller.phase_end_ms(), 20_000);
+        // Never longer.
+        controller.priority_request(VehicleClass::Bus, Approach::Main, 60, 0);
+        assert_eq!(controller.phase_end_ms(), 20_000);
+    }
+
+    #[test]
+    fn green_truncation() {
+        let mut controller = start(PRIORITY, Signal::Green);
+        // Green is cleared by off.
+        controller.priority_request(VehicleClass::Bus, Approach::Side, 30, 0);
+        assert_eq!(controller.phase_end_ms(), 20_000);
+    }
+
+    #[test]
+    fn min_green() {
+        let priority = TransitPriority {
+            max_reduction_s: 30,
+            ..PRIORITY
+        };
+        let mut controller = start(priority, Signal::Red);
+        controller.priority_request(VehicleClass::Bus, Approach::Main, 0, 2_000);
+        assert_eq!(controller.phase_end_ms(), MIN_GREEN_MS);
+        // Past the minimum, right away.
+        let mut controller = start(priority, Signal::Green);
+        controller.priority_request(VehicleClass::Bus, Approach::Side, 0, 12_000);
+        assert_eq!(controller.phase_end_ms(), 12_000);
+    }
+
+    #[test]
+    fn overridden() {
+        let mut controller = start(PRIORITY, Signal::Red);
+        controller.preempt(Approach::Side, 0);
+        controller.priority_request(VehicleClass::Bus, Approach::Main, 0, 0);
+        assert_eq!(controller.phase_end_ms(), PREEMPT_HOLD_MS);
+    }
+}
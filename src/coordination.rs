@@ -0,0 +1,81 @@
+use crate::Signal;
+
+/// Coordination of the signal cycle with other controllers along a road, to produce a green wave.
+///
+/// Coordinated controllers share a common cycle length and their cycles start at multiples of it
+/// since the UNIX epoch, according to the synchronized time, each shifted by its own offset.
+#[derive(defmt::Format, Clone, Copy)]
+pub struct Coordination {
+    pub role: CoordinationRole,
+    /// Must be longer than the fixed (yellow and off) phases.
+    pub cycle_length_s: u16,
+    /// Offset of this intersection's cycle from the common cycle.
+    pub offset_s: u16,
+}
+
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub enum CoordinationRole {
+    /// Provides the time to the other controllers.
+    Master,
+    /// Follows the time from the master.
+    Secondary,
+}
+
+impl Coordination {
+    /// Duration of `signal` starting at `time_ms`, that keeps the cycle in step.
+    ///
+    /// Yellow and off are never adjusted. Red and green are shortened or extended by up to half of
+    /// their split, so a controller that got out of step is back in step after a few cycles.
+    pub fn duration_ms(&self, signal: Signal, time_ms: u64) -> u64 {
+        let split = self.split_ms(signal);
+        if matches!(signal, Signal::Yellow | Signal::Off) {
+            return split;
+        }
+
+        let cycle = self.cycle_ms() as i64;
+        let mut late = (self.position_ms(time_ms) as i64 - self.start_ms(signal) as i64) % cycle;
+        if late > cycle / 2 {
+            late -= cycle;
+        } else if late < -cycle / 2 {
+            late += cycle;
+        }
+        let split = split as i64;
+
+        (split - late).clamp(split / 2, split + split / 2) as u64
+    }
+
+    fn cycle_ms(&self) -> u64 {
+        self.cycle_length_s as u64 * 1000
+    }
+
+    fn position_ms(&self, time_ms: u64) -> u64 {
+        let cycle = self.cycle_ms();
+
+        (time_ms % cycle + cycle - self.offset_s as u64 * 1000 % cycle) % cycle
+    }
+
+    fn split_ms(&self, signal: Signal) -> u64 {
+        // Red and green share what the fixed phases leave of the cycle.
+        let shared = self
+            .cycle_ms()
+            .saturating_sub((Signal::Yellow.duration() + Signal::Off.duration()) * 1000);
+
+        match signal {
+            Signal::Red => shared / 2,
+            Signal::Green => shared - shared / 2,
+            Signal::Yellow | Signal::Off => signal.duration() * 1000,
+        }
+    }
+
+    fn start_ms(&self, signal: Signal) -> u64 {
+        // The cycle starts with red.
+        let mut start = 0;
+        let mut s = Signal::Red;
+        while s != signal {
+            start += self.split_ms(s);
+            s.rotate();
+        }
+
+        start
+    }
+}
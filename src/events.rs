@@ -0,0 +1,392 @@
+use crc::{Crc, CRC_32_ISO_HDLC};
+use defmt::{info, warn};
+use heapless::Deque;
+
+use crate::{
+    Address, Approach, Faults, LinkState, OperatingMode, ResetCause, Signal, Storage, StoreError,
+};
+
+/// Something that happened to a node, see [`EventLog`].
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    /// Numbers the events, across resets.
+    pub seq: u32,
+    /// Seconds since the UNIX epoch, as far as the node knows.
+    pub time_s: u32,
+    pub kind: EventKind,
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    Reset(ResetCause),
+    /// A phase change, to this signal.
+    Signal(Signal),
+    OperatingMode(OperatingMode),
+    /// The faults changed, to these.
+    Faults(Faults),
+    Preempt {
+        approach: Approach,
+        active: bool,
+    },
+    /// The link with `peer` changed state, e.g it was lost.
+    Link {
+        peer: Address,
+        state: LinkState,
+    },
+}
+
+impl EventKind {
+    /// Phase changes come every few seconds, and would wear the flash out within a year.
+    fn is_persistent(&self) -> bool {
+        !matches!(self, Self::Signal(_))
+    }
+}
+
+impl Event {
+    pub fn to_bytes(&self) -> [u8; EVENT_LEN] {
+        let (kind, args) = match self.kind {
+            EventKind::Reset(cause) => (0, [cause as u8, 0]),
+            EventKind::Signal(signal) => (1, [signal as u8, 0]),
+            EventKind::OperatingMode(mode) => (2, [mode as u8, 0]),
+            EventKind::Faults(faults) => (3, faults.0.to_le_bytes()),
+            EventKind::Preempt { approach, active } => (4, [approach as u8, active as u8]),
+            EventKind::Link { peer, state } => (5, [state as u8, peer.0]),
+        };
+        let mut bytes = [0; EVENT_LEN];
+        bytes[..4].copy_from_slice(&self.seq.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.time_s.to_le_bytes());
+        bytes[8] = kind;
+        bytes[9..].copy_from_slice(&args);
+
+        bytes
+    }
+
+    /// `None` for an unknown kind of event, or invalid arguments.
+    pub fn from_bytes(bytes: &[u8; EVENT_LEN]) -> Option<Self> {
+        let [s0, s1, s2, s3, t0, t1, t2, t3, kind, a, b] = *bytes;
+        let kind = match kind {
+            0 => EventKind::Reset(ResetCause::from_u8(a)?),
+            1 => EventKind::Signal(Signal::from_u8(a)?),
+            2 => EventKind::OperatingMode(OperatingMode::from_u8(a)?),
+            3 => EventKind::Faults(Faults(u16::from_le_bytes([a, b]))),
+            4 => EventKind::Preempt {
+                approach: Approach::from_u8(a)?,
+                active: match b {
+                    0 => false,
+                    1 => true,
+                    _ => return None,
+                },
+            },
+            5 => EventKind::Link {
+                peer: Address(b),
+                state: LinkState::from_u8(a)?,
+            },
+            _ => return None,
+        };
+
+        Some(Self {
+            seq: u32::from_le_bytes([s0, s1, s2, s3]),
+            time_s: u32::from_le_bytes([t0, t1, t2, t3]),
+            kind,
+        })
+    }
+}
+
+/// Consecutive events, see [`EventLog::page`].
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct EventPage {
+    len: u8,
+    events: [Event; EVENTS_PER_PAGE],
+}
+
+impl EventPage {
+    pub const EMPTY: Self = Self {
+        len: 0,
+        events: [Event {
+            seq: 0,
+            time_s: 0,
+            kind: EventKind::Reset(ResetCause::Unknown),
+        }; EVENTS_PER_PAGE],
+    };
+
+    /// Returns the event back if the page is full.
+    pub fn push(&mut self, event: Event) -> Result<(), Event> {
+        let slot = self.events.get_mut(self.len as usize).ok_or(event)?;
+        *slot = event;
+        self.len += 1;
+
+        Ok(())
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events[..self.len as usize]
+    }
+
+    /// Where the next page starts. A page that isn't full is the last one, for now.
+    pub fn next(&self) -> Option<u32> {
+        self.events().last().map(|event| event.seq.wrapping_add(1))
+    }
+}
+
+/// The latest `N` events of a node, kept in RAM and mirrored to flash, to survive resets.
+///
+/// The events are appended to one of the two [`Storage`] pages. Once it's full, the other one is
+/// erased and written to, so the older page keeps the events until then.
+pub struct EventLog<S, const N: usize> {
+    storage: S,
+    events: Deque<Event, N>,
+    next_seq: u32,
+    /// The page written to.
+    page: u32,
+    /// Where the next record goes, in the page.
+    end: u32,
+}
+
+impl<S: Storage, const N: usize> EventLog<S, N> {
+    pub fn new(mut storage: S) -> Self {
+        let mut latest = [None, None];
+        for (page, latest) in latest.iter_mut().enumerate() {
+            read_page(&mut storage, page as u32, |event| *latest = Some(event.seq));
+        }
+        let page = match latest {
+            [Some(a), Some(b)] if b > a => 1,
+            [None, Some(_)] => 1,
+            _ => 0,
+        };
+
+        let mut log = Self {
+            storage,
+            events: Deque::new(),
+            next_seq: 0,
+            page,
+            end: 0,
+        };
+        for page in [1 - page, page] {
+            let mut events = core::mem::take(&mut log.events);
+            log.end = read_page(&mut log.storage, page, |event| {
+                if events.is_full() {
+                    events.pop_front();
+                }
+                let _ = events.push_back(event);
+            });
+            log.events = events;
+        }
+        log.next_seq = latest
+            .into_iter()
+            .flatten()
+            .max()
+            .map_or(0, |seq| seq.wrapping_add(1));
+        info!("Event log loaded, next event is {}", log.next_seq);
+
+        log
+    }
+
+    /// Log an event of `kind`, that happened at `time_s`.
+    pub fn log(&mut self, time_s: u32, kind: EventKind) -> Event {
+        let event = Event {
+            seq: self.next_seq,
+            time_s,
+            kind,
+        };
+        self.next_seq = self.next_seq.wrapping_add(1);
+        info!("Event = {:?}", event);
+
+        if self.events.is_full() {
+            self.events.pop_front();
+        }
+        let _ = self.events.push_back(event);
+        if kind.is_persistent() {
+            if let Err(e) = self.write(&event) {
+                warn!("Failed to write event {} to flash: {}", event.seq, e);
+            }
+        }
+
+        event
+    }
+
+    /// Up to [`EVENTS_PER_PAGE`] events, from the one numbered `from` on, or the oldest one kept.
+    pub fn page(&self, from: u32) -> EventPage {
+        let mut page = EventPage::EMPTY;
+        for event in self.events.iter().filter(|event| event.seq >= from) {
+            if page.push(*event).is_err() {
+                break;
+            }
+        }
+
+        page
+    }
+
+    fn write(&mut self, event: &Event) -> Result<(), StoreError> {
+        if self.end + RECORD_LEN > S::PAGE_SIZE {
+            let to = 1 - self.page;
+            self.storage.erase_page(to)?;
+            self.page = to;
+            self.end = 0;
+        }
+
+        let mut record = [0; RECORD_LEN as usize];
+        record[..EVENT_LEN].copy_from_slice(&event.to_bytes());
+        let crc = CRC.checksum(&record[..CRC_OFFSET]);
+        record[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        let offset = self.page * S::PAGE_SIZE + self.end;
+        // Even if the write fails, the record may be half written.
+        self.end += RECORD_LEN;
+
+        self.storage.write(offset, &record)
+    }
+}
+
+/// Calls `f` with the events of `page`, in order, and returns where they end.
+fn read_page<S: Storage>(storage: &mut S, page: u32, mut f: impl FnMut(Event)) -> u32 {
+    let mut offset = 0;
+    while offset + RECORD_LEN <= S::PAGE_SIZE {
+        let mut record = [0; RECORD_LEN as usize];
+        if storage
+            .read(page * S::PAGE_SIZE + offset, &mut record)
+            .is_err()
+            || record == [ERASED; RECORD_LEN as usize]
+        {
+            break;
+        }
+
+        let (bytes, crc) = record.split_at(CRC_OFFSET);
+        let valid = CRC.checksum(bytes).to_le_bytes() == crc;
+        match bytes[..EVENT_LEN].try_into().ok().filter(|_| valid) {
+            Some(bytes) => match Event::from_bytes(bytes) {
+                Some(event) => f(event),
+                None => warn!("Unknown event at {} in page {}", offset, page),
+            },
+            None => warn!("Corrupt event at {} in page {}", offset, page),
+        }
+        offset += RECORD_LEN;
+    }
+
+    offset
+}
+
+/// Length of an [`Event`], on the wire.
+pub const EVENT_LEN: usize = 11;
+/// Events per [`crate::Message::Events`], which is then a few seconds on air at SF12.
+pub const EVENTS_PER_PAGE: usize = 4;
+/// An event, padding and its CRC, written in two double words.
+const RECORD_LEN: u32 = 16;
+const CRC_OFFSET: usize = 12;
+const ERASED: u8 = 0xff;
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Message, Packet};
+
+    /// Pages of 8 records.
+    type Mem = crate::MemStorage<256>;
+    const LEN: usize = 12;
+
+    fn faults(log: &mut EventLog<Mem, LEN>, count: u16) {
+        for i in 0..count {
+            log.log(1_000 + i as u32, EventKind::Faults(Faults(i)));
+        }
+    }
+
+    fn seqs(log: &EventLog<Mem, LEN>) -> Vec<u32> {
+        log.events.iter().map(|event| event.seq).collect()
+    }
+
+    fn reload(log: EventLog<Mem, LEN>) -> EventLog<Mem, LEN> {
+        EventLog::new(log.storage)
+    }
+
+    #[test]
+    fn round_trip() {
+        let kinds = [
+            EventKind::Reset(ResetCause::PowerOn),
+            EventKind::Signal(Signal::Yellow),
+            EventKind::OperatingMode(OperatingMode::Flash),
+            EventKind::Faults(Faults(0x0102)),
+            EventKind::Preempt {
+                approach: Approach::Side,
+                active: true,
+            },
+            EventKind::Link {
+                peer: Address(3),
+                state: LinkState::Lost,
+            },
+        ];
+        for kind in kinds {
+            let event = Event {
+                seq: 0x0102_0304,
+                time_s: 1_700_000_000,
+                kind,
+            };
+            assert_eq!(Event::from_bytes(&event.to_bytes()), Some(event));
+        }
+    }
+
+    #[test]
+    fn wraparound() {
+        let mut log = EventLog::new(Mem::new());
+        // Through both pages, and back to the first one.
+        faults(&mut log, 20);
+        assert_eq!(log.page, 0);
+        assert_eq!(seqs(&log), (8..20).collect::<Vec<_>>());
+
+        let mut log = reload(log);
+        assert_eq!(seqs(&log), (8..20).collect::<Vec<_>>());
+        assert_eq!(log.log(0, EventKind::Faults(Faults(0))).seq, 20);
+        assert_eq!(seqs(&reload(log)), (9..21).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn compaction() {
+        let mut log = EventLog::new(Mem::new());
+        faults(&mut log, 8);
+        // Phase changes stay in RAM.
+        log.log(0, EventKind::Signal(Signal::Green));
+        assert_eq!(seqs(&log), (0..9).collect::<Vec<_>>());
+
+        // The first page is full, so the next event erases the second one, keeping the first.
+        faults(&mut log, 1);
+        assert_eq!((log.page, log.end), (1, RECORD_LEN));
+        let mut log = reload(log);
+        assert_eq!(seqs(&log), [0, 1, 2, 3, 4, 5, 6, 7, 9]);
+
+        // Half written, e.g on a reset: skipped, and written after.
+        log.storage.bytes_mut()[128 + 16] = 0;
+        let mut log = reload(log);
+        assert_eq!(seqs(&log), [0, 1, 2, 3, 4, 5, 6, 7, 9]);
+        assert_eq!(log.log(0, EventKind::Faults(Faults(0))).seq, 10);
+        assert_eq!(seqs(&reload(log)), [0, 1, 2, 3, 4, 5, 6, 7, 9, 10]);
+    }
+
+    #[test]
+    fn get_events() {
+        let mut log = EventLog::new(Mem::new());
+        faults(&mut log, 20);
+
+        // As the CLI pages through the log of a node.
+        let mut from = 0;
+        let mut events = Vec::new();
+        loop {
+            let packet = Packet {
+                src: Address(2),
+                dst: Address(1),
+                seq: None,
+                ttl: 0,
+                msg: Message::Events(log.page(from)),
+            };
+            let Message::Events(page) = Packet::from_bytes(&packet.to_bytes()).unwrap().msg else {
+                panic!("not an event page");
+            };
+            events.extend(page.events().iter().map(|event| event.seq));
+            match page.next() {
+                Some(next) if page.events().len() == EVENTS_PER_PAGE => from = next,
+                _ => break,
+            }
+        }
+        assert_eq!(events, (8..20).collect::<Vec<_>>());
+
+        assert_eq!(log.page(18).events().len(), 2);
+        assert_eq!(log.page(20).next(), None);
+    }
+}
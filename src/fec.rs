@@ -0,0 +1,61 @@
+use core::ops::Range;
+
+/// Forward error correction of a payload cut into fragments, by XOR parity.
+///
+/// Every `group_len` data fragments are protected by a parity fragment, their XOR, so that any one
+/// of them lost is recovered from the others and the parity. The parity fragments come after all
+/// the data fragments. Used by both [`crate::Fragmenter`] and [`crate::OtaImage`].
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct Fec {
+    pub data_fragments: u16,
+    /// 0 for no parity.
+    pub group_len: u16,
+}
+
+impl Fec {
+    /// Number of fragments, parity included.
+    pub fn fragments(&self) -> u16 {
+        self.data_fragments + self.parity_fragments()
+    }
+
+    pub fn parity_fragments(&self) -> u16 {
+        match self.group_len {
+            0 => 0,
+            len => self.data_fragments.div_ceil(len),
+        }
+    }
+
+    /// The group of the parity fragment `index`, if it's one.
+    pub fn parity_group(&self, index: u16) -> Option<u16> {
+        index
+            .checked_sub(self.data_fragments)
+            .filter(|group| *group < self.parity_fragments())
+    }
+
+    /// The data fragments protected by the parity fragment of `group`.
+    pub fn group(&self, group: u16) -> Range<u16> {
+        let start = group.saturating_mul(self.group_len);
+
+        start
+            ..self
+                .data_fragments
+                .min(start.saturating_add(self.group_len))
+    }
+
+    /// The parity fragment of `group`, from its data fragments.
+    pub fn parity<const N: usize>(&self, group: u16, data: impl Fn(u16) -> [u8; N]) -> [u8; N] {
+        let mut parity = [0; N];
+        for i in self.group(group) {
+            xor(&mut parity, &data(i));
+        }
+
+        parity
+    }
+}
+
+/// XOR `other` into `data`.
+pub(crate) fn xor(data: &mut [u8], other: &[u8]) {
+    for (a, b) in data.iter_mut().zip(other) {
+        *a ^= b;
+    }
+}
@@ -0,0 +1,66 @@
+use core::cell::RefCell;
+
+use defmt::{warn, Debug2Format};
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_stm32::flash::{Blocking, Flash, FLASH_SIZE};
+use embassy_sync::blocking_mutex::{raw::NoopRawMutex, Mutex};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+use crate::{Storage, StoreError};
+
+/// The internal flash, shared by the config store, the event log and the firmware updates.
+pub type SharedFlash<'d> = Mutex<NoopRawMutex, RefCell<Flash<'d, Blocking>>>;
+pub type FlashPartition<'a, 'd> = BlockingPartition<'a, NoopRawMutex, Flash<'d, Blocking>>;
+
+/// [`Storage`] in two pages at the end of the internal flash, the last ones for the config store
+/// and the ones before for the event log.
+///
+/// `memory.x` keeps the firmware and the bootloader partitions out of these pages.
+pub struct FlashStorage<'a, 'd> {
+    flash: FlashPartition<'a, 'd>,
+}
+
+impl<'a, 'd> FlashStorage<'a, 'd> {
+    pub fn new(flash: &'a SharedFlash<'d>) -> Self {
+        Self {
+            flash: BlockingPartition::new(flash, STORE_OFFSET, 2 * Self::PAGE_SIZE),
+        }
+    }
+
+    pub fn event_log(flash: &'a SharedFlash<'d>) -> Self {
+        Self {
+            flash: BlockingPartition::new(flash, EVENT_LOG_OFFSET, 2 * Self::PAGE_SIZE),
+        }
+    }
+}
+
+impl Storage for FlashStorage<'_, '_> {
+    const PAGE_SIZE: u32 = 2048;
+    // The STM32WL programs double words.
+    const WRITE_SIZE: u32 = 8;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), StoreError> {
+        self.flash.read(offset, bytes).map_err(|e| {
+            warn!("Flash read failed: {}", Debug2Format(&e));
+            StoreError::Storage
+        })
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), StoreError> {
+        self.flash.write(offset, bytes).map_err(|e| {
+            warn!("Flash write failed: {}", Debug2Format(&e));
+            StoreError::Storage
+        })
+    }
+
+    fn erase_page(&mut self, page: u32) -> Result<(), StoreError> {
+        let from = page * Self::PAGE_SIZE;
+        self.flash.erase(from, from + Self::PAGE_SIZE).map_err(|e| {
+            warn!("Flash erase failed: {}", Debug2Format(&e));
+            StoreError::Storage
+        })
+    }
+}
+
+const STORE_OFFSET: u32 = FLASH_SIZE as u32 - 2 * <FlashStorage as Storage>::PAGE_SIZE;
+const EVENT_LOG_OFFSET: u32 = STORE_OFFSET - 2 * <FlashStorage as Storage>::PAGE_SIZE;
@@ -0,0 +1,416 @@
+use defmt::{info, warn};
+
+use crate::{xor, Address, Fec};
+
+/// A piece of a payload too large for a frame, see [`Fragmenter`] and [`Reassembler`].
+#[derive(defmt::Format, Debug, Clone, Copy)]
+pub struct Fragment {
+    /// Tells the transfers from a node apart.
+    pub transfer: u8,
+    /// The data fragments come first, then the parity fragments.
+    pub index: u8,
+    /// Length of the payload.
+    pub len: u16,
+    /// Data fragments per parity fragment, 0 for none.
+    pub fec_group: u8,
+    pub data: [u8; FRAGMENT_DATA_LEN],
+}
+
+/// Cuts a payload into [`Fragment`]s.
+///
+/// With FEC, every `fec_group` data fragments are followed by a parity fragment (their XOR), so
+/// that any one fragment lost in a group is recovered by the [`Reassembler`].
+pub struct Fragmenter<'a> {
+    transfer: u8,
+    payload: &'a [u8],
+    fec_group: u8,
+}
+
+impl<'a> Fragmenter<'a> {
+    /// `None` if the payload is empty or needs more than 255 fragments.
+    pub fn new(transfer: u8, payload: &'a [u8], fec_group: u8) -> Option<Self> {
+        let len = u16::try_from(payload.len()).ok()?;
+        fragments(len, fec_group)?;
+
+        Some(Self {
+            transfer,
+            payload,
+            fec_group,
+        })
+    }
+
+    /// Number of fragments, parity included.
+    pub fn fragments(&self) -> u8 {
+        fragments(self.payload.len() as u16, self.fec_group).unwrap_or(0)
+    }
+
+    pub fn fragment(&self, index: u8) -> Fragment {
+        let fec = fec(self.payload.len() as u16, self.fec_group);
+        let data = match fec.parity_group(index as u16) {
+            Some(group) => fec.parity(group, |i| self.data(i as u8)),
+            None => self.data(index),
+        };
+
+        Fragment {
+            transfer: self.transfer,
+            index,
+            len: self.payload.len() as u16,
+            fec_group: self.fec_group,
+            data,
+        }
+    }
+
+    fn data(&self, index: u8) -> [u8; FRAGMENT_DATA_LEN] {
+        let start = index as usize * FRAGMENT_DATA_LEN;
+        let end = self.payload.len().min(start + FRAGMENT_DATA_LEN);
+        let mut data = [0; FRAGMENT_DATA_LEN];
+        data[..end - start].copy_from_slice(&self.payload[start..end]);
+
+        data
+    }
+}
+
+/// Puts the payloads back together from their [`Fragment`]s, in any order, one transfer at a time.
+///
+/// Up to `N` bytes long payloads are received. A transfer that gets no fragment for the timeout is
+/// dropped, see [`Reassembler::expire`].
+pub struct Reassembler<const N: usize> {
+    buffer: [u8; N],
+    transfer: Option<Transfer>,
+    /// The last completed transfer, to ignore its late fragments.
+    completed: Option<(Address, u8)>,
+    timeout_ms: u64,
+}
+
+struct Transfer {
+    src: Address,
+    id: u8,
+    len: u16,
+    fec_group: u8,
+    received: Bitmap,
+    /// The groups with a parity fragment but more than one data fragment missing. Their parity,
+    /// XORed with the data fragments received, is kept in the slot of the first missing one.
+    parity: Bitmap,
+    deadline_ms: u64,
+}
+
+impl<const N: usize> Reassembler<N> {
+    pub fn new(timeout_ms: u64) -> Self {
+        Self {
+            buffer: [0; N],
+            transfer: None,
+            completed: None,
+            timeout_ms,
+        }
+    }
+
+    /// Add a fragment from `src`, returning the payload once complete.
+    ///
+    /// A fragment of another transfer drops the ongoing one.
+    pub fn receive(&mut self, src: Address, fragment: &Fragment, now_ms: u64) -> Option<&[u8]> {
+        if self.completed == Some((src, fragment.transfer)) {
+            return None;
+        }
+
+        let ongoing = self
+            .transfer
+            .as_ref()
+            .is_some_and(|t| t.src == src && t.id == fragment.transfer);
+        if !ongoing {
+            if let Some(t) = &self.transfer {
+                warn!("Transfer {} from {:?} dropped", t.id, t.src);
+            }
+            self.transfer = None;
+            let data_fragments = data_fragments(fragment.len) as usize;
+            if fragments(fragment.len, fragment.fec_group).is_none()
+                || data_fragments * FRAGMENT_DATA_LEN > N
+            {
+                warn!("Transfer of {} bytes from {:?} rejected", fragment.len, src);
+                return None;
+            }
+            self.transfer = Some(Transfer {
+                src,
+                id: fragment.transfer,
+                len: fragment.len,
+                fec_group: fragment.fec_group,
+                received: Bitmap::default(),
+                parity: Bitmap::default(),
+                deadline_ms: 0,
+            });
+        }
+
+        let transfer = self.transfer.as_mut()?;
+        if fragment.len != transfer.len || fragment.fec_group != transfer.fec_group {
+            warn!("Inconsistent fragment from {:?}", src);
+            return None;
+        }
+        transfer.deadline_ms = now_ms + self.timeout_ms;
+        let fec = transfer.fec();
+        if fragment.index < data_fragments(transfer.len) {
+            transfer.data(&mut self.buffer, fragment.index, &fragment.data);
+        } else if let Some(group) = fec.parity_group(fragment.index as u16) {
+            transfer.parity(&mut self.buffer, group as u8, &fragment.data);
+        } else {
+            warn!("Invalid fragment {} from {:?}", fragment.index, src);
+            return None;
+        }
+
+        if !transfer.is_complete() {
+            return None;
+        }
+        info!(
+            "Transfer {} of {} bytes from {:?} complete",
+            transfer.id, transfer.len, src
+        );
+        let len = transfer.len as usize;
+        self.completed = Some((src, transfer.id));
+        self.transfer = None;
+
+        Some(&self.buffer[..len])
+    }
+
+    /// When the ongoing transfer times out.
+    pub fn deadline_ms(&self) -> Option<u64> {
+        self.transfer.as_ref().map(|t| t.deadline_ms)
+    }
+
+    /// Drop the ongoing transfer, if it timed out. Returns whether it did.
+    pub fn expire(&mut self, now_ms: u64) -> bool {
+        match &self.transfer {
+            Some(t) if now_ms >= t.deadline_ms => {
+                warn!("Transfer {} from {:?} timed out", t.id, t.src);
+                self.transfer = None;
+
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Transfer {
+    fn data(&mut self, buffer: &mut [u8], index: u8, data: &[u8; FRAGMENT_DATA_LEN]) {
+        if self.received.get(index) {
+            return;
+        }
+
+        if self.fec_group == 0 || !self.parity.get(index / self.fec_group) {
+            slot(buffer, index).copy_from_slice(data);
+            self.received.set(index);
+            return;
+        }
+        let group = index / self.fec_group;
+
+        // Take this fragment out of the parity, which is then the XOR of the ones still missing.
+        let (holder, _) = self.missing_in(group);
+        let holder = holder.expect("parity kept for a complete group");
+        xor(slot(buffer, holder), data);
+        let remainder: [u8; FRAGMENT_DATA_LEN] = slot(buffer, holder).try_into().unwrap();
+        slot(buffer, index).copy_from_slice(data);
+        self.received.set(index);
+
+        let (Some(next), last) = self.missing_in(group) else {
+            return;
+        };
+        slot(buffer, next).copy_from_slice(&remainder);
+        if last.is_none() {
+            self.received.set(next);
+            self.parity.clear(group);
+            info!("Fragment {} recovered from parity", next);
+        }
+    }
+
+    fn parity(&mut self, buffer: &mut [u8], group: u8, parity: &[u8; FRAGMENT_DATA_LEN]) {
+        if self.parity.get(group) {
+            return;
+        }
+
+        let (Some(first), second) = self.missing_in(group) else {
+            return;
+        };
+        let mut remainder = *parity;
+        for i in self.group(group).filter(|i| self.received.get(*i)) {
+            xor(&mut remainder, slot(buffer, i));
+        }
+        slot(buffer, first).copy_from_slice(&remainder);
+        if second.is_none() {
+            self.received.set(first);
+            info!("Fragment {} recovered from parity", first);
+        } else {
+            self.parity.set(group);
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        (0..data_fragments(self.len)).all(|i| self.received.get(i))
+    }
+
+    /// The first two data fragments missing in `group`.
+    fn missing_in(&self, group: u8) -> (Option<u8>, Option<u8>) {
+        let mut missing = self.group(group).filter(|i| !self.received.get(*i));
+
+        (missing.next(), missing.next())
+    }
+
+    fn group(&self, group: u8) -> impl Iterator<Item = u8> {
+        self.fec().group(group as u16).map(|i| i as u8)
+    }
+
+    fn fec(&self) -> Fec {
+        fec(self.len, self.fec_group)
+    }
+}
+
+#[derive(Default)]
+struct Bitmap([u32; 8]);
+
+impl Bitmap {
+    fn get(&self, index: u8) -> bool {
+        self.0[index as usize / 32] & (1 << (index % 32)) != 0
+    }
+
+    fn set(&mut self, index: u8) {
+        self.0[index as usize / 32] |= 1 << (index % 32);
+    }
+
+    fn clear(&mut self, index: u8) {
+        self.0[index as usize / 32] &= !(1 << (index % 32));
+    }
+}
+
+fn slot(buffer: &mut [u8], index: u8) -> &mut [u8] {
+    let start = index as usize * FRAGMENT_DATA_LEN;
+
+    &mut buffer[start..start + FRAGMENT_DATA_LEN]
+}
+
+/// Number of fragments, parity included, if the payload is valid.
+fn fragments(len: u16, fec_group: u8) -> Option<u8> {
+    let fragments = fec(len, fec_group).fragments();
+
+    u8::try_from(fragments).ok().filter(|_| len > 0)
+}
+
+fn data_fragments(len: u16) -> u8 {
+    len.div_ceil(FRAGMENT_DATA_LEN as u16) as u8
+}
+
+fn fec(len: u16, fec_group: u8) -> Fec {
+    Fec {
+        data_fragments: len.div_ceil(FRAGMENT_DATA_LEN as u16),
+        group_len: fec_group as u16,
+    }
+}
+
+/// Keeps the frames short, as the longer ones are more likely to be lost.
+pub const FRAGMENT_DATA_LEN: usize = 20;
+/// A sensible timeout for [`Reassembler::new`], several fragments' time on air.
+pub const FRAGMENT_TIMEOUT_MS: u64 = 30_000;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: Address = Address(1);
+    const TIMEOUT_MS: u64 = 1_000;
+    /// 7 data fragments, in groups of 4 and 3, then their 2 parity fragments.
+    const PAYLOAD: [u8; 130] = {
+        let mut payload = [0; 130];
+        let mut i = 0;
+        while i < payload.len() {
+            payload[i] = i as u8;
+            i += 1;
+        }
+        payload
+    };
+
+    /// Send the fragments `indices`, returning whether the last one completed the payload.
+    fn send(reassembler: &mut Reassembler<256>, indices: &[u8]) -> bool {
+        let fragmenter = Fragmenter::new(7, &PAYLOAD, 4).unwrap();
+        assert_eq!(fragmenter.fragments(), 9);
+        let mut complete = false;
+        for (i, index) in indices.iter().enumerate() {
+            assert!(!complete, "complete before fragment {}", index);
+            let payload = reassembler.receive(SRC, &fragmenter.fragment(*index), i as u64);
+            if let Some(payload) = payload {
+                assert_eq!(payload, PAYLOAD);
+                complete = true;
+            }
+        }
+
+        complete
+    }
+
+    #[test]
+    fn all() {
+        assert!(send(
+            &mut Reassembler::new(TIMEOUT_MS),
+            &[0, 1, 2, 3, 4, 5, 6]
+        ));
+    }
+
+    #[test]
+    fn dropped() {
+        let mut reassembler = Reassembler::new(TIMEOUT_MS);
+        assert!(send(&mut reassembler, &[0, 2, 3, 4, 6, 7, 8]));
+    }
+
+    #[test]
+    fn reordered() {
+        let mut reassembler = Reassembler::new(TIMEOUT_MS);
+        assert!(send(&mut reassembler, &[8, 6, 4, 2, 0, 5, 3, 1]));
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut reassembler = Reassembler::new(TIMEOUT_MS);
+        assert!(send(&mut reassembler, &[0, 0, 1, 7, 2, 7, 1, 3, 4, 5, 6]));
+        // Late fragments of the completed transfer.
+        assert!(!send(&mut reassembler, &[8, 3]));
+        assert_eq!(reassembler.deadline_ms(), None);
+    }
+
+    #[test]
+    fn parity_before_data() {
+        let mut reassembler = Reassembler::new(TIMEOUT_MS);
+        assert!(send(&mut reassembler, &[7, 8, 0, 2, 3, 4, 5]));
+    }
+
+    #[test]
+    fn two_lost_in_a_group() {
+        let mut reassembler = Reassembler::new(TIMEOUT_MS);
+        assert!(!send(&mut reassembler, &[0, 3, 7, 4, 5, 6, 8]));
+        // Resending either is enough, the parity recovers the other.
+        assert!(send(&mut reassembler, &[2]));
+
+        let mut reassembler = Reassembler::new(TIMEOUT_MS);
+        assert!(!send(&mut reassembler, &[7, 0, 3, 4, 5, 6]));
+        assert!(send(&mut reassembler, &[1]));
+    }
+
+    #[test]
+    fn timeout() {
+        let mut reassembler = Reassembler::new(TIMEOUT_MS);
+        assert!(!send(&mut reassembler, &[0, 1, 2]));
+        assert_eq!(reassembler.deadline_ms(), Some(2 + TIMEOUT_MS));
+        assert!(!reassembler.expire(1 + TIMEOUT_MS));
+        assert!(reassembler.expire(2 + TIMEOUT_MS));
+        assert_eq!(reassembler.deadline_ms(), None);
+
+        // The rest of the transfer isn't enough anymore.
+        assert!(!send(&mut reassembler, &[3, 4, 5, 6, 8]));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Fragmenter::new(7, &[], 4).is_none());
+        assert!(Fragmenter::new(7, &[0; 256 * FRAGMENT_DATA_LEN], 0).is_none());
+
+        let mut reassembler = Reassembler::<64>::new(TIMEOUT_MS);
+        let fragmenter = Fragmenter::new(7, &PAYLOAD, 4).unwrap();
+        assert!(reassembler
+            .receive(SRC, &fragmenter.fragment(0), 0)
+            .is_none());
+        assert_eq!(reassembler.deadline_ms(), None);
+    }
+}
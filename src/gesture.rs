@@ -0,0 +1,223 @@
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    ShortPress,
+    /// Reported as soon as the button is held long enough, not on release.
+    LongPress,
+    DoublePress,
+}
+
+/// Turns the (debounced) edges of a button into [`Gesture`]s.
+///
+/// All times are in milliseconds, from any monotonic clock. Besides feeding it the edges,
+/// [`GestureDetector::timeout`] must be called at [`GestureDetector::deadline_ms`], as some gestures
+/// are only known when nothing happens for a while.
+pub struct GestureDetector {
+    state: State,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Idle,
+    Pressed {
+        since_ms: u64,
+    },
+    /// Released after a short press, a second press makes it a double press.
+    Released {
+        at_ms: u64,
+    },
+    SecondPress,
+    /// Long press reported, waiting for the release.
+    Held,
+}
+
+impl GestureDetector {
+    pub fn new() -> Self {
+        Self { state: State::Idle }
+    }
+
+    /// The button got `pressed` or released at `at_ms`.
+    ///
+    /// Edges that don't change the state of the button are ignored, so it's fine to report the
+    /// current state whenever it's not known whether it changed.
+    pub fn edge(&mut self, pressed: bool, at_ms: u64) -> Option<Gesture> {
+        let (state, gesture) = match (self.state, pressed) {
+            (State::Idle, true) => (State::Pressed { since_ms: at_ms }, None),
+            (State::Pressed { since_ms }, false) if at_ms >= since_ms + LONG_PRESS_MS => {
+                // Released before the timeout got a chance to report it.
+                (State::Idle, Some(Gesture::LongPress))
+            }
+            (State::Pressed { .. }, false) => (State::Released { at_ms }, None),
+            (State::Released { at_ms: released }, true) if at_ms < released + DOUBLE_PRESS_MS => {
+                (State::SecondPress, None)
+            }
+            (State::Released { .. }, true) => {
+                // Too late for a double press, the timeout was missed.
+                (
+                    State::Pressed { since_ms: at_ms },
+                    Some(Gesture::ShortPress),
+                )
+            }
+            (State::SecondPress, false) => (State::Idle, Some(Gesture::DoublePress)),
+            (State::Held, false) => (State::Idle, None),
+            (state, _) => (state, None),
+        };
+        self.state = state;
+
+        gesture
+    }
+
+    /// When [`GestureDetector::timeout`] must be called, if at all.
+    pub fn deadline_ms(&self) -> Option<u64> {
+        match self.state {
+            State::Pressed { since_ms } => Some(since_ms + LONG_PRESS_MS),
+            State::Released { at_ms } => Some(at_ms + DOUBLE_PRESS_MS),
+            State::Idle | State::SecondPress | State::Held => None,
+        }
+    }
+
+    pub fn timeout(&mut self, now_ms: u64) -> Option<Gesture> {
+        if self.deadline_ms().is_none_or(|deadline| now_ms < deadline) {
+            return None;
+        }
+
+        let (state, gesture) = match self.state {
+            State::Pressed { .. } => (State::Held, Gesture::LongPress),
+            _ => (State::Idle, Gesture::ShortPress),
+        };
+        self.state = state;
+
+        Some(gesture)
+    }
+}
+
+impl Default for GestureDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How long the button must be held for a long press.
+const LONG_PRESS_MS: u64 = 2_000;
+/// How soon after a short press, the second press must come for a double press.
+const DOUBLE_PRESS_MS: u64 = 400;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed the `edges` of (time, pressed) and the timeouts in between, returning the gestures
+    /// with when they were reported.
+    fn gestures(edges: &[(u64, bool)], until_ms: u64) -> Vec<(u64, Gesture)> {
+        let mut detector = GestureDetector::new();
+        let mut gestures = Vec::new();
+        let mut edges = edges.iter().peekable();
+        loop {
+            let deadline = detector.deadline_ms().unwrap_or(u64::MAX);
+            match edges.peek() {
+                Some(&&(at_ms, pressed)) if at_ms < deadline => {
+                    edges.next();
+                    gestures.extend(detector.edge(pressed, at_ms).map(|g| (at_ms, g)));
+                }
+                _ if deadline <= until_ms => {
+                    gestures.extend(detector.timeout(deadline).map(|g| (deadline, g)));
+                }
+                _ => return gestures,
+            }
+        }
+    }
+
+    #[test]
+    fn short_press() {
+        let gestures = gestures(&[(1_000, true), (1_200, false)], 5_000);
+        assert_eq!(gestures, [(1_200 + DOUBLE_PRESS_MS, Gesture::ShortPress)]);
+    }
+
+    #[test]
+    fn long_press() {
+        // Reported while still held.
+        let gestures = gestures(&[(1_000, true), (6_000, false)], 10_000);
+        assert_eq!(gestures, [(1_000 + LONG_PRESS_MS, Gesture::LongPress)]);
+
+        // Released right at the deadline, before the timeout was handled.
+        let mut detector = GestureDetector::new();
+        detector.edge(true, 1_000);
+        assert_eq!(
+            detector.edge(false, 1_000 + LONG_PRESS_MS),
+            Some(Gesture::LongPress)
+        );
+        assert_eq!(detector.deadline_ms(), None);
+    }
+
+    #[test]
+    fn double_press() {
+        let edges = [(1_000, true), (1_100, false), (1_300, true), (1_400, false)];
+        assert_eq!(gestures(&edges, 5_000), [(1_400, Gesture::DoublePress)]);
+
+        // Too slow for a double press.
+        let edges = [(1_000, true), (1_100, false), (1_600, true), (1_700, false)];
+        assert_eq!(
+            gestures(&edges, 5_000),
+            [
+                (1_100 + DOUBLE_PRESS_MS, Gesture::ShortPress),
+                (1_700 + DOUBLE_PRESS_MS, Gesture::ShortPress)
+            ]
+        );
+    }
+
+    #[test]
+    fn bounce() {
+        // The button reports the level once settled, so a bounce repeats the edge.
+        let edges = [
+            (1_000, true),
+            (1_050, true),
+            (1_200, false),
+            (1_250, false),
+            (1_300, true),
+            (1_350, true),
+            (1_400, false),
+            (1_450, false),
+        ];
+        assert_eq!(gestures(&edges, 5_000), [(1_400, Gesture::DoublePress)]);
+
+        // Bouncing while held isn't a second press.
+        let edges = [(1_000, true), (1_050, true), (4_000, false)];
+        assert_eq!(
+            gestures(&edges, 5_000),
+            [(1_000 + LONG_PRESS_MS, Gesture::LongPress)]
+        );
+        // Nor an early timeout.
+        let mut detector = GestureDetector::new();
+        detector.edge(true, 1_000);
+        assert_eq!(detector.timeout(1_500), None);
+        assert_eq!(detector.deadline_ms(), Some(1_000 + LONG_PRESS_MS));
+    }
+
+    #[test]
+    fn late_second_press() {
+        // The timeout wasn't handled, e.g while waiting for something else.
+        let mut detector = GestureDetector::new();
+        detector.edge(true, 1_000);
+        detector.edge(false, 1_100);
+        assert_eq!(detector.edge(true, 5_000), Some(Gesture::ShortPress));
+        assert_eq!(detector.edge(false, 5_100), None);
+        assert_eq!(
+            detector.timeout(5_100 + DOUBLE_PRESS_MS),
+            Some(Gesture::ShortPress)
+        );
+
+        // Right at the deadline.
+        let edges = [
+            (1_000, true),
+            (1_100, false),
+            (1_100 + DOUBLE_PRESS_MS, true),
+        ];
+        let mut detector = GestureDetector::new();
+        for (at_ms, pressed) in edges {
+            detector.edge(pressed, at_ms);
+        }
+        assert_eq!(
+            detector.deadline_ms(),
+            Some(1_100 + DOUBLE_PRESS_MS + LONG_PRESS_MS)
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use defmt::{info, warn};
+use heapless::LinearMap;
+
+use crate::{is_compatible, Address, Message, Packet, PROTOCOL_VERSION};
+
+/// Optional features of a node, advertised in its [`Message::Hello`].
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+    pub const TIME_SYNC: Self = Self(1 << 0);
+    pub const PEDESTRIAN_HEAD: Self = Self(1 << 1);
+    pub const PEDESTRIAN_CALL: Self = Self(1 << 2);
+    pub const DETECTORS: Self = Self(1 << 3);
+    pub const OPERATING_MODE: Self = Self(1 << 4);
+    pub const REMOTE_CONFIG: Self = Self(1 << 5);
+    pub const OTA: Self = Self(1 << 6);
+    pub const FRAGMENTS: Self = Self(1 << 7);
+    pub const HEARTBEAT: Self = Self(1 << 8);
+    pub const STATUS: Self = Self(1 << 9);
+    pub const EVENT_LOG: Self = Self(1 << 10);
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// What a node needs, to handle `msg`.
+    pub fn required_by(msg: &Message) -> Self {
+        match msg {
+            Message::TimeSync(_) => Self::TIME_SYNC,
+            Message::PedestrianSignal { .. } => Self::PEDESTRIAN_HEAD,
+            Message::OperatingMode(_) => Self::OPERATING_MODE,
+            Message::GetConfig(_) | Message::SetConfig { .. } | Message::ConfirmConfig { .. } => {
+                Self::REMOTE_CONFIG
+            }
+            Message::OtaStart { .. }
+            | Message::OtaFragment { .. }
+            | Message::OtaStatus
+            | Message::OtaFinish => Self::OTA,
+            Message::Fragment(_) => Self::FRAGMENTS,
+            Message::Heartbeat { .. } => Self::HEARTBEAT,
+            Message::QueryStatus => Self::STATUS,
+            Message::GetEvents { .. } => Self::EVENT_LOG,
+            _ => Self::NONE,
+        }
+    }
+}
+
+/// What a peer told us in its [`Message::Hello`].
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub struct Peer {
+    pub version: u8,
+    pub capabilities: Capabilities,
+}
+
+/// The version and capabilities exchange between nodes.
+///
+/// Nodes say hello on startup and answer hellos, so that they know what their peers can do, and
+/// don't use what they can't. Peers that haven't said hello yet, are assumed to be able to.
+pub struct Handshake {
+    capabilities: Capabilities,
+    peers: LinearMap<Address, Peer, MAX_PEERS>,
+}
+
+impl Handshake {
+    pub fn new(capabilities: Capabilities) -> Self {
+        Self {
+            capabilities,
+            peers: LinearMap::new(),
+        }
+    }
+
+    /// Our hello, expecting one back.
+    pub fn hello(&self) -> Message {
+        self.hello_message(false)
+    }
+
+    /// Handle a handshake message, returning the reply, if any.
+    pub fn handle(&mut self, packet: &Packet) -> Option<Message> {
+        match packet.msg {
+            Message::Hello {
+                version,
+                capabilities,
+                reply,
+            } => {
+                let peer = Peer {
+                    version,
+                    capabilities,
+                };
+                if self.peers.insert(packet.src, peer).is_err() {
+                    warn!("Too many peers, forgetting about {:?}", packet.src);
+                } else if !is_compatible(version) {
+                    warn!(
+                        "{:?} speaks protocol version {:#x}, incompatible with ours ({:#x})",
+                        packet.src, version, PROTOCOL_VERSION
+                    );
+                } else {
+                    info!("{:?} said hello: {:?}", packet.src, peer);
+                }
+
+                (!reply).then(|| self.hello_message(true))
+            }
+            Message::Unsupported { command } => {
+                warn!("{:?} doesn't support message {}", packet.src, command);
+
+                None
+            }
+            _ => None,
+        }
+    }
+
+    pub fn peer(&self, address: Address) -> Option<&Peer> {
+        self.peers.get(&address)
+    }
+
+    /// Whether `dst` can handle `msg`, as far as we know.
+    pub fn check(&self, dst: Address, msg: &Message) -> Result<(), HandshakeError> {
+        let Some(peer) = self.peer(dst) else {
+            return Ok(());
+        };
+        if !is_compatible(peer.version) {
+            return Err(HandshakeError::IncompatibleVersion(peer.version));
+        }
+        let required = Capabilities::required_by(msg);
+        if !peer.capabilities.contains(required) {
+            return Err(HandshakeError::Unsupported(required));
+        }
+
+        Ok(())
+    }
+
+    fn hello_message(&self, reply: bool) -> Message {
+        Message::Hello {
+            version: PROTOCOL_VERSION,
+            capabilities: self.capabilities,
+            reply,
+        }
+    }
+}
+
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub enum HandshakeError {
+    /// The peer speaks another major version of the protocol.
+    IncompatibleVersion(u8),
+    /// The peer lacks the capabilities.
+    Unsupported(Capabilities),
+}
+
+const MAX_PEERS: usize = 8;
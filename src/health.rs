@@ -0,0 +1,88 @@
+use embassy_stm32::adc::{Adc, SampleTime, Temperature, Vbat, VrefInt};
+use embassy_stm32::pac::RCC;
+use embassy_stm32::peripherals::ADC;
+
+use crate::{supply_mv, temperature_c, vbat_mv, ResetCause};
+
+/// Measures the supply, the backup battery and the MCU temperature with the internal channels of
+/// the ADC.
+pub struct Health<'d> {
+    adc: Adc<'d, ADC>,
+    vrefint: VrefInt,
+    vbat: Vbat,
+    temperature: Temperature,
+}
+
+/// Supply and VBAT in mV, and the temperature in °C.
+#[derive(defmt::Format, Clone, Copy)]
+pub struct Measurements {
+    pub supply_mv: u16,
+    pub vbat_mv: u16,
+    pub temperature_c: i8,
+}
+
+impl<'d> Health<'d> {
+    pub fn new(adc: ADC) -> Self {
+        let mut adc = Adc::new(adc);
+        // The internal channels need a long sampling time.
+        adc.set_sample_time(SampleTime::CYCLES160_5);
+        let vrefint = adc.enable_vrefint();
+        let vbat = adc.enable_vbat();
+        let temperature = adc.enable_temperature();
+
+        Self {
+            adc,
+            vrefint,
+            vbat,
+            temperature,
+        }
+    }
+
+    pub fn measure(&mut self) -> Measurements {
+        let supply_mv = supply_mv(self.adc.blocking_read(&mut self.vrefint), cal(VREFINT_CAL));
+        let vbat = self.adc.blocking_read(&mut self.vbat);
+        let sensor = self.adc.blocking_read(&mut self.temperature);
+
+        Measurements {
+            supply_mv,
+            vbat_mv: vbat_mv(vbat, supply_mv),
+            temperature_c: temperature_c(sensor, supply_mv, cal(TS_CAL1), cal(TS_CAL2)),
+        }
+    }
+}
+
+/// Why the MCU was last reset. The flags are cleared, so this must only be called once.
+pub fn reset_cause() -> ResetCause {
+    let csr = RCC.csr().read();
+    let cause = if csr.lpwrrstf() {
+        ResetCause::LowPower
+    } else if csr.wwdgrstf() {
+        ResetCause::WindowWatchdog
+    } else if csr.iwdgrstf() {
+        ResetCause::IndependentWatchdog
+    } else if csr.sftrstf() {
+        ResetCause::Software
+    } else if csr.borrstf() {
+        ResetCause::PowerOn
+    } else if csr.oblrstf() {
+        ResetCause::OptionByteLoad
+    } else if csr.pinrstf() {
+        // Set along with the others, so checked last.
+        ResetCause::Pin
+    } else {
+        ResetCause::Unknown
+    };
+    RCC.csr().modify(|w| w.set_rmvf(true));
+
+    cause
+}
+
+fn cal(address: usize) -> u16 {
+    // SAFETY: Factory calibration values, in the system memory.
+    unsafe { core::ptr::read_volatile(address as *const u16) }
+}
+
+/// Factory calibration addresses, from the datasheet.
+const TS_CAL1: usize = 0x1FFF_75A8;
+const VREFINT_CAL: usize = 0x1FFF_75AA;
+const TS_CAL2: usize = 0x1FFF_75CA;
@@ -0,0 +1,329 @@
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+use std::{fmt, thread};
+
+use crate::{
+    Address, ConfigKey, GatewayError, GatewayMessage, GatewayStatus, HostMessage, Message, Packet,
+    Reception, SerialError, SlipDecoder,
+};
+
+/// The serial gateway (see `lora2traffic-gateway`), from the host.
+///
+/// The packets received by the gateway while waiting for a reply are kept, for
+/// [`Gateway::receive`].
+pub struct Gateway {
+    port: Box<dyn Write + Send>,
+    messages: Receiver<Result<GatewayMessage, HostError>>,
+    received: VecDeque<Reception>,
+    timeout: Duration,
+}
+
+impl Gateway {
+    /// Open the serial port of the gateway, e.g `/dev/ttyUSB0`. It must be set to
+    /// [`crate::GATEWAY_BAUD_RATE`] and raw mode already, e.g with
+    /// `stty -F /dev/ttyUSB0 115200 raw -echo`.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let port = OpenOptions::new().read(true).write(true).open(path)?;
+        let reader = port.try_clone()?;
+
+        Ok(Self::new(reader, port))
+    }
+
+    /// A gateway at the other end of `reader` and `writer`, e.g a pseudo-terminal.
+    pub fn new(reader: impl Read + Send + 'static, writer: impl Write + Send + 'static) -> Self {
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || read_messages(reader, sender));
+
+        Self {
+            port: Box::new(writer),
+            messages,
+            received: VecDeque::new(),
+            timeout: REPLY_TIMEOUT,
+        }
+    }
+
+    /// How long to wait for the replies of the gateway. Sending takes seconds at SF12.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Send `msg` to `dst`, from the gateway's address.
+    pub fn send(&mut self, dst: Address, msg: Message) -> Result<(), HostError> {
+        // The gateway fills in the rest.
+        let packet = Packet {
+            src: Address::BROADCAST,
+            dst,
+            seq: None,
+            ttl: 0,
+            msg,
+        };
+        match self.request(HostMessage::Send(packet))? {
+            GatewayMessage::Sent => Ok(()),
+            _ => Err(HostError::UnexpectedReply),
+        }
+    }
+
+    /// The next packet received by the gateway, if one comes within `timeout`.
+    pub fn receive(&mut self, timeout: Duration) -> Result<Option<Reception>, HostError> {
+        if let Some(reception) = self.received.pop_front() {
+            return Ok(Some(reception));
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.next_message(deadline)? {
+                Some(GatewayMessage::Received(reception)) => return Ok(Some(reception)),
+                // A reply that came too late.
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    pub fn status(&mut self) -> Result<GatewayStatus, HostError> {
+        match self.request(HostMessage::QueryStatus)? {
+            GatewayMessage::Status(status) => Ok(status),
+            _ => Err(HostError::UnexpectedReply),
+        }
+    }
+
+    pub fn get_config(&mut self, key: ConfigKey) -> Result<u32, HostError> {
+        self.config(HostMessage::GetConfig(key))
+    }
+
+    /// Change a setting of the gateway. Returns its new value.
+    pub fn set_config(&mut self, key: ConfigKey, value: u32) -> Result<u32, HostError> {
+        self.config(HostMessage::SetConfig { key, value })
+    }
+
+    fn config(&mut self, msg: HostMessage) -> Result<u32, HostError> {
+        match self.request(msg)? {
+            GatewayMessage::ConfigValue { value, .. } => Ok(value),
+            _ => Err(HostError::UnexpectedReply),
+        }
+    }
+
+    fn request(&mut self, msg: HostMessage) -> Result<GatewayMessage, HostError> {
+        // Drop the replies that came too late, for them not to be taken for this one's.
+        while let Ok(msg) = self.messages.try_recv() {
+            if let Ok(GatewayMessage::Received(reception)) = msg {
+                self.received.push_back(reception);
+            }
+        }
+        self.port.write_all(&msg.to_frame())?;
+        self.port.flush()?;
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.next_message(deadline) {
+                Ok(Some(GatewayMessage::Received(reception))) => self.received.push_back(reception),
+                Ok(Some(GatewayMessage::Error(e))) => return Err(HostError::Gateway(e)),
+                Ok(Some(reply)) => return Ok(reply),
+                Ok(None) => return Err(HostError::Timeout),
+                // Most likely a packet received, of a newer version.
+                Err(HostError::Serial(_)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The next message from the gateway, or `None` if none comes by `deadline`.
+    fn next_message(&mut self, deadline: Instant) -> Result<Option<GatewayMessage>, HostError> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.messages.recv_timeout(timeout) {
+            Ok(msg) => msg.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(HostError::Disconnected),
+        }
+    }
+}
+
+/// Decodes the messages read from the gateway, until it's disconnected.
+fn read_messages(mut reader: impl Read, sender: Sender<Result<GatewayMessage, HostError>>) {
+    let mut decoder = SlipDecoder::new();
+    let mut buf = [0; 256];
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => return,
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                let _ = sender.send(Err(e.into()));
+                return;
+            }
+        };
+
+        for byte in &buf[..len] {
+            let Some(frame) = decoder.push(*byte) else {
+                continue;
+            };
+            let msg = GatewayMessage::from_frame(frame).map_err(HostError::Serial);
+            if sender.send(msg).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HostError {
+    Io(io::Error),
+    /// The gateway didn't reply in time.
+    Timeout,
+    /// The serial port was closed.
+    Disconnected,
+    /// A frame from the gateway couldn't be decoded.
+    Serial(SerialError),
+    Gateway(GatewayError),
+    UnexpectedReply,
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Serial port error: {e}"),
+            Self::Timeout => write!(f, "The gateway didn't reply"),
+            Self::Disconnected => write!(f, "The gateway was disconnected"),
+            Self::Serial(e) => write!(f, "Undecodable frame from the gateway: {e:?}"),
+            Self::Gateway(e) => write!(f, "The gateway failed: {e:?}"),
+            Self::UnexpectedReply => write!(f, "Unexpected reply from the gateway"),
+        }
+    }
+}
+
+impl std::error::Error for HostError {}
+
+impl From<io::Error> for HostError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Long enough for the gateway to send a packet of [`crate::MAX_MSG_SIZE`] bytes, 28 s on air.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::os::fd::FromRawFd;
+
+    use super::*;
+    use crate::{Signal, GATEWAY_ADDRESS};
+
+    /// A pseudo-terminal in raw mode: its controller side, and the path of the port.
+    fn pty() -> (File, String) {
+        let (mut controller, mut port) = (0, 0);
+        // SAFETY: all the pointers are valid or null, and the file descriptors are owned by the
+        // files made of them.
+        unsafe {
+            let null = std::ptr::null_mut();
+            let result = libc::openpty(&mut controller, &mut port, null, null as _, null as _);
+            assert_eq!(result, 0);
+            let mut termios = std::mem::zeroed();
+            assert_eq!(libc::tcgetattr(port, &mut termios), 0);
+            libc::cfmakeraw(&mut termios);
+            assert_eq!(libc::tcsetattr(port, libc::TCSANOW, &termios), 0);
+            let path = CStr::from_ptr(libc::ptsname(controller)).to_str().unwrap();
+            // The settings stay with the pseudo-terminal, for the gateway to open it again.
+            drop(File::from_raw_fd(port));
+
+            (File::from_raw_fd(controller), path.to_owned())
+        }
+    }
+
+    /// Plays the gateway: replies to each message from the host with the next of `replies`. The
+    /// port is returned once they're all sent, still open.
+    fn gateway(mut port: File, replies: Vec<Vec<GatewayMessage>>) -> thread::JoinHandle<File> {
+        thread::spawn(move || {
+            let mut decoder = SlipDecoder::new();
+            let mut replies = replies.into_iter();
+            let mut buf = [0; 256];
+            loop {
+                let len = port.read(&mut buf).unwrap();
+                for byte in &buf[..len] {
+                    let Some(frame) = decoder.push(*byte) else {
+                        continue;
+                    };
+                    HostMessage::from_frame(frame).unwrap();
+                    for msg in replies.next().unwrap() {
+                        port.write_all(&msg.to_frame()).unwrap();
+                    }
+                    if replies.len() == 0 {
+                        return port;
+                    }
+                }
+            }
+        })
+    }
+
+    fn reception(msg: Message) -> Reception {
+        Reception {
+            rssi_dbm: -100,
+            snr_db: 5,
+            packet: Packet {
+                src: Address(2),
+                dst: GATEWAY_ADDRESS,
+                seq: Some(1),
+                ttl: 0,
+                msg,
+            },
+        }
+    }
+
+    #[test]
+    fn gateway_over_pty() {
+        let status = GatewayStatus {
+            version: crate::PROTOCOL_VERSION,
+            address: GATEWAY_ADDRESS,
+            frequency_hz: 868_100_000,
+            tx_power_dbm: 14,
+            uptime_s: 60,
+            received: 1,
+            sent: 0,
+            errors: 0,
+        };
+        let (controller, path) = pty();
+        let replies = vec![
+            // A packet received while the host waits for the reply.
+            vec![
+                GatewayMessage::Received(reception(Message::Signal(Signal::Red))),
+                GatewayMessage::Status(status),
+            ],
+            vec![GatewayMessage::Sent],
+            vec![GatewayMessage::Error(GatewayError::InvalidConfig)],
+            vec![GatewayMessage::ConfigValue {
+                key: ConfigKey::TxPower,
+                value: 10,
+            }],
+            // No reply.
+            vec![],
+        ];
+        let gateway = gateway(controller, replies);
+
+        let mut host = Gateway::open(&path).unwrap();
+        host.set_timeout(Duration::from_millis(500));
+        assert_eq!(host.status().unwrap(), status);
+        host.send(Address(2), Message::QueryStatus).unwrap();
+        assert!(matches!(
+            host.set_config(ConfigKey::TxPower, 100),
+            Err(HostError::Gateway(GatewayError::InvalidConfig))
+        ));
+        assert_eq!(host.set_config(ConfigKey::TxPower, 10).unwrap(), 10);
+        assert!(matches!(
+            host.get_config(ConfigKey::TxPower),
+            Err(HostError::Timeout)
+        ));
+
+        let port = gateway.join().unwrap();
+        let reception = host.receive(Duration::ZERO).unwrap().unwrap();
+        assert!(matches!(reception.packet.msg, Message::Signal(Signal::Red)));
+        assert!(host.receive(Duration::ZERO).unwrap().is_none());
+
+        drop(port);
+        assert!(host.receive(Duration::from_secs(1)).is_err());
+    }
+}
@@ -1,20 +1,99 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+#[cfg(feature = "stm32")]
 use embassy_stm32::bind_interrupts;
 
+#[cfg(feature = "stm32")]
 mod iv;
+#[cfg(feature = "stm32")]
 pub use iv::*;
+#[cfg(feature = "stm32")]
 mod lora;
+#[cfg(feature = "stm32")]
 pub use lora::*;
 mod signal;
 pub use signal::*;
+mod gesture;
+pub use gesture::*;
+#[cfg(feature = "stm32")]
+mod button;
+#[cfg(feature = "stm32")]
+pub use button::*;
 mod protocol;
 pub use protocol::*;
+mod persist;
+pub use persist::*;
+mod startup;
+pub use startup::*;
+#[cfg(feature = "stm32")]
+mod rtc;
+#[cfg(feature = "stm32")]
+pub use rtc::*;
+mod store;
+pub use store::*;
+#[cfg(feature = "stm32")]
+mod flash;
+#[cfg(feature = "stm32")]
+pub use flash::*;
+mod fec;
+pub use fec::*;
+mod fragment;
+pub use fragment::*;
+mod handshake;
+pub use handshake::*;
+mod link;
+pub use link::*;
+mod airtime;
+pub use airtime::*;
+mod ack;
+pub use ack::*;
+mod repeater;
+pub use repeater::*;
+mod serial;
+pub use serial::*;
+mod pcap;
+pub use pcap::*;
+#[cfg(feature = "std")]
+mod host;
+#[cfg(feature = "std")]
+pub use host::*;
+mod status;
+pub use status::*;
+mod events;
+pub use events::*;
+#[cfg(feature = "stm32")]
+mod health;
+#[cfg(feature = "stm32")]
+pub use health::*;
+mod ota;
+pub use ota::*;
+#[cfg(feature = "stm32")]
+mod boot;
+#[cfg(feature = "stm32")]
+pub use boot::*;
+mod config;
+pub use config::*;
+mod remote;
+pub use remote::*;
+mod timesync;
+pub use timesync::*;
+mod coordination;
+pub use coordination::*;
+mod actuation;
+pub use actuation::*;
+mod preemption;
+pub use preemption::*;
+mod priority;
+pub use priority::*;
+mod controller;
+pub use controller::*;
 
+#[cfg(feature = "stm32")]
 bind_interrupts!(struct Irqs{
     SUBGHZ_RADIO => InterruptHandler;
 });
 
+#[cfg(feature = "stm32")]
 pub fn create_stm32_config() -> embassy_stm32::Config {
     let mut config = embassy_stm32::Config::default();
     {
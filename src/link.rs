@@ -0,0 +1,156 @@
+use defmt::warn;
+use heapless::LinearMap;
+
+use crate::Address;
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LinkState {
+    Good,
+    /// Heartbeats are late or lost, or the signal is weak.
+    Degraded,
+    /// Nothing heard for [`LOST_AFTER_HEARTBEATS`] heartbeats, or ever.
+    Lost,
+}
+
+impl LinkState {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Good),
+            1 => Some(Self::Degraded),
+            2 => Some(Self::Lost),
+            _ => None,
+        }
+    }
+}
+
+/// What we know of the link with a peer.
+#[derive(defmt::Format, Clone, Copy)]
+pub struct Link {
+    pub last_seen_ms: u64,
+    /// Heartbeats received, the latest in the lowest bit.
+    history: u32,
+    /// Number of heartbeats the history covers.
+    expected: u8,
+    last_seq: u8,
+    /// Averages over a few packets and over many, in 1/16 dB.
+    rssi_fast: i32,
+    rssi_slow: i32,
+    snr: i32,
+}
+
+impl Link {
+    /// Percentage of the recent heartbeats lost.
+    pub fn loss_percent(&self) -> u8 {
+        if self.expected == 0 {
+            return 0;
+        }
+        let lost = self.expected as u32 - self.history.count_ones();
+
+        (lost * 100 / self.expected as u32) as u8
+    }
+
+    /// The average RSSI of the last few packets, in dBm.
+    pub fn rssi_dbm(&self) -> i16 {
+        (self.rssi_fast / 16) as i16
+    }
+
+    /// How much the RSSI went up (or down) lately, in dB.
+    pub fn rssi_trend_db(&self) -> i16 {
+        ((self.rssi_fast - self.rssi_slow) / 16) as i16
+    }
+
+    pub fn snr_db(&self) -> i16 {
+        (self.snr / 16) as i16
+    }
+}
+
+/// Tracks the links with the peers, from all the packets heard from them and the sequence numbers
+/// of their heartbeats, sent every `heartbeat_ms`.
+pub struct LinkSupervisor {
+    links: LinearMap<Address, Link, MAX_PEERS>,
+    heartbeat_ms: u64,
+}
+
+impl LinkSupervisor {
+    pub fn new(heartbeat_ms: u64) -> Self {
+        Self {
+            links: LinearMap::new(),
+            heartbeat_ms,
+        }
+    }
+
+    /// A packet from `src`, received with `rssi` and `snr`.
+    pub fn received(&mut self, src: Address, rssi: i16, snr: i16, now_ms: u64) {
+        let (rssi, snr) = (rssi as i32 * 16, snr as i32 * 16);
+        match self.links.get_mut(&src) {
+            Some(link) => {
+                link.last_seen_ms = now_ms;
+                link.rssi_fast += (rssi - link.rssi_fast) / 4;
+                link.rssi_slow += (rssi - link.rssi_slow) / 32;
+                link.snr += (snr - link.snr) / 4;
+            }
+            None => {
+                let link = Link {
+                    last_seen_ms: now_ms,
+                    history: 0,
+                    expected: 0,
+                    last_seq: 0,
+                    rssi_fast: rssi,
+                    rssi_slow: rssi,
+                    snr,
+                };
+                if self.links.insert(src, link).is_err() {
+                    warn!("Too many peers, not supervising the link with {:?}", src);
+                }
+            }
+        }
+    }
+
+    /// The heartbeat `seq` from `src`, after [`LinkSupervisor::received`].
+    pub fn heartbeat(&mut self, src: Address, seq: u8) {
+        let Some(link) = self.links.get_mut(&src) else {
+            return;
+        };
+
+        let gap = seq.wrapping_sub(link.last_seq);
+        if link.expected == 0 || gap > 128 {
+            // The first one, or the peer restarted.
+            link.history = 1;
+            link.expected = 1;
+        } else if gap > 0 {
+            link.history = link.history.checked_shl(gap as u32).unwrap_or(0) | 1;
+            link.expected = (link.expected as u32 + gap as u32).min(HISTORY_LEN) as u8;
+        }
+        link.last_seq = seq;
+    }
+
+    pub fn link(&self, peer: Address) -> Option<&Link> {
+        self.links.get(&peer)
+    }
+
+    pub fn state(&self, peer: Address, now_ms: u64) -> LinkState {
+        let Some(link) = self.link(peer) else {
+            return LinkState::Lost;
+        };
+
+        let silent_ms = now_ms.saturating_sub(link.last_seen_ms);
+        if silent_ms > LOST_AFTER_HEARTBEATS * self.heartbeat_ms {
+            LinkState::Lost
+        } else if silent_ms > self.heartbeat_ms * 3 / 2
+            || link.loss_percent() > DEGRADED_LOSS_PERCENT
+            || link.rssi_dbm() < DEGRADED_RSSI_DBM
+        {
+            LinkState::Degraded
+        } else {
+            LinkState::Good
+        }
+    }
+}
+
+pub const LOST_AFTER_HEARTBEATS: u64 = 3;
+const DEGRADED_LOSS_PERCENT: u8 = 20;
+/// Leaves some 15 dB of margin over the sensitivity at SF12, for fading.
+const DEGRADED_RSSI_DBM: i16 = -120;
+const HISTORY_LEN: u32 = 32;
+const MAX_PEERS: usize = 8;
@@ -1,6 +1,8 @@
 use defmt::{info, warn};
+use embassy_futures::select::{select, Either};
 use embassy_stm32::{gpio::Output, mode::Async, spi::Spi};
-use embassy_time::Delay;
+use embassy_time::{Delay, Duration, Instant, Timer};
+use heapless::Vec;
 use lora_phy::{
     mod_params::{Bandwidth, CodingRate, ModulationParams, RadioError, SpreadingFactor},
     sx126x::{self, Stm32wl, Sx126x, TcxoCtrlVoltage},
@@ -9,25 +11,46 @@ use lora_phy::{
 
 use crate::{
     iv::{Stm32wlInterfaceVariant, SubghzSpiDevice},
-    Irqs, Message,
+    time_on_air_us, Address, Capabilities, Capture, DecodeError, Duplicates, Handshake,
+    HandshakeError, Irqs, Link, LinkState, LinkSupervisor, Message, OperatingMode, Packet,
+    Repeater, Route, Signal, DEFAULT_TTL, PREAMBLE_LEN,
 };
 
+/// The default frequency.
 pub const LORA_FREQUENCY_IN_HZ: u32 = 434_000_000; // Top of the EU RF band range
+/// Packets remembered, to drop the copies of repeaters.
+const DUPLICATES_LEN: usize = 16;
+/// How often the nodes send a heartbeat to their peer. Mind the duty cycle, frames take seconds on
+/// air at SF12.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+
+type Radio = LoRa<
+    Sx126x<SubghzSpiDevice<Spi<'static, Async>>, Stm32wlInterfaceVariant<Output<'static>>, Stm32wl>,
+    Delay,
+>;
 
 pub struct LoraHw {
-    lora: LoRa<
-        Sx126x<
-            SubghzSpiDevice<Spi<'static, Async>>,
-            Stm32wlInterfaceVariant<Output<'static>>,
-            Stm32wl,
-        >,
-        Delay,
-    >,
+    lora: Radio,
     mod_params: ModulationParams,
+    frequency_hz: u32,
+    address: Address,
+    tx_power_dbm: i8,
+    handshake: Handshake,
+    link: LinkSupervisor,
+    heartbeat_seq: u8,
+    rx_quality: (i16, i16),
+    /// Of the next packet sent.
+    seq: u16,
+    duplicates: Duplicates<DUPLICATES_LEN>,
+    repeater: Option<Repeater>,
 }
 
 impl LoraHw {
     pub async fn new(
+        address: Address,
+        frequency_hz: u32,
+        tx_power_dbm: i8,
+        capabilities: Capabilities,
         ctrl1: Output<'static>,
         ctrl2: Output<'static>,
         ctrl3: Output<'static>,
@@ -54,30 +77,114 @@ impl LoraHw {
             .await
             .unwrap();
 
-        let mod_params = {
-            match lora.create_modulation_params(
-                SpreadingFactor::_12,
-                Bandwidth::_62KHz,
-                CodingRate::_4_8,
-                LORA_FREQUENCY_IN_HZ,
-            ) {
-                Ok(mp) => mp,
-                Err(err) => {
-                    panic!("Radio error = {err:?}");
-                }
-            }
+        let mod_params = modulation_params(&mut lora, frequency_hz).unwrap();
+
+        Self {
+            lora,
+            mod_params,
+            frequency_hz,
+            address,
+            tx_power_dbm,
+            handshake: Handshake::new(capabilities),
+            link: LinkSupervisor::new(HEARTBEAT_INTERVAL.as_millis()),
+            heartbeat_seq: 0,
+            rx_quality: (0, 0),
+            seq: 0,
+            duplicates: Duplicates::new(),
+            repeater: None,
+        }
+    }
+
+    /// Forward the packets of other nodes taking `routes`, or all of them if there are none.
+    pub fn repeat(&mut self, routes: &'static [Route]) {
+        info!("Repeating packets, routes = {:?}", routes);
+        self.repeater = Some(Repeater::new(self.address, routes));
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Say hello to `dst`, for it to tell us what it can do. The reply is handled by
+    /// [`LoraHw::receive`].
+    pub async fn say_hello(&mut self, dst: Address) -> Result<(), RadioError> {
+        self.send(dst, self.handshake.hello()).await
+    }
+
+    /// Send our status to `dst`, for it to supervise the link. To be called every
+    /// [`HEARTBEAT_INTERVAL`].
+    pub async fn send_heartbeat(
+        &mut self,
+        dst: Address,
+        signal: Signal,
+        mode: OperatingMode,
+    ) -> Result<(), RadioError> {
+        let msg = Message::Heartbeat {
+            seq: self.heartbeat_seq,
+            uptime_s: Instant::now().as_secs() as u32,
+            signal,
+            mode,
         };
+        if self.check(dst, &msg).is_err() {
+            return Ok(());
+        }
+        self.heartbeat_seq = self.heartbeat_seq.wrapping_add(1);
+
+        self.send(dst, msg).await
+    }
 
-        Self { lora, mod_params }
+    pub fn link_state(&self, peer: Address) -> LinkState {
+        self.link.state(peer, Instant::now().as_millis())
     }
 
-    pub async fn receive(&mut self) -> Result<Message, ()> {
-        let mut buffer = [00u8; crate::MSG_SIZE];
+    pub fn link(&self, peer: Address) -> Option<&Link> {
+        self.link.link(peer)
+    }
+
+    /// The RSSI (in dBm) and SNR (in dB) of the last packet received.
+    pub fn rx_quality(&self) -> (i16, i16) {
+        self.rx_quality
+    }
+
+    /// Whether `dst` can handle `msg`, as far as we know.
+    pub fn check(&self, dst: Address, msg: &Message) -> Result<(), HandshakeError> {
+        self.handshake.check(dst, msg)
+    }
+
+    /// Switch to a new address, frequency and TX power, e.g after a config change.
+    pub fn reconfigure(
+        &mut self,
+        address: Address,
+        frequency_hz: u32,
+        tx_power_dbm: i8,
+    ) -> Result<(), RadioError> {
+        self.mod_params = modulation_params(&mut self.lora, frequency_hz)?;
+        self.frequency_hz = frequency_hz;
+        self.address = address;
+        if let Some(repeater) = &mut self.repeater {
+            repeater.set_address(address);
+        }
+        self.tx_power_dbm = tx_power_dbm;
+        info!(
+            "Radio reconfigured: address = {:?}, frequency = {} Hz, TX power = {} dBm",
+            address, frequency_hz, tx_power_dbm
+        );
+
+        Ok(())
+    }
+
+    /// Receive the next packet addressed to us.
+    ///
+    /// The handshake is handled here, and messages we don't know about are answered with
+    /// [`Message::Unsupported`]. If we're a repeater, the packets to forward are sent on from here
+    /// too, when they're due.
+    pub async fn receive(&mut self) -> Result<Packet, ()> {
+        let mut buffer = [00u8; crate::MAX_MSG_SIZE];
 
         loop {
             let rx_pkt_params = {
                 match self.lora.create_rx_packet_params(
-                    4,
+                    PREAMBLE_LEN,
                     false,
                     buffer.len() as u8,
                     true,
@@ -102,18 +209,82 @@ impl LoraHw {
                     return Err(());
                 }
             };
-            match self.lora.rx(&rx_pkt_params, &mut buffer).await {
+            // Wake up for the packets to forward, if we're a repeater.
+            let forward_at = self
+                .repeater
+                .as_ref()
+                .and_then(Repeater::next_due_ms)
+                .map_or(Instant::MAX, Instant::from_millis);
+            let rx = match select(
+                self.lora.rx(&rx_pkt_params, &mut buffer),
+                Timer::at(forward_at),
+            )
+            .await
+            {
+                Either::First(rx) => rx,
+                Either::Second(()) => {
+                    self.forward_due().await;
+
+                    continue;
+                }
+            };
+            match rx {
                 Ok((received_len, rx_pkt_status)) => {
                     info!(
                         "rx received something. SNR = {}, RSSI = {}",
                         rx_pkt_status.snr, rx_pkt_status.rssi
                     );
-                    match Message::from_bytes(&buffer[..received_len as usize]) {
-                        Some(msg) => {
-                            info!("rx message = {:?}", msg);
-                            return Ok(msg);
+                    let received_at = Instant::now();
+                    self.rx_quality = (rx_pkt_status.rssi, rx_pkt_status.snr);
+                    let packet = Packet::from_bytes(&buffer[..received_len as usize]);
+                    if let Ok(packet) = packet {
+                        // Repeated by a repeater, or heard directly and then repeated.
+                        if packet.src == self.address
+                            || self.duplicates.check(&packet, received_at.as_millis())
+                        {
+                            info!(
+                                "rx copy of packet {:?} from {:?}. Ignoring...",
+                                packet.seq, packet.src
+                            );
+                            continue;
+                        }
+                        if let Some(repeater) = &mut self.repeater {
+                            repeater.heard(&packet, received_at.as_millis());
+                        }
+
+                        let now = received_at.as_millis();
+                        let (rssi, snr) = self.rx_quality;
+                        self.link.received(packet.src, rssi, snr, now);
+                        if let Message::Heartbeat { seq, .. } = packet.msg {
+                            self.link.heartbeat(packet.src, seq);
                         }
-                        None => info!("rx unknown packet. Ignoring..."),
+                    }
+                    match packet {
+                        Ok(
+                            packet @ Packet {
+                                msg: Message::Hello { .. } | Message::Unsupported { .. },
+                                ..
+                            },
+                        ) if packet.is_for(self.address) => {
+                            if let Some(reply) = self.handshake.handle(&packet) {
+                                self.reply(packet.src, reply).await;
+                            }
+                        }
+                        Ok(packet) if packet.is_for(self.address) => {
+                            info!("rx packet = {:?}", packet);
+                            return Ok(packet);
+                        }
+                        Ok(packet) => info!("rx packet for {:?}. Ignoring...", packet.dst),
+                        Err(DecodeError::UnknownMessage { src, dst, command })
+                            if dst == self.address =>
+                        {
+                            warn!("rx unknown message {} from {:?}", command, src);
+                            self.reply(src, Message::Unsupported { command }).await;
+                        }
+                        Err(e @ DecodeError::IncompatibleVersion(_)) => {
+                            warn!("rx packet of an incompatible protocol = {}", e)
+                        }
+                        Err(e) => info!("rx undecodable packet = {}. Ignoring...", e),
                     }
                 }
                 Err(err) => {
@@ -125,16 +296,89 @@ impl LoraHw {
         }
     }
 
-    pub async fn send(&mut self, msg: Message) -> Result<(), RadioError> {
-        info!("tx message = {:?}", msg);
-        let buffer = msg.to_bytes();
+    /// Receive the next frame on air, whoever it's for and whatever it holds, for monitoring the
+    /// channel. Nothing is answered nor forwarded.
+    pub async fn sniff(&mut self) -> Result<Capture, RadioError> {
+        let mut buffer = [0; crate::MAX_MSG_SIZE];
+        let rx_pkt_params = self.lora.create_rx_packet_params(
+            PREAMBLE_LEN,
+            false,
+            buffer.len() as u8,
+            true,
+            false,
+            &self.mod_params,
+        )?;
+        self.lora
+            .prepare_for_rx(RxMode::Continuous, &self.mod_params, &rx_pkt_params)
+            .await?;
+        let (received_len, rx_pkt_status) = match self.lora.rx(&rx_pkt_params, &mut buffer).await {
+            Ok(received) => received,
+            Err(RadioError::HeaderError | RadioError::CRCErrorOnReceive) => {
+                return Ok(Capture::corrupt(
+                    Instant::now().as_micros(),
+                    self.frequency_hz,
+                ));
+            }
+            Err(err) => return Err(err),
+        };
+        self.rx_quality = (rx_pkt_status.rssi, rx_pkt_status.snr);
+
+        Ok(Capture {
+            at_us: Instant::now().as_micros(),
+            frequency_hz: self.frequency_hz,
+            rssi_dbm: rx_pkt_status.rssi,
+            snr_db: rx_pkt_status.snr,
+            bytes: Vec::from_slice(&buffer[..received_len as usize]).unwrap(),
+        })
+    }
+
+    pub async fn send(&mut self, dst: Address, msg: Message) -> Result<(), RadioError> {
+        let packet = Packet {
+            src: self.address,
+            dst,
+            seq: Some(self.seq),
+            ttl: DEFAULT_TTL,
+            msg,
+        };
+        self.seq = self.seq.wrapping_add(1);
+        info!("tx packet = {:?}", packet);
 
-        let mut tx_pkt_params =
-            self.lora
-                .create_tx_packet_params(4, false, true, false, &self.mod_params)?;
+        self.transmit(&packet).await
+    }
+
+    /// Forward the packets that are due, if we're a repeater.
+    async fn forward_due(&mut self) {
+        loop {
+            let now = Instant::now().as_millis();
+            let Some(packet) = self.repeater.as_mut().and_then(|r| r.due(now)) else {
+                return;
+            };
+
+            info!("Forwarding packet = {:?}", packet);
+            if let Err(e) = self.transmit(&packet).await {
+                warn!("Radio error = {}", e);
+            }
+        }
+    }
+
+    async fn transmit(&mut self, packet: &Packet) -> Result<(), RadioError> {
+        let buffer = packet.to_bytes();
+
+        let mut tx_pkt_params = self.lora.create_tx_packet_params(
+            PREAMBLE_LEN,
+            false,
+            true,
+            false,
+            &self.mod_params,
+        )?;
 
         self.lora
-            .prepare_for_tx(&self.mod_params, &mut tx_pkt_params, 20, &buffer)
+            .prepare_for_tx(
+                &self.mod_params,
+                &mut tx_pkt_params,
+                self.tx_power_dbm as i32,
+                &buffer,
+            )
             .await?;
 
         self.lora.tx().await?;
@@ -143,9 +387,31 @@ impl LoraHw {
         Ok(())
     }
 
+    async fn reply(&mut self, dst: Address, msg: Message) {
+        if let Err(e) = self.send(dst, msg).await {
+            warn!("tx failed: {}", e);
+        }
+    }
+
     pub async fn sleep(&mut self) {
         if let Err(e) = self.lora.sleep(false).await {
             warn!("Failed to put radio to sleep: {}", e);
         }
     }
 }
+
+fn modulation_params(lora: &mut Radio, frequency_hz: u32) -> Result<ModulationParams, RadioError> {
+    lora.create_modulation_params(
+        SpreadingFactor::_12,
+        Bandwidth::_62KHz,
+        CodingRate::_4_8,
+        frequency_hz,
+    )
+    .inspect_err(|err| warn!("Radio error = {}", err))
+}
+
+/// Time on air of a packet with a payload of `payload_len` bytes, with the modulation and packet
+/// parameters used by [`LoraHw`].
+pub fn time_on_air(payload_len: usize) -> Duration {
+    Duration::from_micros(time_on_air_us(payload_len))
+}
@@ -0,0 +1,425 @@
+use defmt::{info, warn};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashError, NorFlashErrorKind,
+    ReadNorFlash,
+};
+use sha2::{Digest, Sha512};
+
+use crate::{time_on_air_us, xor, Address, Fec, Message, Packet, DEFAULT_TTL};
+
+/// A firmware update to send over the air: the firmware, followed by its signature.
+///
+/// The update is cut into [`FRAGMENT_LEN`] bytes long data fragments, every [`FEC_GROUP_LEN`] of
+/// them protected by a parity fragment (see [`Fec`]), so that any one fragment lost in a group is
+/// recovered without asking for it again. Whatever can't be recovered is reported missing by the
+/// [`OtaReceiver`], and must be sent again.
+pub struct OtaImage<'a> {
+    firmware: &'a [u8],
+    signature: [u8; SIGNATURE_LEN],
+}
+
+impl<'a> OtaImage<'a> {
+    /// `signature` is the Ed25519 signature of the SHA-512 digest of `firmware`, as expected by
+    /// embassy-boot.
+    pub fn new(firmware: &'a [u8], signature: [u8; SIGNATURE_LEN]) -> Self {
+        Self {
+            firmware,
+            signature,
+        }
+    }
+
+    /// Length of the firmware.
+    pub fn len(&self) -> u32 {
+        self.firmware.len() as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.firmware.is_empty()
+    }
+
+    /// Number of fragments, parity included. The parity fragments come after the data fragments.
+    pub fn fragments(&self) -> u16 {
+        fec(self.len()).fragments()
+    }
+
+    /// Time on air of a fragment, in its [`Message::OtaFragment`].
+    pub fn fragment_time_on_air_us() -> u64 {
+        let packet = Packet {
+            src: Address::BROADCAST,
+            dst: Address::BROADCAST,
+            seq: Some(0),
+            ttl: DEFAULT_TTL,
+            msg: Message::OtaFragment {
+                index: 0,
+                fragment: [0; FRAGMENT_LEN],
+            },
+        };
+
+        time_on_air_us(packet.to_bytes().len())
+    }
+
+    pub fn fragment(&self, index: u16) -> [u8; FRAGMENT_LEN] {
+        let fec = fec(self.len());
+        match fec.parity_group(index) {
+            Some(group) => fec.parity(group, |i| self.data_fragment(i)),
+            None => self.data_fragment(index),
+        }
+    }
+
+    fn data_fragment(&self, index: u16) -> [u8; FRAGMENT_LEN] {
+        let start = index as usize * FRAGMENT_LEN;
+        let mut fragment = [ERASED; FRAGMENT_LEN];
+        for (i, byte) in fragment.iter_mut().enumerate() {
+            let offset = start + i;
+            let signature_offset = offset.checked_sub(self.firmware.len());
+            if let Some(b) = self
+                .firmware
+                .get(offset)
+                .or_else(|| signature_offset.and_then(|offset| self.signature.get(offset)))
+            {
+                *byte = *b;
+            }
+        }
+
+        fragment
+    }
+}
+
+/// Receives an [`OtaImage`] into the DFU partition, where the bootloader picks it up from.
+pub struct OtaReceiver {
+    session: Option<Session>,
+}
+
+struct Session {
+    len: u32,
+    /// Bit mask of the data fragments in the partition, indexed by fragment number.
+    received: [u32; MAX_FRAGMENTS.div_ceil(32)],
+}
+
+impl Session {
+    fn is_received(&self, index: u16) -> bool {
+        self.received[index as usize / 32] & (1 << (index % 32)) != 0
+    }
+
+    fn set_received(&mut self, index: u16) {
+        self.received[index as usize / 32] |= 1 << (index % 32);
+    }
+
+    fn data_fragments(&self) -> u16 {
+        data_fragments(self.len)
+    }
+}
+
+impl OtaReceiver {
+    pub fn new() -> Self {
+        Self { session: None }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Start receiving a `len` bytes long firmware, erasing `dfu` for it. Any ongoing session is
+    /// dropped.
+    pub fn start<F: NorFlash>(&mut self, len: u32, dfu: &mut F) -> Result<(), OtaError> {
+        self.session = None;
+        let image_len = data_fragments(len) as u32 * FRAGMENT_LEN as u32;
+        if len == 0 || len > MAX_UPDATE_LEN || image_len > dfu.capacity() as u32 {
+            warn!("OTA update of {} bytes doesn't fit", len);
+            return Err(OtaError::TooLarge);
+        }
+
+        dfu.erase(0, dfu.capacity() as u32).map_err(flash_error)?;
+        self.session = Some(Session {
+            len,
+            received: [0; MAX_FRAGMENTS.div_ceil(32)],
+        });
+        info!(
+            "OTA update of {} bytes started, in {} fragments",
+            len,
+            data_fragments(len)
+        );
+
+        Ok(())
+    }
+
+    /// Store the fragment `index`, or use it to recover a lost one if it's a parity fragment.
+    pub fn fragment<F: NorFlash>(
+        &mut self,
+        index: u16,
+        fragment: &[u8; FRAGMENT_LEN],
+        dfu: &mut F,
+    ) -> Result<(), OtaError> {
+        let session = self.session.as_mut().ok_or(OtaError::NoSession)?;
+        if index < session.data_fragments() {
+            if !session.is_received(index) {
+                write_fragment(dfu, index, fragment)?;
+                session.set_received(index);
+            }
+            return Ok(());
+        }
+
+        let fec = fec(session.len);
+        let group = fec.parity_group(index).ok_or(OtaError::InvalidFragment)?;
+        let group = fec.group(group);
+        let mut missing = group.clone().filter(|i| !session.is_received(*i));
+        let (Some(lost), None) = (missing.next(), missing.next()) else {
+            // Nothing to recover, or too much.
+            return Ok(());
+        };
+        let mut recovered = *fragment;
+        let mut buf = [0; FRAGMENT_LEN];
+        for i in group.filter(|i| *i != lost) {
+            dfu.read(i as u32 * FRAGMENT_LEN as u32, &mut buf)
+                .map_err(flash_error)?;
+            xor(&mut recovered, &buf);
+        }
+        write_fragment(dfu, lost, &recovered)?;
+        session.set_received(lost);
+        info!("OTA fragment {} recovered from parity", lost);
+
+        Ok(())
+    }
+
+    /// The first missing data fragment and a bit mask of the missing ones from there on, for the
+    /// sender to send again. The first missing one is past the last fragment when none is missing.
+    pub fn missing(&self) -> Option<(u16, u32)> {
+        let session = self.session.as_ref()?;
+        let data = session.data_fragments();
+        let first = (0..data).find(|i| !session.is_received(*i)).unwrap_or(data);
+        let bitmap = (first..data.min(first.saturating_add(32)))
+            .filter(|i| !session.is_received(*i))
+            .fold(0, |bitmap, i| bitmap | 1 << (i - first));
+
+        Some((first, bitmap))
+    }
+
+    /// Verify the signature of the received firmware with `public_key`, ending the session.
+    ///
+    /// Returns the length of the firmware, for the bootloader to swap it in.
+    pub fn finish<F: NorFlash>(
+        &mut self,
+        dfu: &mut F,
+        public_key: &[u8; 32],
+    ) -> Result<u32, OtaError> {
+        let (first, _) = self.missing().ok_or(OtaError::NoSession)?;
+        let session = self.session.as_ref().ok_or(OtaError::NoSession)?;
+        if first < session.data_fragments() {
+            return Err(OtaError::Incomplete);
+        }
+        let len = session.len;
+        self.session = None;
+
+        let mut signature = [0; SIGNATURE_LEN];
+        dfu.read(len, &mut signature).map_err(flash_error)?;
+        let mut digest = Sha512::new();
+        let mut buf = [0; 64];
+        for offset in (0..len).step_by(buf.len()) {
+            let chunk = &mut buf[..(len - offset).min(64) as usize];
+            dfu.read(offset, chunk).map_err(flash_error)?;
+            digest.update(chunk);
+        }
+
+        let verified = VerifyingKey::from_bytes(public_key)
+            .and_then(|key| key.verify(&digest.finalize(), &Signature::from_bytes(&signature)));
+        if verified.is_err() {
+            warn!("OTA update signature verification failed");
+            return Err(OtaError::BadSignature);
+        }
+        info!("OTA update of {} bytes verified", len);
+
+        Ok(len)
+    }
+}
+
+impl Default for OtaReceiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum OtaError {
+    NoSession,
+    /// The update doesn't fit in the DFU partition.
+    TooLarge,
+    InvalidFragment,
+    Incomplete,
+    Flash,
+    BadSignature,
+}
+
+/// A [`NorFlash`] in RAM, e.g to simulate transfers on the host.
+pub struct MemFlash<const N: usize> {
+    bytes: [u8; N],
+}
+
+impl<const N: usize> MemFlash<N> {
+    pub fn new() -> Self {
+        Self { bytes: [ERASED; N] }
+    }
+}
+
+impl<const N: usize> Default for MemFlash<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ErrorType for MemFlash<N> {
+    type Error = NorFlashErrorKind;
+}
+
+impl<const N: usize> ReadNorFlash for MemFlash<N> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.bytes[offset..offset + bytes.len()]);
+
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<const N: usize> NorFlash for MemFlash<N> {
+    const WRITE_SIZE: usize = 8;
+    const ERASE_SIZE: usize = 2048;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        self.bytes[from as usize..to as usize].fill(ERASED);
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        // Like flash, writing can only clear bits.
+        for (d, s) in self.bytes[offset..offset + bytes.len()]
+            .iter_mut()
+            .zip(bytes)
+        {
+            *d &= s;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_fragment<F: NorFlash>(
+    dfu: &mut F,
+    index: u16,
+    fragment: &[u8; FRAGMENT_LEN],
+) -> Result<(), OtaError> {
+    dfu.write(index as u32 * FRAGMENT_LEN as u32, fragment)
+        .map_err(flash_error)
+}
+
+fn flash_error<E: NorFlashError>(e: E) -> OtaError {
+    warn!("DFU flash error: {}", defmt::Debug2Format(&e.kind()));
+
+    OtaError::Flash
+}
+
+/// Number of data fragments for a `len` bytes long firmware and its signature.
+fn data_fragments(len: u32) -> u16 {
+    (len as usize + SIGNATURE_LEN).div_ceil(FRAGMENT_LEN) as u16
+}
+
+fn fec(len: u32) -> Fec {
+    Fec {
+        data_fragments: data_fragments(len),
+        group_len: FEC_GROUP_LEN,
+    }
+}
+
+/// Keeps the frames short, and is a multiple of the flash write size.
+pub const FRAGMENT_LEN: usize = 24;
+/// Data fragments per parity fragment.
+pub const FEC_GROUP_LEN: u16 = 8;
+pub const SIGNATURE_LEN: usize = 64;
+/// The size of the active partition in `memory.x`. The DFU partition has a page more, which also
+/// makes room for the signature.
+pub const MAX_UPDATE_LEN: u32 = 108 * 1024;
+const MAX_FRAGMENTS: usize = (MAX_UPDATE_LEN as usize + SIGNATURE_LEN).div_ceil(FRAGMENT_LEN);
+const ERASED: u8 = 0xff;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRMWARE: [u8; 1000] = [0x5a; 1000];
+    const DFU_LEN: usize = 4096;
+
+    fn receive(image: &OtaImage, lost: impl Fn(u16) -> bool) -> (OtaReceiver, MemFlash<DFU_LEN>) {
+        let mut dfu = MemFlash::new();
+        let mut receiver = OtaReceiver::new();
+        receiver.start(image.len(), &mut dfu).unwrap();
+        for i in (0..image.fragments()).filter(|i| !lost(*i)) {
+            receiver.fragment(i, &image.fragment(i), &mut dfu).unwrap();
+        }
+
+        (receiver, dfu)
+    }
+
+    #[test]
+    fn fragment_time_on_air() {
+        // A 36 bytes frame.
+        assert_eq!(OtaImage::fragment_time_on_air_us(), 4_734_976);
+    }
+
+    #[test]
+    fn recovered_from_parity() {
+        let image = OtaImage::new(&FIRMWARE, [1; SIGNATURE_LEN]);
+        // 45 data fragments, then 6 parity fragments.
+        assert_eq!(image.fragments(), 51);
+
+        let (receiver, mut dfu) = receive(&image, |i| i < 45 && i % 8 == 3);
+        assert_eq!(receiver.missing(), Some((45, 0)));
+        let mut firmware = [0; FIRMWARE.len()];
+        dfu.read(0, &mut firmware).unwrap();
+        assert_eq!(firmware, FIRMWARE);
+    }
+
+    #[test]
+    fn two_lost_in_a_group() {
+        let image = OtaImage::new(&FIRMWARE, [1; SIGNATURE_LEN]);
+        let (mut receiver, mut dfu) = receive(&image, |i| i == 9 || i == 10);
+        assert_eq!(receiver.missing(), Some((9, 0b11)));
+        assert_eq!(
+            receiver.finish(&mut dfu, &[0; 32]),
+            Err(OtaError::Incomplete)
+        );
+
+        receiver
+            .fragment(10, &image.fragment(10), &mut dfu)
+            .unwrap();
+        receiver
+            .fragment(46, &image.fragment(46), &mut dfu)
+            .unwrap();
+        assert_eq!(receiver.missing(), Some((45, 0)));
+    }
+
+    #[test]
+    fn invalid_fragment() {
+        let image = OtaImage::new(&FIRMWARE, [1; SIGNATURE_LEN]);
+        let mut dfu = MemFlash::<DFU_LEN>::new();
+        let mut receiver = OtaReceiver::new();
+        assert_eq!(
+            receiver.fragment(0, &image.fragment(0), &mut dfu),
+            Err(OtaError::NoSession)
+        );
+        receiver.start(image.len(), &mut dfu).unwrap();
+        assert_eq!(
+            receiver.fragment(image.fragments(), &[0; FRAGMENT_LEN], &mut dfu),
+            Err(OtaError::InvalidFragment)
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use heapless::Vec;
+
+use crate::MAX_MSG_SIZE;
+
+/// A frame heard on air, whoever it was for.
+///
+/// Captures are streamed as a PCAP file, [`PCAP_HEADER`] and then a record per capture, with the
+/// LoRaTap link type that Wireshark reads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+    /// Microseconds since startup, at the end of the frame.
+    pub at_us: u64,
+    pub frequency_hz: u32,
+    pub rssi_dbm: i16,
+    pub snr_db: i16,
+    /// Whatever they are, to decode with [`crate::Packet::from_bytes`].
+    pub bytes: Vec<u8, MAX_MSG_SIZE>,
+}
+
+impl Capture {
+    /// A frame whose header or CRC was wrong, so neither its bytes nor its RSSI and SNR are known.
+    pub fn corrupt(at_us: u64, frequency_hz: u32) -> Self {
+        Self {
+            at_us,
+            frequency_hz,
+            rssi_dbm: 0,
+            snr_db: 0,
+            bytes: Vec::new(),
+        }
+    }
+
+    pub fn is_corrupt(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The PCAP record of the capture.
+    pub fn to_record(&self) -> Vec<u8, MAX_RECORD_LEN> {
+        let quality = match self.is_corrupt() {
+            false => {
+                let rssi = (self.rssi_dbm + RSSI_OFFSET_DBM).clamp(0, u8::MAX as i16) as u8;
+                let snr = (self.snr_db * 4).clamp(i8::MIN as i16, i8::MAX as i16) as i8;
+                [rssi, rssi, rssi, snr as u8]
+            }
+            true => [0; 4],
+        };
+        let len = (LORATAP_LEN + self.bytes.len()) as u32;
+
+        let mut record = Vec::new();
+        let _ = record.extend_from_slice(&((self.at_us / 1_000_000) as u32).to_le_bytes());
+        let _ = record.extend_from_slice(&((self.at_us % 1_000_000) as u32).to_le_bytes());
+        let _ = record.extend_from_slice(&len.to_le_bytes());
+        let _ = record.extend_from_slice(&len.to_le_bytes());
+        // The LoRaTap header (version 0), in network byte order.
+        let _ = record.extend_from_slice(&[0, 0]);
+        let _ = record.extend_from_slice(&(LORATAP_LEN as u16).to_be_bytes());
+        let _ = record.extend_from_slice(&self.frequency_hz.to_be_bytes());
+        // The bandwidth is in steps of 125 kHz, 0 stands for our 62.5 kHz.
+        let _ = record.extend_from_slice(&[0, SPREADING_FACTOR]);
+        let _ = record.extend_from_slice(&quality);
+        let _ = record.push(SYNC_WORD);
+        // Corrupt frames are recorded without a payload.
+        let _ = record.extend_from_slice(&self.bytes);
+
+        record
+    }
+
+    /// Decodes a record of [`Capture::to_record`], with its header.
+    pub fn from_record(record: &[u8]) -> Option<Self> {
+        let [ts_s, ts_us, len, _] = record_header(record)?;
+        let data = &record[RECORD_HEADER_LEN..];
+        if data.len() != len as usize {
+            return None;
+        }
+        let [0, _, l0, l1, f0, f1, f2, f3, _, _, rssi, _, _, snr, _, bytes @ ..] = data else {
+            return None;
+        };
+        if u16::from_be_bytes([*l0, *l1]) as usize != LORATAP_LEN {
+            return None;
+        }
+        let at_us = ts_s as u64 * 1_000_000 + ts_us as u64;
+        let frequency_hz = u32::from_be_bytes([*f0, *f1, *f2, *f3]);
+        if bytes.is_empty() {
+            return Some(Self::corrupt(at_us, frequency_hz));
+        }
+
+        Some(Self {
+            at_us,
+            frequency_hz,
+            rssi_dbm: *rssi as i16 - RSSI_OFFSET_DBM,
+            snr_db: *snr as i8 as i16 / 4,
+            bytes: Vec::from_slice(bytes).ok()?,
+        })
+    }
+}
+
+/// The length of the record starting with `header`, the [`RECORD_HEADER_LEN`] first bytes of it.
+pub fn record_len(header: &[u8]) -> Option<usize> {
+    let [_, _, len, _] = record_header(header)?;
+
+    Some(RECORD_HEADER_LEN + len as usize).filter(|len| *len <= MAX_RECORD_LEN)
+}
+
+/// The timestamp (in seconds and microseconds) and the lengths of a record.
+fn record_header(record: &[u8]) -> Option<[u32; 4]> {
+    let header = record.get(..RECORD_HEADER_LEN)?;
+
+    Some([0, 1, 2, 3].map(|i| u32::from_le_bytes([0, 1, 2, 3].map(|j| header[i * 4 + j]))))
+}
+
+/// Starts a PCAP stream: microsecond timestamps, records of up to a frame and LoRaTap link type.
+pub const PCAP_HEADER: [u8; 24] = [
+    // Magic number, little-endian.
+    0xd4, 0xc3, 0xb2, 0xa1, //
+    // Version 2.4.
+    2, 0, 4, 0, //
+    // UTC, and the timestamps' accuracy.
+    0, 0, 0, 0, 0, 0, 0, 0, //
+    // The longest record data, LORATAP_LEN + MAX_MSG_SIZE.
+    0x0e, 0x01, 0, 0, //
+    // LINKTYPE_LORATAP.
+    0x0e, 0x01, 0, 0,
+];
+pub const RECORD_HEADER_LEN: usize = 16;
+pub const MAX_RECORD_LEN: usize = RECORD_HEADER_LEN + LORATAP_LEN + MAX_MSG_SIZE;
+const LORATAP_LEN: usize = 15;
+/// LoRaTap's RSSI is in dB above -139 dBm.
+const RSSI_OFFSET_DBM: i16 = 139;
+const SPREADING_FACTOR: u8 = 12;
+/// Of private networks, as set up by `LoraHw`.
+const SYNC_WORD: u8 = 0x12;
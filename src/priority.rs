@@ -0,0 +1,32 @@
+/// Settings for transit signal priority, to get buses and trams through with less waiting.
+///
+/// The phase serving a vehicle that's about to arrive is extended until it's through (green
+/// extension), or the conflicting phase is cut short so that the vehicle's phase is shown by the
+/// time it arrives (early green). Neither goes beyond these limits, nor below the minimum phase
+/// times.
+#[derive(defmt::Format, Clone, Copy)]
+pub struct TransitPriority {
+    /// The vehicle classes that get priority.
+    pub classes: &'static [VehicleClass],
+    /// How much longer a phase can last, in total.
+    pub max_extension_s: u16,
+    /// How much shorter the conflicting phase can get.
+    pub max_reduction_s: u16,
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum VehicleClass {
+    Bus = b'b',
+    Tram = b't',
+}
+
+impl VehicleClass {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            b'b' => Some(Self::Bus),
+            b't' => Some(Self::Tram),
+            _ => None,
+        }
+    }
+}
@@ -1,38 +1,549 @@
-#[derive(defmt::Format, Clone, Copy)]
+use heapless::Vec;
+
+use crate::{
+    Approach, Capabilities, ConfigKey, Event, EventPage, Faults, Fragment, OperatingMode,
+    ResetCause, Status, SyncState, SyncStatus, VehicleClass, EVENTS_PER_PAGE, FRAGMENT_LEN,
+    MAC_LEN,
+};
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Address(pub u8);
+
+impl Address {
+    pub const BROADCAST: Self = Self(0xff);
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy)]
+pub struct Packet {
+    pub src: Address,
+    pub dst: Address,
+    /// Numbers the packets of `src`, for the copies of repeaters to be dropped. `None` from nodes
+    /// of versions before 0x11, whose packets aren't repeated. Sent as 0.
+    pub seq: Option<u16>,
+    /// Times the packet may still be forwarded, see [`crate::Repeater`].
+    pub ttl: u8,
+    pub msg: Message,
+}
+
+impl Packet {
+    pub fn is_for(&self, address: Address) -> bool {
+        self.dst == address || self.dst == Address::BROADCAST
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let [HEADER, version, src, dst, rest @ .., FOOTER] = bytes else {
+            return Err(DecodeError::Malformed);
+        };
+        // Hellos are understood whatever the version, for the nodes to tell they're incompatible.
+        if !is_compatible(*version) && rest.first() != Some(&HELLO) {
+            return Err(DecodeError::IncompatibleVersion(*version));
+        }
+        // Since version 0x11, frames end with the fields for repeaters, after the message.
+        let (msg, seq, ttl) = if is_compatible(*version) && *version >= REPEATER_VERSION {
+            let [msg @ .., s0, s1, ttl] = rest else {
+                return Err(DecodeError::Malformed);
+            };
+            (msg, Some(u16::from_le_bytes([*s0, *s1])), *ttl)
+        } else {
+            (rest, None, 0)
+        };
+        let (src, dst) = (Address(*src), Address(*dst));
+        let msg = Message::from_bytes(msg)?.ok_or(DecodeError::UnknownMessage {
+            src,
+            dst,
+            command: msg[0],
+        })?;
+
+        Ok(Self {
+            src,
+            dst,
+            seq,
+            ttl,
+            msg,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8, MAX_MSG_SIZE> {
+        let mut bytes = Vec::new();
+        self.encode(&mut bytes)
+            .expect("MAX_MSG_SIZE is too small for the message");
+
+        bytes
+    }
+
+    fn encode(&self, bytes: &mut Vec<u8, MAX_MSG_SIZE>) -> Result<(), ()> {
+        bytes.extend_from_slice(&[HEADER, PROTOCOL_VERSION, self.src.0, self.dst.0])?;
+        self.msg.encode(bytes)?;
+        bytes.extend_from_slice(&self.seq.unwrap_or(0).to_le_bytes())?;
+        bytes.extend_from_slice(&[self.ttl, FOOTER])
+    }
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy)]
 pub enum Message {
     QuerySignal,
     Signal(crate::Signal),
+    /// Controller time in milliseconds since the UNIX epoch, at the end of the transmission.
+    TimeSync(u64),
+    /// The receiver's response to a [`Message::TimeSync`].
+    SyncStatus(SyncStatus),
+    /// Time of a coordinated controller, for other controllers to coordinate their cycle with.
+    CycleSync {
+        /// Milliseconds since the UNIX epoch, at the end of the transmission.
+        time_ms: u64,
+        cycle_length_s: u16,
+    },
+    /// Sent by a coordinated controller on startup, to get a [`Message::CycleSync`].
+    QueryCycle,
+    /// A pedestrian pressed the call button on the receiver. Echoed back by the controller as ACK.
+    PedestrianCall,
+    PedestrianSignal {
+        signal: crate::PedestrianSignal,
+        /// Seconds until don't walk, for countdown displays.
+        countdown_s: u8,
+    },
+    /// A vehicle detector on the receiver got occupied or vacated.
+    Detection {
+        detector: u8,
+        occupied: bool,
+    },
+    /// Pre-emption for an emergency vehicle, sent by an authorized node. Echoed back by the
+    /// controller as ACK. See [`crate::preempt`].
+    Preempt {
+        approach: Approach,
+        /// Repeated while the vehicle approaches, `false` once it's through the intersection.
+        active: bool,
+        counter: u32,
+        mac: [u8; MAC_LEN],
+    },
+    /// A bus or tram asking for transit signal priority. Echoed back by the controller as ACK.
+    PriorityRequest {
+        class: VehicleClass,
+        approach: Approach,
+        /// Seconds until the vehicle reaches the stop line.
+        eta_s: u8,
+    },
+    /// Sent by the controller when an operator takes over or gives back control.
+    OperatingMode(OperatingMode),
+    GetConfig(ConfigKey),
+    /// The response to [`Message::GetConfig`] and the ACK of [`Message::SetConfig`], with the value
+    /// in use.
+    ConfigValue {
+        key: ConfigKey,
+        value: u32,
+    },
+    /// See [`crate::ConfigManager`] and [`crate::set_config`].
+    SetConfig {
+        key: ConfigKey,
+        value: u32,
+        counter: u32,
+        mac: [u8; MAC_LEN],
+    },
+    /// Make the changes since the last confirmation permanent. Echoed back as ACK.
+    ConfirmConfig {
+        counter: u32,
+        mac: [u8; MAC_LEN],
+    },
+    /// Start an over-the-air update of a `len` bytes long firmware, see [`crate::OtaImage`]. Echoed
+    /// back as ACK. Authenticated like [`Message::SetConfig`], as it erases the DFU partition.
+    OtaStart {
+        len: u32,
+        counter: u32,
+        mac: [u8; MAC_LEN],
+    },
+    /// Not acknowledged, the [`Message::OtaMissing`] reply to [`Message::OtaStatus`] says what's
+    /// missing.
+    OtaFragment {
+        index: u16,
+        fragment: [u8; FRAGMENT_LEN],
+    },
+    OtaStatus,
+    /// The first missing fragment and a bit mask of the missing ones from there on. None is missing
+    /// when the bit mask is 0.
+    OtaMissing {
+        first: u16,
+        bitmap: u32,
+    },
+    /// Verify and install the update. Echoed back as ACK, once the update is verified.
+    OtaFinish,
+    /// A piece of a larger payload, see [`crate::Fragmenter`].
+    Fragment(Fragment),
+    /// Sent on startup, and in reply to a hello, see [`crate::Handshake`]. Decoded whatever the
+    /// version of the sender, so its fields must never change.
+    Hello {
+        version: u8,
+        capabilities: Capabilities,
+        reply: bool,
+    },
+    /// The reply to a message the node doesn't know about.
+    Unsupported {
+        command: u8,
+    },
+    /// Sent periodically to the peer, for it to supervise the link, see [`crate::LinkSupervisor`].
+    Heartbeat {
+        /// Incremented with every heartbeat, for the peer to count the lost ones.
+        seq: u8,
+        uptime_s: u32,
+        /// The signal shown, or expected to be shown by the controller.
+        signal: crate::Signal,
+        mode: OperatingMode,
+    },
+    QueryStatus,
+    /// The reply to [`Message::QueryStatus`], also sent periodically by the receiver.
+    Status(Status),
+    /// Page through the event log, from the event numbered `from` on, see [`crate::EventLog`].
+    GetEvents {
+        from: u32,
+    },
+    /// The reply to [`Message::GetEvents`].
+    Events(EventPage),
 }
 
 impl Message {
-    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
-        if bytes.len() != MSG_SIZE || bytes[0] != HEADER || bytes[3] != FOOTER {
-            return None;
-        }
-        let command = bytes[1];
-        let payload = bytes[2];
+    /// `None` for messages we don't know about.
+    fn from_bytes(bytes: &[u8]) -> Result<Option<Self>, DecodeError> {
+        let mut fields = Fields(bytes);
+        let command = fields.u8()?;
 
-        match command {
-            0 => Some(Self::QuerySignal),
-            1 => Some(Self::Signal(crate::Signal::from_u8(payload)?)),
-            _ => None,
-        }
+        // Fields added by newer versions, at the end, are left unread.
+        Ok(Some(match command {
+            0 => Self::QuerySignal,
+            1 => Self::Signal(fields.parse(crate::Signal::from_u8)?),
+            2 => Self::TimeSync(fields.u64()?),
+            3 => Self::SyncStatus(SyncStatus {
+                state: fields.parse(SyncState::from_u8)?,
+                offset_ms: fields.i32()?,
+                drift_ppm: fields.i16()?,
+            }),
+            4 => Self::CycleSync {
+                time_ms: fields.u64()?,
+                cycle_length_s: fields.u16()?,
+            },
+            5 => Self::QueryCycle,
+            6 => Self::PedestrianCall,
+            7 => Self::PedestrianSignal {
+                signal: fields.parse(crate::PedestrianSignal::from_u8)?,
+                countdown_s: fields.u8()?,
+            },
+            8 => Self::Detection {
+                detector: fields.u8()?,
+                occupied: fields.bool()?,
+            },
+            9 => Self::Preempt {
+                approach: fields.parse(Approach::from_u8)?,
+                active: fields.bool()?,
+                counter: fields.u32()?,
+                mac: fields.array()?,
+            },
+            10 => Self::PriorityRequest {
+                class: fields.parse(VehicleClass::from_u8)?,
+                approach: fields.parse(Approach::from_u8)?,
+                eta_s: fields.u8()?,
+            },
+            11 => Self::OperatingMode(fields.parse(OperatingMode::from_u8)?),
+            12 => Self::GetConfig(fields.parse(ConfigKey::from_u8)?),
+            13 => Self::ConfigValue {
+                key: fields.parse(ConfigKey::from_u8)?,
+                value: fields.u32()?,
+            },
+            14 => Self::SetConfig {
+                key: fields.parse(ConfigKey::from_u8)?,
+                value: fields.u32()?,
+                counter: fields.u32()?,
+                mac: fields.array()?,
+            },
+            15 => Self::ConfirmConfig {
+                counter: fields.u32()?,
+                mac: fields.array()?,
+            },
+            16 => Self::OtaStart {
+                len: fields.u32()?,
+                counter: fields.u32()?,
+                mac: fields.array()?,
+            },
+            17 => Self::OtaFragment {
+                index: fields.u16()?,
+                fragment: fields.array()?,
+            },
+            18 => Self::OtaStatus,
+            19 => Self::OtaMissing {
+                first: fields.u16()?,
+                bitmap: fields.u32()?,
+            },
+            20 => Self::OtaFinish,
+            21 => Self::Fragment(Fragment {
+                transfer: fields.u8()?,
+                index: fields.u8()?,
+                len: fields.u16()?,
+                fec_group: fields.u8()?,
+                data: fields.array()?,
+            }),
+            HELLO => Self::Hello {
+                version: fields.u8()?,
+                capabilities: Capabilities(fields.u32()?),
+                reply: fields.bool()?,
+            },
+            23 => Self::Unsupported {
+                command: fields.u8()?,
+            },
+            24 => Self::Heartbeat {
+                seq: fields.u8()?,
+                uptime_s: fields.u32()?,
+                signal: fields.parse(crate::Signal::from_u8)?,
+                mode: fields.parse(OperatingMode::from_u8)?,
+            },
+            25 => Self::QueryStatus,
+            26 => Self::Status(Status {
+                supply_mv: fields.u16()?,
+                vbat_mv: fields.u16()?,
+                temperature_c: fields.u8()? as i8,
+                uptime_s: fields.u32()?,
+                reset_cause: fields.parse(ResetCause::from_u8)?,
+                rssi_dbm: fields.i16()?,
+                snr_db: fields.u8()? as i8,
+                faults: Faults(fields.u16()?),
+                signal: fields.parse(crate::Signal::from_u8)?,
+                mode: fields.parse(OperatingMode::from_u8)?,
+            }),
+            27 => Self::GetEvents {
+                from: fields.u32()?,
+            },
+            28 => Self::Events(fields.event_page()?),
+            _ => return Ok(None),
+        }))
     }
 
-    pub fn to_bytes(&self) -> [u8; MSG_SIZE] {
-        let mut bytes = [HEADER, 0, 0, FOOTER];
+    fn encode(&self, bytes: &mut Vec<u8, MAX_MSG_SIZE>) -> Result<(), ()> {
         match self {
-            Self::QuerySignal => bytes[1] = 0,
-            Self::Signal(signal) => {
-                bytes[1] = 1;
-                bytes[2] = *signal as u8;
+            Self::QuerySignal => bytes.extend_from_slice(&[0]),
+            Self::Signal(signal) => bytes.extend_from_slice(&[1, *signal as u8]),
+            Self::TimeSync(time) => {
+                bytes.extend_from_slice(&[2])?;
+                bytes.extend_from_slice(&time.to_le_bytes())
+            }
+            Self::SyncStatus(status) => {
+                bytes.extend_from_slice(&[3, status.state as u8])?;
+                bytes.extend_from_slice(&status.offset_ms.to_le_bytes())?;
+                bytes.extend_from_slice(&status.drift_ppm.to_le_bytes())
+            }
+            Self::CycleSync {
+                time_ms,
+                cycle_length_s,
+            } => {
+                bytes.extend_from_slice(&[4])?;
+                bytes.extend_from_slice(&time_ms.to_le_bytes())?;
+                bytes.extend_from_slice(&cycle_length_s.to_le_bytes())
+            }
+            Self::QueryCycle => bytes.extend_from_slice(&[5]),
+            Self::PedestrianCall => bytes.extend_from_slice(&[6]),
+            Self::PedestrianSignal {
+                signal,
+                countdown_s,
+            } => bytes.extend_from_slice(&[7, *signal as u8, *countdown_s]),
+            Self::Detection { detector, occupied } => {
+                bytes.extend_from_slice(&[8, *detector, *occupied as u8])
+            }
+            Self::Preempt {
+                approach,
+                active,
+                counter,
+                mac,
+            } => {
+                bytes.extend_from_slice(&[9, *approach as u8, *active as u8])?;
+                bytes.extend_from_slice(&counter.to_le_bytes())?;
+                bytes.extend_from_slice(mac)
+            }
+            Self::PriorityRequest {
+                class,
+                approach,
+                eta_s,
+            } => bytes.extend_from_slice(&[10, *class as u8, *approach as u8, *eta_s]),
+            Self::OperatingMode(mode) => bytes.extend_from_slice(&[11, *mode as u8]),
+            Self::GetConfig(key) => bytes.extend_from_slice(&[12, *key as u8]),
+            Self::ConfigValue { key, value } => {
+                bytes.extend_from_slice(&[13, *key as u8])?;
+                bytes.extend_from_slice(&value.to_le_bytes())
+            }
+            Self::SetConfig {
+                key,
+                value,
+                counter,
+                mac,
+            } => {
+                bytes.extend_from_slice(&[14, *key as u8])?;
+                bytes.extend_from_slice(&value.to_le_bytes())?;
+                bytes.extend_from_slice(&counter.to_le_bytes())?;
+                bytes.extend_from_slice(mac)
+            }
+            Self::ConfirmConfig { counter, mac } => {
+                bytes.extend_from_slice(&[15])?;
+                bytes.extend_from_slice(&counter.to_le_bytes())?;
+                bytes.extend_from_slice(mac)
+            }
+            Self::OtaStart { len, counter, mac } => {
+                bytes.extend_from_slice(&[16])?;
+                bytes.extend_from_slice(&len.to_le_bytes())?;
+                bytes.extend_from_slice(&counter.to_le_bytes())?;
+                bytes.extend_from_slice(mac)
+            }
+            Self::OtaFragment { index, fragment } => {
+                bytes.extend_from_slice(&[17])?;
+                bytes.extend_from_slice(&index.to_le_bytes())?;
+                bytes.extend_from_slice(fragment)
+            }
+            Self::OtaStatus => bytes.extend_from_slice(&[18]),
+            Self::OtaMissing { first, bitmap } => {
+                bytes.extend_from_slice(&[19])?;
+                bytes.extend_from_slice(&first.to_le_bytes())?;
+                bytes.extend_from_slice(&bitmap.to_le_bytes())
+            }
+            Self::OtaFinish => bytes.extend_from_slice(&[20]),
+            Self::Fragment(fragment) => {
+                bytes.extend_from_slice(&[21, fragment.transfer, fragment.index])?;
+                bytes.extend_from_slice(&fragment.len.to_le_bytes())?;
+                bytes.extend_from_slice(&[fragment.fec_group])?;
+                bytes.extend_from_slice(&fragment.data)
+            }
+            Self::Hello {
+                version,
+                capabilities,
+                reply,
+            } => {
+                bytes.extend_from_slice(&[HELLO, *version])?;
+                bytes.extend_from_slice(&capabilities.0.to_le_bytes())?;
+                bytes.extend_from_slice(&[*reply as u8])
+            }
+            Self::Unsupported { command } => bytes.extend_from_slice(&[23, *command]),
+            Self::Heartbeat {
+                seq,
+                uptime_s,
+                signal,
+                mode,
+            } => {
+                bytes.extend_from_slice(&[24, *seq])?;
+                bytes.extend_from_slice(&uptime_s.to_le_bytes())?;
+                bytes.extend_from_slice(&[*signal as u8, *mode as u8])
+            }
+            Self::QueryStatus => bytes.extend_from_slice(&[25]),
+            Self::Status(status) => {
+                bytes.extend_from_slice(&[26])?;
+                bytes.extend_from_slice(&status.supply_mv.to_le_bytes())?;
+                bytes.extend_from_slice(&status.vbat_mv.to_le_bytes())?;
+                bytes.extend_from_slice(&[status.temperature_c as u8])?;
+                bytes.extend_from_slice(&status.uptime_s.to_le_bytes())?;
+                bytes.extend_from_slice(&[status.reset_cause as u8])?;
+                bytes.extend_from_slice(&status.rssi_dbm.to_le_bytes())?;
+                bytes.extend_from_slice(&[status.snr_db as u8])?;
+                bytes.extend_from_slice(&status.faults.0.to_le_bytes())?;
+                bytes.extend_from_slice(&[status.signal as u8, status.mode as u8])
+            }
+            Self::GetEvents { from } => {
+                bytes.extend_from_slice(&[27])?;
+                bytes.extend_from_slice(&from.to_le_bytes())
+            }
+            Self::Events(page) => {
+                bytes.extend_from_slice(&[28, page.events().len() as u8])?;
+                for event in page.events() {
+                    bytes.extend_from_slice(&event.to_bytes())?;
+                }
+
+                Ok(())
             }
         }
+    }
+}
 
-        bytes
+/// Why a frame couldn't be decoded.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum DecodeError {
+    /// Not a frame of ours, or truncated.
+    Malformed,
+    /// From a node speaking another major version of the protocol.
+    IncompatibleVersion(u8),
+    /// A message we don't know about, from a node of a newer minor version.
+    UnknownMessage {
+        src: Address,
+        dst: Address,
+        command: u8,
+    },
+}
+
+/// Whether frames of protocol `version` can be decoded. Only the major versions have to match.
+pub fn is_compatible(version: u8) -> bool {
+    version >> 4 == PROTOCOL_VERSION >> 4
+}
+
+/// The fields of a message, read in order.
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let (field, rest) = self.0.split_first_chunk().ok_or(DecodeError::Malformed)?;
+        self.0 = rest;
+
+        Ok(*field)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        self.array().map(u8::from_le_bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn i16(&mut self) -> Result<i16, DecodeError> {
+        self.array().map(i16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, DecodeError> {
+        self.array().map(i32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn bool(&mut self) -> Result<bool, DecodeError> {
+        self.parse(|byte| match byte {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        })
+    }
+
+    fn event_page(&mut self) -> Result<EventPage, DecodeError> {
+        let len = self.u8()? as usize;
+        if len > EVENTS_PER_PAGE {
+            return Err(DecodeError::Malformed);
+        }
+        let mut page = EventPage::EMPTY;
+        for _ in 0..len {
+            let event = Event::from_bytes(&self.array()?).ok_or(DecodeError::Malformed)?;
+            let _ = page.push(event);
+        }
+
+        Ok(page)
+    }
+
+    /// A byte, converted with `from_u8`.
+    fn parse<T>(&mut self, from_u8: impl FnOnce(u8) -> Option<T>) -> Result<T, DecodeError> {
+        from_u8(self.u8()?).ok_or(DecodeError::Malformed)
     }
 }
 
-pub const MSG_SIZE: usize = 4;
+/// The high nibble is the major version, for incompatible changes to the format. The low one is the
+/// minor version, for new messages and new fields at the end of the existing ones, which older
+/// nodes ignore. The fields for repeaters stay last, at the end of the frame.
+pub const PROTOCOL_VERSION: u8 = 0x11;
+/// The radio's maximum payload.
+pub const MAX_MSG_SIZE: usize = 255;
 const HEADER: u8 = 117;
+const HELLO: u8 = 22;
+/// The first version with [`Packet::seq`] and [`Packet::ttl`].
+const REPEATER_VERSION: u8 = 0x11;
 const FOOTER: u8 = 255;
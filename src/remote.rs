@@ -0,0 +1,629 @@
+use defmt::{info, warn};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{Address, Approach, Config, ConfigKey, ConfigStore, Message, Storage};
+
+/// Key shared by the nodes, to authenticate config changes or pre-emption requests.
+pub type AuthKey = [u8; 16];
+pub const MAC_LEN: usize = 8;
+
+/// The config of a node, that can be changed over the air.
+///
+/// Changes are authenticated with a MAC, over a counter that must increase with every message so
+/// they can't be replayed. They're applied right away but only saved once confirmed, which must
+/// happen within [`CONFIRM_WINDOW_MS`]. Otherwise, they're rolled back, so that e.g a node moved to
+/// a frequency no one else uses, comes back.
+///
+/// The counters of pre-emption requests are kept along, see
+/// [`ConfigManager::authenticate_preemption`].
+pub struct ConfigManager<S> {
+    config: Config,
+    store: ConfigStore<S>,
+    auth_key: AuthKey,
+    /// The last counter of config messages, and then of each pre-emption source.
+    counters: [u32; 1 + MAX_PREEMPT_SOURCES],
+    /// The config to roll back to, and when.
+    rollback: Option<(Config, u64)>,
+}
+
+impl<S: Storage> ConfigManager<S> {
+    pub fn new(mut store: ConfigStore<S>, defaults: Config, auth_key: AuthKey) -> Self {
+        let config = Config::load(&mut store, defaults);
+        let counters = core::array::from_fn(|i| {
+            let mut buf = [0; 4];
+            store
+                .get(COUNTER_KEY + i as u8, COUNTER_VERSION, &mut buf)
+                .and_then(|counter| counter.try_into().ok())
+                .map_or(0, u32::from_le_bytes)
+        });
+
+        Self {
+            config,
+            store,
+            auth_key,
+            counters,
+            rollback: None,
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Handle a config message from `src` to `dst`, returning the reply, if any.
+    ///
+    /// The reply must be sent before applying the new config, as it may change the radio settings.
+    pub fn handle(
+        &mut self,
+        src: Address,
+        dst: Address,
+        msg: Message,
+        uptime_ms: u64,
+    ) -> Option<Message> {
+        match msg {
+            Message::GetConfig(key) => Some(self.value(key)),
+            Message::SetConfig {
+                key,
+                value,
+                counter,
+                mac,
+            } => {
+                let data = set_config_data(key, value, counter);
+                self.authenticate(src, dst, &data, counter, &mac)?;
+                match self.config.with(key, value) {
+                    Some(config) => {
+                        info!("{:?} set to {} by {:?}, until confirmed", key, value, src);
+                        let previous = self.rollback.map_or(self.config, |(previous, _)| previous);
+                        self.rollback = Some((previous, uptime_ms + CONFIRM_WINDOW_MS));
+                        self.config = config;
+                    }
+                    None => warn!("Invalid {:?} = {} from {:?}", key, value, src),
+                }
+
+                // ACK, with the value in use.
+                Some(self.value(key))
+            }
+            Message::ConfirmConfig { counter, mac } => {
+                let data = confirm_config_data(counter);
+                self.authenticate(src, dst, &data, counter, &mac)?;
+                if self.rollback.take().is_some() {
+                    info!("Config confirmed by {:?}", src);
+                    if let Err(e) = self.config.save(&mut self.store) {
+                        warn!("Failed to save config: {}", e);
+                    }
+                }
+
+                // ACK
+                Some(msg)
+            }
+            _ => None,
+        }
+    }
+
+    /// When an unconfirmed change is rolled back.
+    pub fn rollback_ms(&self) -> Option<u64> {
+        self.rollback.map(|(_, deadline)| deadline)
+    }
+
+    /// Roll back an unconfirmed change, if it's time. Returns whether the config changed.
+    pub fn roll_back_if_due(&mut self, uptime_ms: u64) -> bool {
+        match self.rollback {
+            Some((previous, deadline)) if uptime_ms >= deadline => {
+                warn!("Config change not confirmed, rolling back");
+                self.config = previous;
+                self.rollback = None;
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Authenticate a [`Message::Preempt`] of pre-emption source number `source`, from `src` to
+    /// `dst`. Returns whether it may be acted upon.
+    ///
+    /// Pre-emption requests are signed with their own key, for the pre-empting nodes not to be able
+    /// to change the config, and every source has its own counter.
+    pub fn authenticate_preemption(
+        &mut self,
+        auth_key: &AuthKey,
+        source: usize,
+        src: Address,
+        dst: Address,
+        msg: &Message,
+    ) -> bool {
+        let Message::Preempt {
+            approach,
+            active,
+            counter,
+            mac,
+        } = *msg
+        else {
+            return false;
+        };
+        if source >= MAX_PREEMPT_SOURCES {
+            warn!("Too many pre-emption sources, can't authenticate {:?}", src);
+            return false;
+        }
+        let data = preempt_data(approach, active, counter);
+        if !verify(auth_key, src, dst, &data, &mac) {
+            warn!("Pre-emption request from {:?} failed authentication", src);
+            return false;
+        }
+        if !self.check_counter(1 + source, counter) {
+            warn!("Replayed pre-emption request from {:?}", src);
+            return false;
+        }
+
+        true
+    }
+
+    /// Authenticate a [`Message::OtaStart`] from `src` to `dst`, like config messages as it erases
+    /// the DFU partition. Returns whether it may be acted upon.
+    pub fn authenticate_ota_start(&mut self, src: Address, dst: Address, msg: &Message) -> bool {
+        let Message::OtaStart { len, counter, mac } = *msg else {
+            return false;
+        };
+        let data = ota_start_data(len, counter);
+
+        self.authenticate(src, dst, &data, counter, &mac).is_some()
+    }
+
+    fn value(&self, key: ConfigKey) -> Message {
+        Message::ConfigValue {
+            key,
+            value: self.config.get(key),
+        }
+    }
+
+    fn authenticate(
+        &mut self,
+        src: Address,
+        dst: Address,
+        data: &[u8],
+        counter: u32,
+        mac: &[u8; MAC_LEN],
+    ) -> Option<()> {
+        if !verify(&self.auth_key, src, dst, data, mac) {
+            warn!("Config message from {:?} failed authentication", src);
+            return None;
+        }
+        if !self.check_counter(0, counter) {
+            warn!("Replayed config message from {:?}", src);
+            return None;
+        }
+
+        Some(())
+    }
+
+    /// Whether `counter` is past the last one of `counters[index]`, and then save it as the last.
+    fn check_counter(&mut self, index: usize, counter: u32) -> bool {
+        if counter <= self.counters[index] {
+            return false;
+        }
+
+        self.counters[index] = counter;
+        let key = COUNTER_KEY + index as u8;
+        if let Err(e) = self.store.set(key, COUNTER_VERSION, &counter.to_le_bytes()) {
+            warn!("Failed to save counter: {}", e);
+        }
+
+        true
+    }
+}
+
+/// A [`Message::SetConfig`] from `src` to `dst`, for the configuring node.
+pub fn set_config(
+    auth_key: &AuthKey,
+    src: Address,
+    dst: Address,
+    key: ConfigKey,
+    value: u32,
+    counter: u32,
+) -> Message {
+    let data = set_config_data(key, value, counter);
+
+    Message::SetConfig {
+        key,
+        value,
+        counter,
+        mac: sign(auth_key, src, dst, &data),
+    }
+}
+
+/// A [`Message::ConfirmConfig`] from `src` to `dst`, for the configuring node.
+pub fn confirm_config(auth_key: &AuthKey, src: Address, dst: Address, counter: u32) -> Message {
+    let data = confirm_config_data(counter);
+
+    Message::ConfirmConfig {
+        counter,
+        mac: sign(auth_key, src, dst, &data),
+    }
+}
+
+/// A [`Message::OtaStart`] from `src` to `dst`, for the updating node. It shares the counter of
+/// the config messages.
+pub fn ota_start(
+    auth_key: &AuthKey,
+    src: Address,
+    dst: Address,
+    len: u32,
+    counter: u32,
+) -> Message {
+    let data = ota_start_data(len, counter);
+
+    Message::OtaStart {
+        len,
+        counter,
+        mac: sign(auth_key, src, dst, &data),
+    }
+}
+
+/// A [`Message::Preempt`] from `src` to `dst`, for the pre-empting node.
+pub fn preempt(
+    auth_key: &AuthKey,
+    src: Address,
+    dst: Address,
+    approach: Approach,
+    active: bool,
+    counter: u32,
+) -> Message {
+    let data = preempt_data(approach, active, counter);
+
+    Message::Preempt {
+        approach,
+        active,
+        counter,
+        mac: sign(auth_key, src, dst, &data),
+    }
+}
+
+fn set_config_data(key: ConfigKey, value: u32, counter: u32) -> [u8; 10] {
+    let mut data = [b's', key as u8, 0, 0, 0, 0, 0, 0, 0, 0];
+    data[2..6].copy_from_slice(&value.to_le_bytes());
+    data[6..].copy_from_slice(&counter.to_le_bytes());
+
+    data
+}
+
+fn confirm_config_data(counter: u32) -> [u8; 5] {
+    let [c0, c1, c2, c3] = counter.to_le_bytes();
+
+    [b'c', c0, c1, c2, c3]
+}
+
+fn ota_start_data(len: u32, counter: u32) -> [u8; 9] {
+    let mut data = [b'o', 0, 0, 0, 0, 0, 0, 0, 0];
+    data[1..5].copy_from_slice(&len.to_le_bytes());
+    data[5..].copy_from_slice(&counter.to_le_bytes());
+
+    data
+}
+
+fn preempt_data(approach: Approach, active: bool, counter: u32) -> [u8; 7] {
+    let [c0, c1, c2, c3] = counter.to_le_bytes();
+
+    [b'p', approach as u8, active as u8, c0, c1, c2, c3]
+}
+
+fn sign(auth_key: &AuthKey, src: Address, dst: Address, data: &[u8]) -> [u8; MAC_LEN] {
+    let tag = hmac(auth_key, src, dst, data).finalize().into_bytes();
+    let mut mac = [0; MAC_LEN];
+    mac.copy_from_slice(&tag[..MAC_LEN]);
+
+    mac
+}
+
+fn verify(auth_key: &AuthKey, src: Address, dst: Address, data: &[u8], mac: &[u8]) -> bool {
+    hmac(auth_key, src, dst, data)
+        .verify_truncated_left(mac)
+        .is_ok()
+}
+
+fn hmac(auth_key: &AuthKey, src: Address, dst: Address, data: &[u8]) -> Hmac<Sha256> {
+    let mut hmac = Hmac::<Sha256>::new_from_slice(auth_key).expect("HMAC takes keys of any size");
+    hmac.update(&[src.0, dst.0]);
+    hmac.update(data);
+
+    hmac
+}
+
+/// Shared by all the nodes, from the file given by `CONFIG_AUTH_KEY` at build time. See
+/// `lora2traffic-cli`.
+#[cfg(feature = "stm32")]
+pub const CONFIG_AUTH_KEY: AuthKey = *include_bytes!(concat!(env!("OUT_DIR"), "/config.key"));
+/// Shared by the controllers and the nodes allowed to pre-empt them, from the file given by
+/// `PREEMPT_AUTH_KEY` at build time.
+#[cfg(feature = "stm32")]
+pub const PREEMPT_AUTH_KEY: AuthKey = *include_bytes!(concat!(env!("OUT_DIR"), "/preempt.key"));
+/// Nodes allowed to pre-empt the signal of a controller, each with its own counter.
+pub const MAX_PREEMPT_SOURCES: usize = 8;
+/// How long a config change has to be confirmed.
+pub const CONFIRM_WINDOW_MS: u64 = 60_000;
+/// Store key of the config counter, out of the range of [`ConfigKey`]. Those of the pre-emption
+/// sources follow.
+const COUNTER_KEY: u8 = 0x80;
+const COUNTER_VERSION: u8 = 1;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemStorage, Packet, DEFAULT_TTL};
+
+    const CONFIG_KEY: AuthKey = *b"config-key-12345";
+    const PREEMPT_KEY: AuthKey = *b"preempt-key-1234";
+    const SOURCE: Address = Address(3);
+    const CONTROLLER: Address = Address(1);
+    const DEFAULTS: Config = Config {
+        address: CONTROLLER,
+        frequency_hz: 434_000_000,
+        tx_power_dbm: 20,
+        failsafe_timeout_s: 0,
+        red_s: 30,
+        green_s: 30,
+    };
+
+    type Manager = ConfigManager<MemStorage<1024>>;
+
+    fn manager() -> Manager {
+        ConfigManager::new(ConfigStore::new(MemStorage::new()), DEFAULTS, CONFIG_KEY)
+    }
+
+    fn authenticate(manager: &mut Manager, source: usize, msg: Message) -> bool {
+        // Over the air.
+        let packet = Packet {
+            src: SOURCE,
+            dst: CONTROLLER,
+            seq: Some(0),
+            ttl: DEFAULT_TTL,
+            msg,
+        };
+        let msg = Packet::from_bytes(&packet.to_bytes()).unwrap().msg;
+
+        manager.authenticate_preemption(&PREEMPT_KEY, source, SOURCE, CONTROLLER, &msg)
+    }
+
+    fn request(auth_key: &AuthKey, active: bool, counter: u32) -> Message {
+        preempt(
+            auth_key,
+            SOURCE,
+            CONTROLLER,
+            Approach::Main,
+            active,
+            counter,
+        )
+    }
+
+    fn set(manager: &mut Manager, value: u32, counter: u32, uptime_ms: u64) -> Option<u32> {
+        let msg = set_config(
+            &CONFIG_KEY,
+            SOURCE,
+            CONTROLLER,
+            ConfigKey::RedTime,
+            value,
+            counter,
+        );
+        match manager.handle(SOURCE, CONTROLLER, msg, uptime_ms)? {
+            Message::ConfigValue {
+                key: ConfigKey::RedTime,
+                value,
+            } => Some(value),
+            reply => panic!("Unexpected reply {reply:?}"),
+        }
+    }
+
+    fn confirm(manager: &mut Manager, counter: u32) -> bool {
+        let msg = confirm_config(&CONFIG_KEY, SOURCE, CONTROLLER, counter);
+
+        manager.handle(SOURCE, CONTROLLER, msg, 0).is_some()
+    }
+
+    /// After a reset.
+    fn reset(manager: Manager) -> Manager {
+        ConfigManager::new(manager.store, DEFAULTS, CONFIG_KEY)
+    }
+
+    #[test]
+    fn set_config_applied() {
+        let mut manager = manager();
+        assert_eq!(set(&mut manager, 60, 1, 1_000), Some(60));
+        assert_eq!(manager.config().red_s, 60);
+        assert_eq!(manager.rollback_ms(), Some(1_000 + CONFIRM_WINDOW_MS));
+
+        // Invalid, the value in use is sent back.
+        assert_eq!(set(&mut manager, 0, 2, 2_000), Some(60));
+        assert_eq!(manager.config().red_s, 60);
+    }
+
+    #[test]
+    fn set_config_forged() {
+        let mut manager = manager();
+        let msg = set_config(&PREEMPT_KEY, SOURCE, CONTROLLER, ConfigKey::RedTime, 60, 1);
+        assert!(manager.handle(SOURCE, CONTROLLER, msg, 0).is_none());
+
+        let Message::SetConfig { counter, mac, .. } =
+            set_config(&CONFIG_KEY, SOURCE, CONTROLLER, ConfigKey::RedTime, 60, 1)
+        else {
+            unreachable!();
+        };
+        let msg = Message::SetConfig {
+            key: ConfigKey::GreenTime,
+            value: 60,
+            counter,
+            mac,
+        };
+        assert!(manager.handle(SOURCE, CONTROLLER, msg, 0).is_none());
+        // From another node.
+        let msg = set_config(&CONFIG_KEY, SOURCE, CONTROLLER, ConfigKey::RedTime, 60, 1);
+        assert!(manager.handle(Address(4), CONTROLLER, msg, 0).is_none());
+        assert_eq!(manager.config().red_s, DEFAULTS.red_s);
+        assert_eq!(manager.rollback_ms(), None);
+    }
+
+    #[test]
+    fn set_config_replayed() {
+        let mut manager = manager();
+        assert_eq!(set(&mut manager, 60, 5, 0), Some(60));
+        assert_eq!(set(&mut manager, 60, 5, 0), None);
+        assert_eq!(set(&mut manager, 45, 4, 0), None);
+        assert_eq!(manager.config().red_s, 60);
+
+        // Nor after a reset.
+        let mut manager = reset(manager);
+        assert_eq!(set(&mut manager, 45, 5, 0), None);
+        assert_eq!(set(&mut manager, 45, 6, 0), Some(45));
+    }
+
+    #[test]
+    fn rolled_back() {
+        let mut manager = manager();
+        assert_eq!(set(&mut manager, 60, 1, 0), Some(60));
+        // A second change rolls back to before the first one.
+        assert_eq!(set(&mut manager, 45, 2, 10_000), Some(45));
+        assert!(!manager.roll_back_if_due(10_000 + CONFIRM_WINDOW_MS - 1));
+        assert!(manager.roll_back_if_due(10_000 + CONFIRM_WINDOW_MS));
+        assert_eq!(manager.config().red_s, DEFAULTS.red_s);
+        assert_eq!(manager.rollback_ms(), None);
+
+        // Confirmed too late.
+        assert!(confirm(&mut manager, 3));
+        assert_eq!(reset(manager).config().red_s, DEFAULTS.red_s);
+    }
+
+    #[test]
+    fn confirmed() {
+        let mut manager = manager();
+        assert_eq!(set(&mut manager, 60, 1, 0), Some(60));
+        // Not saved until confirmed.
+        let mut manager = reset(manager);
+        assert_eq!(manager.config().red_s, DEFAULTS.red_s);
+
+        assert_eq!(set(&mut manager, 60, 2, 0), Some(60));
+        assert!(!confirm(&mut manager, 2));
+        assert!(confirm(&mut manager, 3));
+        assert_eq!(manager.rollback_ms(), None);
+        assert!(!manager.roll_back_if_due(CONFIRM_WINDOW_MS));
+        assert_eq!(reset(manager).config().red_s, 60);
+    }
+
+    #[test]
+    fn ota_start_authenticated() {
+        let mut manager = manager();
+        let msg = ota_start(&CONFIG_KEY, SOURCE, CONTROLLER, 4096, 1);
+        assert!(manager.authenticate_ota_start(SOURCE, CONTROLLER, &msg));
+        // Replayed.
+        assert!(!manager.authenticate_ota_start(SOURCE, CONTROLLER, &msg));
+        // Shares the counter of config messages.
+        assert_eq!(set(&mut manager, 60, 1, 0), None);
+        assert_eq!(set(&mut manager, 60, 2, 0), Some(60));
+        let msg = ota_start(&CONFIG_KEY, SOURCE, CONTROLLER, 4096, 2);
+        assert!(!manager.authenticate_ota_start(SOURCE, CONTROLLER, &msg));
+    }
+
+    #[test]
+    fn ota_start_forged() {
+        let mut manager = manager();
+        let msg = ota_start(&PREEMPT_KEY, SOURCE, CONTROLLER, 4096, 1);
+        assert!(!manager.authenticate_ota_start(SOURCE, CONTROLLER, &msg));
+
+        let Message::OtaStart { counter, mac, .. } =
+            ota_start(&CONFIG_KEY, SOURCE, CONTROLLER, 4096, 1)
+        else {
+            unreachable!();
+        };
+        let msg = Message::OtaStart {
+            len: 8192,
+            counter,
+            mac,
+        };
+        assert!(!manager.authenticate_ota_start(SOURCE, CONTROLLER, &msg));
+        let msg = ota_start(&CONFIG_KEY, SOURCE, CONTROLLER, 4096, 1);
+        assert!(!manager.authenticate_ota_start(Address(4), CONTROLLER, &msg));
+        assert!(manager.authenticate_ota_start(SOURCE, CONTROLLER, &msg));
+    }
+
+    #[test]
+    fn preemption() {
+        let mut manager = manager();
+        assert!(authenticate(
+            &mut manager,
+            0,
+            request(&PREEMPT_KEY, true, 1)
+        ));
+        assert!(authenticate(
+            &mut manager,
+            0,
+            request(&PREEMPT_KEY, false, 2)
+        ));
+    }
+
+    #[test]
+    fn preemption_replayed() {
+        let mut manager = manager();
+        let msg = request(&PREEMPT_KEY, true, 5);
+        assert!(authenticate(&mut manager, 0, msg));
+        assert!(!authenticate(&mut manager, 0, msg));
+        assert!(!authenticate(
+            &mut manager,
+            0,
+            request(&PREEMPT_KEY, true, 4)
+        ));
+
+        // Each source counts on its own.
+        assert!(authenticate(
+            &mut manager,
+            1,
+            request(&PREEMPT_KEY, true, 1)
+        ));
+    }
+
+    #[test]
+    fn preemption_forged() {
+        let mut manager = manager();
+        assert!(!authenticate(
+            &mut manager,
+            0,
+            request(&CONFIG_KEY, true, 1)
+        ));
+
+        let Message::Preempt { counter, mac, .. } = request(&PREEMPT_KEY, true, 1) else {
+            unreachable!();
+        };
+        let msg = Message::Preempt {
+            approach: Approach::Side,
+            active: true,
+            counter,
+            mac,
+        };
+        assert!(!authenticate(&mut manager, 0, msg));
+        assert!(!authenticate(
+            &mut manager,
+            MAX_PREEMPT_SOURCES,
+            request(&PREEMPT_KEY, true, 1)
+        ));
+
+        // Nor can a pre-emption source change the config.
+        let msg = set_config(&PREEMPT_KEY, SOURCE, CONTROLLER, ConfigKey::RedTime, 60, 1);
+        assert!(manager.handle(SOURCE, CONTROLLER, msg, 0).is_none());
+    }
+
+    #[test]
+    fn preemption_counter_saved() {
+        let mut manager = manager();
+        let msg = request(&PREEMPT_KEY, true, 7);
+        assert!(authenticate(&mut manager, 2, msg));
+        let set = set_config(&CONFIG_KEY, SOURCE, CONTROLLER, ConfigKey::RedTime, 60, 3);
+        assert!(manager.handle(SOURCE, CONTROLLER, set, 0).is_some());
+
+        // After a reset.
+        let mut manager = ConfigManager::new(manager.store, DEFAULTS, CONFIG_KEY);
+        assert!(!authenticate(&mut manager, 2, msg));
+        assert!(manager.handle(SOURCE, CONTROLLER, set, 0).is_none());
+        assert!(authenticate(
+            &mut manager,
+            2,
+            request(&PREEMPT_KEY, true, 8)
+        ));
+    }
+}
@@ -0,0 +1,333 @@
+use defmt::warn;
+use heapless::Deque;
+
+use crate::{ack_window_ms, time_on_air_us, Address, Message, Packet, ACK_ROUND_MS, ACK_SLOT_MS};
+
+/// Remembers the latest `N` packets heard, to drop the copies of repeaters.
+///
+/// They're forgotten after [`DUPLICATE_WINDOW_MS`], so that a node numbering its packets from 0
+/// again after a reset isn't mistaken for a repeater.
+pub struct Duplicates<const N: usize> {
+    /// Source, sequence number and when it was heard.
+    seen: Deque<(Address, u16, u64), N>,
+}
+
+impl<const N: usize> Duplicates<N> {
+    pub fn new() -> Self {
+        Self { seen: Deque::new() }
+    }
+
+    /// Whether `packet` was heard already. Packets without a sequence number never were.
+    pub fn check(&mut self, packet: &Packet, now_ms: u64) -> bool {
+        while let Some((_, _, at_ms)) = self.seen.front() {
+            if now_ms.saturating_sub(*at_ms) < DUPLICATE_WINDOW_MS {
+                break;
+            }
+            self.seen.pop_front();
+        }
+        let Some(seq) = packet.seq else {
+            return false;
+        };
+        let heard = |(src, s, _): &(Address, u16, u64)| *src == packet.src && *s == seq;
+        if self.seen.iter().any(heard) {
+            return true;
+        }
+
+        if self.seen.is_full() {
+            self.seen.pop_front();
+        }
+        let _ = self.seen.push_back((packet.src, seq, now_ms));
+
+        false
+    }
+}
+
+impl<const N: usize> Default for Duplicates<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Two nodes out of range of each other, whose packets a [`Repeater`] forwards, either way.
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub struct Route(pub Address, pub Address);
+
+impl Route {
+    /// Whether a packet from `src` to `dst` takes this route. Broadcasts from either end do.
+    pub fn takes(&self, src: Address, dst: Address) -> bool {
+        let other = match src {
+            src if src == self.0 => self.1,
+            src if src == self.1 => self.0,
+            _ => return false,
+        };
+
+        dst == other || dst == Address::BROADCAST
+    }
+}
+
+/// Forwards the packets of other nodes, for them to reach beyond a single hop.
+///
+/// Only the packets taking one of the routes are forwarded, or all of them without routes. Every
+/// hop takes one off the packet's TTL, and the packet isn't forwarded once it's 0.
+///
+/// Packets are forwarded in turn, a slot apart, and never in the ACK slots of others:
+/// - A broadcast is forwarded once the receivers in range (the repeater too) had their round to
+///   ACK it, which opens the round of the receivers beyond.
+/// - Packets for a single node are forwarded a slot after they're heard, after the ACK of the node
+///   if it heard them directly. Those heard while a broadcast is being ACKed, e.g the ACKs, are
+///   held until the ACKs from further away are back, the furthest repeater going first. See
+///   [`crate::ack_window_ms`].
+///
+/// That's for a chain of repeaters, a single one at every hop.
+pub struct Repeater {
+    address: Address,
+    routes: &'static [Route],
+    /// When they're due, when they were heard and the packets as heard.
+    queue: Deque<(u64, u64, Packet), QUEUE_LEN>,
+    /// Until when the packets for a single node are held.
+    hold_until_ms: u64,
+    /// When the last packet forwarded is over.
+    free_at_ms: u64,
+}
+
+impl Repeater {
+    pub fn new(address: Address, routes: &'static [Route]) -> Self {
+        Self {
+            address,
+            routes,
+            queue: Deque::new(),
+            hold_until_ms: 0,
+            free_at_ms: 0,
+        }
+    }
+
+    /// Our address changed. The packets scheduled are still forwarded.
+    pub fn set_address(&mut self, address: Address) {
+        self.address = address;
+    }
+
+    /// Schedule `packet`, whose reception ended at `now_ms`, if it's to be forwarded.
+    pub fn heard(&mut self, packet: &Packet, now_ms: u64) {
+        if !self.takes(packet) {
+            return;
+        }
+
+        let due_ms = match packet.dst {
+            Address::BROADCAST => {
+                let ttl = packet.ttl as u64;
+                let back_ms = now_ms + ttl * (2 * ACK_ROUND_MS + ACK_SLOT_MS);
+                self.hold_until_ms = self.hold_until_ms.max(back_ms);
+
+                now_ms + ACK_ROUND_MS
+            }
+            _ => (now_ms + ACK_SLOT_MS).max(self.hold_until_ms),
+        }
+        .max(self.free_at_ms);
+        if self.queue.push_back((due_ms, now_ms, *packet)).is_err() {
+            warn!(
+                "Too many packets to forward, dropping {:?} from {:?}",
+                packet.seq, packet.src
+            );
+            return;
+        }
+        self.free_at_ms = due_ms + ACK_SLOT_MS;
+    }
+
+    /// When the next packet is to be forwarded, if any.
+    pub fn next_due_ms(&self) -> Option<u64> {
+        self.queue.front().map(|(due_ms, _, _)| *due_ms)
+    }
+
+    /// The next packet to send on, if it's due at `now_ms`.
+    pub fn due(&mut self, now_ms: u64) -> Option<Packet> {
+        if self.next_due_ms()? > now_ms {
+            return None;
+        }
+        let (_, heard_ms, packet) = self.queue.pop_front()?;
+        let airtime_ms = time_on_air_us(packet.to_bytes().len()) / 1000;
+
+        self.forward(&packet, now_ms - heard_ms + airtime_ms)
+    }
+
+    /// The packet to send on, if `packet` is to be forwarded. The time syncs in it are moved on by
+    /// `hop_ms`, the time from the end of its reception to the end of its transmission.
+    pub fn forward(&self, packet: &Packet, hop_ms: u64) -> Option<Packet> {
+        if !self.takes(packet) {
+            return None;
+        }
+
+        let msg = match packet.msg {
+            Message::TimeSync(time_ms) => Message::TimeSync(time_ms + hop_ms),
+            Message::CycleSync {
+                time_ms,
+                cycle_length_s,
+            } => Message::CycleSync {
+                time_ms: time_ms + hop_ms,
+                cycle_length_s,
+            },
+            msg => msg,
+        };
+
+        Some(Packet {
+            ttl: packet.ttl - 1,
+            msg,
+            ..*packet
+        })
+    }
+
+    fn takes(&self, packet: &Packet) -> bool {
+        if packet.src == self.address
+            || packet.dst == self.address
+            || packet.seq.is_none()
+            || packet.ttl == 0
+        {
+            return false;
+        }
+
+        self.routes.is_empty()
+            || self
+                .routes
+                .iter()
+                .any(|route| route.takes(packet.src, packet.dst))
+    }
+}
+
+/// The TTL of new packets, which then reach three hops away.
+pub const DEFAULT_TTL: u8 = 2;
+/// Packets waiting to be forwarded, e.g the ACKs of all the receivers.
+const QUEUE_LEN: usize = 8;
+/// Copies of a packet come within the ACK window of a broadcast, as long as repeaters hold them.
+pub const DUPLICATE_WINDOW_MS: u64 = ack_window_ms(DEFAULT_TTL);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Signal, DEFAULT_TTL};
+
+    const CONTROLLER: Address = Address(1);
+    const RECEIVER: Address = Address(2);
+
+    fn packet(src: Address, dst: Address, ttl: u8, msg: Message) -> Packet {
+        Packet {
+            src,
+            dst,
+            seq: Some(7),
+            ttl,
+            msg,
+        }
+    }
+
+    fn broadcast(ttl: u8) -> Packet {
+        let msg = Message::Signal(Signal::Green);
+        packet(CONTROLLER, Address::BROADCAST, ttl, msg)
+    }
+
+    fn ack() -> Packet {
+        packet(
+            RECEIVER,
+            CONTROLLER,
+            DEFAULT_TTL,
+            Message::Signal(Signal::Green),
+        )
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut duplicates = Duplicates::<2>::new();
+        let packet = ack();
+        assert!(!duplicates.check(&packet, 0));
+        assert!(duplicates.check(&packet, 1_000));
+        assert!(!duplicates.check(
+            &Packet {
+                seq: None,
+                ..packet
+            },
+            1_000
+        ));
+        assert!(!duplicates.check(&packet, DUPLICATE_WINDOW_MS));
+    }
+
+    #[test]
+    fn readdressed() {
+        let mut repeater = Repeater::new(Address(3), &[]);
+        let to_4 = packet(CONTROLLER, Address(4), 2, ack().msg);
+        assert!(repeater.forward(&to_4, 0).is_some());
+
+        repeater.set_address(Address(4));
+        assert!(repeater.forward(&to_4, 0).is_none());
+        let to_3 = packet(CONTROLLER, Address(3), 2, ack().msg);
+        assert!(repeater.forward(&to_3, 0).is_some());
+    }
+
+    #[test]
+    fn forward() {
+        let repeater = Repeater::new(Address(3), &[Route(CONTROLLER, RECEIVER)]);
+        let sync = packet(CONTROLLER, Address::BROADCAST, 1, Message::TimeSync(1_000));
+        let forwarded = repeater.forward(&sync, 250).unwrap();
+        assert_eq!(forwarded.ttl, 0);
+        assert!(matches!(forwarded.msg, Message::TimeSync(1_250)));
+        assert!(repeater.forward(&forwarded, 250).is_none());
+
+        // Not routed, or ours.
+        assert!(repeater
+            .forward(&packet(Address(4), RECEIVER, 2, ack().msg), 0)
+            .is_none());
+        assert!(repeater
+            .forward(&packet(Address(3), RECEIVER, 2, ack().msg), 0)
+            .is_none());
+    }
+
+    #[test]
+    fn broadcast_after_acks() {
+        let mut repeater = Repeater::new(Address(3), &[]);
+        repeater.heard(&broadcast(DEFAULT_TTL), 0);
+        assert_eq!(repeater.next_due_ms(), Some(ACK_ROUND_MS));
+        assert!(repeater.due(ACK_ROUND_MS - 1).is_none());
+        assert_eq!(repeater.due(ACK_ROUND_MS).unwrap().ttl, DEFAULT_TTL - 1);
+        assert!(repeater.due(u64::MAX).is_none());
+    }
+
+    #[test]
+    fn unicast_after_a_slot() {
+        let mut repeater = Repeater::new(Address(3), &[]);
+        repeater.heard(&ack(), 1_000);
+        repeater.heard(&ack(), 2_000);
+        assert_eq!(repeater.next_due_ms(), Some(1_000 + ACK_SLOT_MS));
+        assert!(repeater.due(1_000 + ACK_SLOT_MS).is_some());
+
+        // In turn.
+        assert_eq!(repeater.next_due_ms(), Some(1_000 + 2 * ACK_SLOT_MS));
+    }
+
+    #[test]
+    fn acks_held_until_back() {
+        let mut repeater = Repeater::new(Address(3), &[]);
+        repeater.heard(&broadcast(1), 0);
+        repeater.heard(&ack(), 1_000);
+        assert!(repeater.due(ACK_ROUND_MS).is_some());
+
+        // Once the receivers beyond had their round.
+        let back_ms = 2 * ACK_ROUND_MS + ACK_SLOT_MS;
+        assert_eq!(repeater.next_due_ms(), Some(back_ms));
+    }
+
+    #[test]
+    fn furthest_back_first() {
+        // The controller's broadcast reaches `near`, which forwards it to `far`.
+        let mut near = Repeater::new(Address(3), &[]);
+        let mut far = Repeater::new(Address(4), &[]);
+        near.heard(&broadcast(DEFAULT_TTL), 0);
+        near.heard(&ack(), 1_000);
+        let forwarded = near.due(ACK_ROUND_MS).unwrap();
+        let heard_ms = ACK_ROUND_MS + ACK_SLOT_MS;
+        far.heard(&forwarded, heard_ms);
+        far.heard(&ack(), heard_ms + 1_000);
+        assert!(far.due(heard_ms + ACK_ROUND_MS).is_some());
+
+        // The ACKs of `far` come back in a round, before `near` brings all of them back.
+        let far_ack_ms = far.next_due_ms().unwrap();
+        let near_ack_ms = near.next_due_ms().unwrap();
+        assert!(far_ack_ms + ACK_ROUND_MS <= near_ack_ms);
+        assert!(near_ack_ms + ACK_ROUND_MS <= ack_window_ms(DEFAULT_TTL));
+    }
+}
@@ -0,0 +1,44 @@
+use defmt::warn;
+use embassy_stm32::rtc::Rtc;
+
+use crate::SavedState;
+
+/// Read the RTC time, in milliseconds since the UNIX epoch.
+pub fn read_rtc(rtc: &Rtc) -> Option<u64> {
+    match rtc.now() {
+        Ok(now) => {
+            let now: chrono::NaiveDateTime = now.into();
+
+            u64::try_from(now.and_utc().timestamp_millis()).ok()
+        }
+        Err(e) => {
+            warn!("Failed to read RTC: {}", e);
+
+            None
+        }
+    }
+}
+
+/// Set the RTC time, given in milliseconds since the UNIX epoch.
+pub fn set_rtc(rtc: &mut Rtc, epoch_ms: u64) {
+    let Some(time) = chrono::DateTime::from_timestamp_millis(epoch_ms as i64) else {
+        warn!("Invalid time: {}", epoch_ms);
+        return;
+    };
+    if let Err(e) = rtc.set_datetime(time.naive_utc().into()) {
+        warn!("Failed to set RTC: {}", e);
+    }
+}
+
+/// Save `state` in the RTC domain's backup registers, which survive resets.
+pub fn save_state(rtc: &Rtc, state: SavedState) {
+    rtc.write_backup_register(STATE_REGISTER, state.to_u32());
+}
+
+/// The state saved by [`save_state`] before the last reset, if any.
+pub fn load_state(rtc: &Rtc) -> Option<SavedState> {
+    rtc.read_backup_register(STATE_REGISTER)
+        .and_then(SavedState::from_u32)
+}
+
+const STATE_REGISTER: usize = 0;
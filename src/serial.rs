@@ -0,0 +1,474 @@
+use crc::{Crc, CRC_16_IBM_3740};
+use heapless::Vec;
+
+use crate::{Address, ConfigKey, DecodeError, Packet, MAX_MSG_SIZE};
+
+/// Sent by the host to the gateway, over the serial port.
+#[derive(defmt::Format, Debug, Clone, Copy)]
+pub enum HostMessage {
+    /// Send the packet's message to its destination. The gateway sends it from its own address,
+    /// with its own sequence number. Answered with [`GatewayMessage::Sent`].
+    Send(Packet),
+    QueryStatus,
+    GetConfig(ConfigKey),
+    /// Change a setting of the gateway, which is saved and applied at once.
+    SetConfig {
+        key: ConfigKey,
+        value: u32,
+    },
+}
+
+/// Sent by the gateway to the host, over the serial port.
+#[derive(defmt::Format, Debug, Clone, Copy)]
+pub enum GatewayMessage {
+    Sent,
+    /// A packet for the gateway, or broadcast. Sent whenever one is received.
+    Received(Reception),
+    Status(GatewayStatus),
+    /// The reply to [`HostMessage::GetConfig`] and [`HostMessage::SetConfig`].
+    ConfigValue {
+        key: ConfigKey,
+        value: u32,
+    },
+    Error(GatewayError),
+}
+
+/// A packet received by the gateway.
+#[derive(defmt::Format, Debug, Clone, Copy)]
+pub struct Reception {
+    pub rssi_dbm: i16,
+    pub snr_db: i16,
+    pub packet: Packet,
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct GatewayStatus {
+    /// The gateway's [`crate::PROTOCOL_VERSION`].
+    pub version: u8,
+    pub address: Address,
+    pub frequency_hz: u32,
+    pub tx_power_dbm: i8,
+    pub uptime_s: u32,
+    /// Packets received, sent and the radio errors, since startup.
+    pub received: u32,
+    pub sent: u32,
+    pub errors: u32,
+}
+
+/// Why the gateway couldn't do what the host asked.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum GatewayError {
+    /// The frame was corrupt, or not understood.
+    Malformed = 0,
+    Radio = 1,
+    InvalidConfig = 2,
+    /// The setting was applied, but not saved.
+    Store = 3,
+}
+
+impl GatewayError {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        [
+            Self::Malformed,
+            Self::Radio,
+            Self::InvalidConfig,
+            Self::Store,
+        ]
+        .into_iter()
+        .find(|e| *e as u8 == byte)
+    }
+}
+
+/// Why a serial frame couldn't be decoded.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum SerialError {
+    /// Truncated, with a wrong CRC, or an unknown command.
+    Malformed,
+    /// The packet in the frame couldn't be decoded.
+    Packet(DecodeError),
+}
+
+impl HostMessage {
+    pub fn to_frame(&self) -> Frame {
+        let mut bytes = Vec::<u8, MAX_FRAME_LEN>::new();
+        let _ = match self {
+            Self::Send(packet) => {
+                let _ = bytes.push(0);
+                bytes.extend_from_slice(&packet.to_bytes())
+            }
+            Self::QueryStatus => bytes.extend_from_slice(&[1]),
+            Self::GetConfig(key) => bytes.extend_from_slice(&[2, *key as u8]),
+            Self::SetConfig { key, value } => {
+                let _ = bytes.extend_from_slice(&[3, *key as u8]);
+                bytes.extend_from_slice(&value.to_le_bytes())
+            }
+        };
+
+        encode_frame(&bytes)
+    }
+
+    /// Decodes a frame from [`SlipDecoder::push`].
+    pub fn from_frame(frame: &[u8]) -> Result<Self, SerialError> {
+        let msg = match check_crc(frame)? {
+            [0, packet @ ..] => {
+                Self::Send(Packet::from_bytes(packet).map_err(SerialError::Packet)?)
+            }
+            [1] => Self::QueryStatus,
+            [2, key] => Self::GetConfig(config_key(*key)?),
+            [3, key, v0, v1, v2, v3] => Self::SetConfig {
+                key: config_key(*key)?,
+                value: u32::from_le_bytes([*v0, *v1, *v2, *v3]),
+            },
+            _ => return Err(SerialError::Malformed),
+        };
+
+        Ok(msg)
+    }
+}
+
+impl GatewayMessage {
+    pub fn to_frame(&self) -> Frame {
+        let mut bytes = Vec::<u8, MAX_FRAME_LEN>::new();
+        let _ = match self {
+            Self::Sent => bytes.extend_from_slice(&[0x80]),
+            Self::Received(reception) => {
+                let _ = bytes.push(0x81);
+                let _ = bytes.extend_from_slice(&reception.rssi_dbm.to_le_bytes());
+                let _ = bytes.extend_from_slice(&reception.snr_db.to_le_bytes());
+                bytes.extend_from_slice(&reception.packet.to_bytes())
+            }
+            Self::Status(status) => {
+                let _ = bytes.extend_from_slice(&[
+                    0x82,
+                    status.version,
+                    status.address.0,
+                    status.tx_power_dbm as u8,
+                ]);
+                let _ = bytes.extend_from_slice(&status.frequency_hz.to_le_bytes());
+                let _ = bytes.extend_from_slice(&status.uptime_s.to_le_bytes());
+                let _ = bytes.extend_from_slice(&status.received.to_le_bytes());
+                let _ = bytes.extend_from_slice(&status.sent.to_le_bytes());
+                bytes.extend_from_slice(&status.errors.to_le_bytes())
+            }
+            Self::ConfigValue { key, value } => {
+                let _ = bytes.extend_from_slice(&[0x83, *key as u8]);
+                bytes.extend_from_slice(&value.to_le_bytes())
+            }
+            Self::Error(e) => bytes.extend_from_slice(&[0x84, *e as u8]),
+        };
+
+        encode_frame(&bytes)
+    }
+
+    /// Decodes a frame from [`SlipDecoder::push`].
+    pub fn from_frame(frame: &[u8]) -> Result<Self, SerialError> {
+        let msg = match check_crc(frame)? {
+            [0x80] => Self::Sent,
+            [0x81, r0, r1, s0, s1, packet @ ..] => Self::Received(Reception {
+                rssi_dbm: i16::from_le_bytes([*r0, *r1]),
+                snr_db: i16::from_le_bytes([*s0, *s1]),
+                packet: Packet::from_bytes(packet).map_err(SerialError::Packet)?,
+            }),
+            [0x82, version, address, tx_power, rest @ ..] if rest.len() == 5 * 4 => {
+                let word = |i: usize| u32::from_le_bytes([0, 1, 2, 3].map(|j| rest[i * 4 + j]));
+                Self::Status(GatewayStatus {
+                    version: *version,
+                    address: Address(*address),
+                    frequency_hz: word(0),
+                    tx_power_dbm: *tx_power as i8,
+                    uptime_s: word(1),
+                    received: word(2),
+                    sent: word(3),
+                    errors: word(4),
+                })
+            }
+            [0x83, key, v0, v1, v2, v3] => Self::ConfigValue {
+                key: config_key(*key)?,
+                value: u32::from_le_bytes([*v0, *v1, *v2, *v3]),
+            },
+            [0x84, e] => Self::Error(GatewayError::from_u8(*e).ok_or(SerialError::Malformed)?),
+            _ => return Err(SerialError::Malformed),
+        };
+
+        Ok(msg)
+    }
+}
+
+/// A message framed for the serial port: its bytes and their CRC, SLIP encoded (RFC 1055).
+pub type Frame = Vec<u8, MAX_SLIP_LEN>;
+
+fn encode_frame(bytes: &[u8]) -> Frame {
+    let crc = CRC.checksum(bytes).to_le_bytes();
+    // Starting with an END flushes any line noise received before.
+    let mut frame = Frame::new();
+    let _ = frame.push(SLIP_END);
+    for byte in bytes.iter().chain(&crc) {
+        let escaped: &[u8] = match *byte {
+            SLIP_END => &[SLIP_ESC, SLIP_ESC_END],
+            SLIP_ESC => &[SLIP_ESC, SLIP_ESC_ESC],
+            _ => core::slice::from_ref(byte),
+        };
+        let _ = frame.extend_from_slice(escaped);
+    }
+    let _ = frame.push(SLIP_END);
+
+    frame
+}
+
+/// The bytes of `frame`, if its CRC is right.
+fn check_crc(frame: &[u8]) -> Result<&[u8], SerialError> {
+    let [bytes @ .., c0, c1] = frame else {
+        return Err(SerialError::Malformed);
+    };
+    if CRC.checksum(bytes) != u16::from_le_bytes([*c0, *c1]) {
+        return Err(SerialError::Malformed);
+    }
+
+    Ok(bytes)
+}
+
+fn config_key(byte: u8) -> Result<ConfigKey, SerialError> {
+    ConfigKey::from_u8(byte).ok_or(SerialError::Malformed)
+}
+
+/// Splits the bytes read from the serial port into SLIP frames.
+pub struct SlipDecoder {
+    frame: Vec<u8, MAX_FRAME_LEN>,
+    escaped: bool,
+    /// The frame is dropped at its end, it's too long or badly escaped.
+    invalid: bool,
+    /// The frame was returned, the next byte starts another one.
+    done: bool,
+}
+
+impl SlipDecoder {
+    pub fn new() -> Self {
+        Self {
+            frame: Vec::new(),
+            escaped: false,
+            invalid: false,
+            done: false,
+        }
+    }
+
+    /// Decode the next byte read. Returns the frame it ends, if any, to decode with
+    /// [`HostMessage::from_frame`] or [`GatewayMessage::from_frame`].
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        if self.done {
+            self.frame.clear();
+            self.done = false;
+        }
+        if byte == SLIP_END {
+            let valid = !self.invalid && !self.escaped && !self.frame.is_empty();
+            (self.escaped, self.invalid, self.done) = (false, false, true);
+
+            return valid.then_some(&self.frame[..]);
+        }
+
+        let byte = match (self.escaped, byte) {
+            (false, SLIP_ESC) => {
+                self.escaped = true;
+                return None;
+            }
+            (false, byte) => byte,
+            (true, SLIP_ESC_END) => SLIP_END,
+            (true, SLIP_ESC_ESC) => SLIP_ESC,
+            (true, byte) => {
+                self.invalid = true;
+                byte
+            }
+        };
+        self.escaped = false;
+        if self.frame.push(byte).is_err() {
+            self.invalid = true;
+        }
+
+        None
+    }
+}
+
+impl Default for SlipDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The default address of the gateway, the last one before broadcast.
+pub const GATEWAY_ADDRESS: Address = Address(254);
+/// The baud rate of the gateway's serial port. 8 data bits, no parity and 1 stop bit.
+pub const GATEWAY_BAUD_RATE: u32 = 115_200;
+/// The longest message, a [`GatewayMessage::Received`] packet, and its CRC.
+pub const MAX_FRAME_LEN: usize = 5 + MAX_MSG_SIZE + 2;
+/// The longest frame, with every byte escaped.
+pub const MAX_SLIP_LEN: usize = 2 * MAX_FRAME_LEN + 2;
+const SLIP_END: u8 = 0xc0;
+const SLIP_ESC: u8 = 0xdb;
+const SLIP_ESC_END: u8 = 0xdc;
+const SLIP_ESC_ESC: u8 = 0xdd;
+const CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Event, EventKind, EventPage, Message, ResetCause, Signal};
+
+    /// The frames in `bytes`.
+    fn frames(bytes: &[u8]) -> std::vec::Vec<std::vec::Vec<u8>> {
+        let mut decoder = SlipDecoder::new();
+        bytes
+            .iter()
+            .filter_map(|byte| decoder.push(*byte).map(<[u8]>::to_vec))
+            .collect()
+    }
+
+    /// The only frame in `frame`, unescaped.
+    fn unescape(frame: &[u8]) -> std::vec::Vec<u8> {
+        let [frame] = &frames(frame)[..] else {
+            panic!("not one frame");
+        };
+
+        frame.clone()
+    }
+
+    fn packet(msg: Message) -> Packet {
+        Packet {
+            src: Address(0xc0),
+            dst: Address(0xdb),
+            seq: Some(0xc0db),
+            ttl: 2,
+            msg,
+        }
+    }
+
+    /// A long packet, full of bytes to escape.
+    fn events() -> Packet {
+        let mut page = EventPage::EMPTY;
+        let event = Event {
+            seq: 0xc0db_c0db,
+            time_s: 0xdbc0_dbc0,
+            kind: EventKind::Reset(ResetCause::Unknown),
+        };
+        while page.push(event).is_ok() {}
+
+        packet(Message::Events(page))
+    }
+
+    #[test]
+    fn host_messages() {
+        let messages = [
+            HostMessage::Send(packet(Message::Signal(Signal::Green))),
+            HostMessage::Send(events()),
+            HostMessage::QueryStatus,
+            HostMessage::GetConfig(ConfigKey::TxPower),
+            HostMessage::SetConfig {
+                key: ConfigKey::Frequency,
+                value: 0xc0db_c0db,
+            },
+        ];
+        for msg in messages {
+            let frame = msg.to_frame();
+            let decoded = HostMessage::from_frame(&unescape(&frame)).unwrap();
+            assert_eq!(decoded.to_frame(), frame, "{msg:?}");
+        }
+    }
+
+    #[test]
+    fn gateway_messages() {
+        let status = GatewayStatus {
+            version: 0x11,
+            address: GATEWAY_ADDRESS,
+            frequency_hz: 868_100_000,
+            tx_power_dbm: -5,
+            uptime_s: 0xc0c0,
+            received: 1,
+            sent: 2,
+            errors: 3,
+        };
+        let messages = [
+            GatewayMessage::Sent,
+            GatewayMessage::Received(Reception {
+                rssi_dbm: -120,
+                snr_db: -15,
+                packet: events(),
+            }),
+            GatewayMessage::Status(status),
+            GatewayMessage::ConfigValue {
+                key: ConfigKey::Address,
+                value: 0xdb,
+            },
+            GatewayMessage::Error(GatewayError::Store),
+        ];
+        for msg in messages {
+            let frame = msg.to_frame();
+            let decoded = GatewayMessage::from_frame(&unescape(&frame)).unwrap();
+            assert_eq!(decoded.to_frame(), frame, "{msg:?}");
+        }
+    }
+
+    #[test]
+    fn escaping() {
+        let msg = HostMessage::SetConfig {
+            key: ConfigKey::Frequency,
+            value: u32::from_le_bytes([SLIP_END, SLIP_ESC, SLIP_ESC_END, SLIP_END]),
+        };
+        let frame = msg.to_frame();
+        assert_eq!(frame[0], SLIP_END);
+        assert_eq!(frame.last(), Some(&SLIP_END));
+        // After the start, the command and the key.
+        assert_eq!(
+            frame[3..10],
+            [
+                SLIP_ESC,
+                SLIP_ESC_END,
+                SLIP_ESC,
+                SLIP_ESC_ESC,
+                SLIP_ESC_END,
+                SLIP_ESC,
+                SLIP_ESC_END
+            ]
+        );
+        assert!(!frame[1..frame.len() - 1].contains(&SLIP_END));
+        assert_eq!(
+            unescape(&frame)[..6],
+            [
+                3,
+                ConfigKey::Frequency as u8,
+                SLIP_END,
+                SLIP_ESC,
+                SLIP_ESC_END,
+                SLIP_END
+            ]
+        );
+    }
+
+    #[test]
+    fn dropped() {
+        // Line noise, a bad escape and a frame too long, before a good one.
+        let mut bytes = std::vec![1, 2, SLIP_END, 5, SLIP_ESC, 7, SLIP_END];
+        bytes.extend([9; MAX_FRAME_LEN + 1]);
+        bytes.extend_from_slice(&HostMessage::QueryStatus.to_frame());
+        let frames = frames(&bytes);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            HostMessage::from_frame(&frames[0]).err(),
+            Some(SerialError::Malformed)
+        );
+        assert!(matches!(
+            HostMessage::from_frame(&frames[1]),
+            Ok(HostMessage::QueryStatus)
+        ));
+
+        // A bad CRC.
+        let mut frame = unescape(&GatewayMessage::Sent.to_frame());
+        frame[0] ^= 1;
+        assert_eq!(
+            GatewayMessage::from_frame(&frame).err(),
+            Some(SerialError::Malformed)
+        );
+        assert_eq!(
+            GatewayMessage::from_frame(&[]).err(),
+            Some(SerialError::Malformed)
+        );
+    }
+}
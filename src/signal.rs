@@ -1,4 +1,4 @@
-#[derive(defmt::Format, Clone, Copy, PartialEq)]
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum Signal {
     Red = b'r',
@@ -42,3 +42,24 @@ impl Default for Signal {
         Self::Off
     }
 }
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Default)]
+#[repr(u8)]
+pub enum PedestrianSignal {
+    Walk = b'w',
+    /// Clearance: pedestrians who haven't started crossing must not start.
+    FlashingDontWalk = b'f',
+    #[default]
+    DontWalk = b'd',
+}
+
+impl PedestrianSignal {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            b'w' => Some(Self::Walk),
+            b'f' => Some(Self::FlashingDontWalk),
+            b'd' => Some(Self::DontWalk),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::Signal;
+
+/// What the receiver shows after a (re)start, before it accepts commands from the controller.
+///
+/// Traffic is first warned by flashing yellow, then stopped by all-red for a minimum time, so the
+/// controller takes over from a safe state. The lamp test, cycling through all the lights, only
+/// runs for maintenance, as it shows green to whoever is there.
+#[derive(defmt::Format, Clone, Copy)]
+pub struct StartupPolicy {
+    pub flash_s: u16,
+    /// Should never be zero.
+    pub all_red_s: u16,
+}
+
+impl StartupPolicy {
+    /// The lights to show `elapsed_ms` after the start, or `None` once it's done.
+    pub fn lights(&self, lamp_test: bool, elapsed_ms: u64) -> Option<Signal> {
+        let mut elapsed_ms = elapsed_ms;
+        if lamp_test {
+            let step = (elapsed_ms / LAMP_TEST_STEP_MS) as usize;
+            if let Some(signal) = LAMP_TEST.get(step) {
+                return Some(*signal);
+            }
+            elapsed_ms -= LAMP_TEST.len() as u64 * LAMP_TEST_STEP_MS;
+        }
+
+        let flash_ms = self.flash_s as u64 * 1000;
+        if elapsed_ms < flash_ms {
+            let on = (elapsed_ms / FLASH_HALF_PERIOD_MS).is_multiple_of(2);
+
+            return Some(if on { Signal::Yellow } else { Signal::Off });
+        }
+        elapsed_ms -= flash_ms;
+
+        (elapsed_ms < self.all_red_s as u64 * 1000).then_some(Signal::Red)
+    }
+}
+
+const LAMP_TEST: [Signal; 3] = [Signal::Red, Signal::Yellow, Signal::Green];
+const LAMP_TEST_STEP_MS: u64 = 1_000;
+const FLASH_HALF_PERIOD_MS: u64 = 500;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: StartupPolicy = StartupPolicy {
+        flash_s: 2,
+        all_red_s: 10,
+    };
+
+    /// The lights shown, with when they changed, and when commands are accepted.
+    fn run(lamp_test: bool) -> (Vec<(u64, Signal)>, u64) {
+        let mut lights: Vec<(u64, Signal)> = Vec::new();
+        // As often as the receiver checks.
+        for elapsed_ms in (0..).step_by(100) {
+            let Some(signal) = POLICY.lights(lamp_test, elapsed_ms) else {
+                return (lights, elapsed_ms);
+            };
+            if lights.last().is_none_or(|(_, last)| *last != signal) {
+                lights.push((elapsed_ms, signal));
+            }
+        }
+        unreachable!()
+    }
+
+    const SEQUENCE: [(u64, Signal); 5] = [
+        (0, Signal::Yellow),
+        (500, Signal::Off),
+        (1_000, Signal::Yellow),
+        (1_500, Signal::Off),
+        (2_000, Signal::Red),
+    ];
+
+    #[test]
+    fn without_lamp_test() {
+        let (lights, accepted_ms) = run(false);
+        assert_eq!(lights, SEQUENCE);
+        // All-red for the minimum time first.
+        assert_eq!(accepted_ms, 12_000);
+    }
+
+    #[test]
+    fn with_lamp_test() {
+        let (lights, accepted_ms) = run(true);
+        let lamp_test = [
+            (0, Signal::Red),
+            (1_000, Signal::Yellow),
+            (2_000, Signal::Green),
+        ];
+        let sequence = SEQUENCE.map(|(ms, signal)| (3_000 + ms, signal));
+        assert_eq!(lights, [&lamp_test[..], &sequence[..]].concat());
+        assert_eq!(accepted_ms, 15_000);
+    }
+}
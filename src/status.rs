@@ -0,0 +1,130 @@
+use crate::{OperatingMode, Signal};
+
+/// The health of a light, reported to the controller.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct Status {
+    /// VDDA, from VREFINT.
+    pub supply_mv: u16,
+    pub vbat_mv: u16,
+    /// Of the MCU.
+    pub temperature_c: i8,
+    pub uptime_s: u32,
+    pub reset_cause: ResetCause,
+    /// Of the packets from the controller, averaged over the last few.
+    pub rssi_dbm: i16,
+    pub snr_db: i8,
+    pub faults: Faults,
+    pub signal: Signal,
+    pub mode: OperatingMode,
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ResetCause {
+    /// Or a brown out, which the MCU doesn't tell apart.
+    PowerOn,
+    Pin,
+    Software,
+    IndependentWatchdog,
+    WindowWatchdog,
+    LowPower,
+    OptionByteLoad,
+    Unknown,
+}
+
+impl ResetCause {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::PowerOn),
+            1 => Some(Self::Pin),
+            2 => Some(Self::Software),
+            3 => Some(Self::IndependentWatchdog),
+            4 => Some(Self::WindowWatchdog),
+            5 => Some(Self::LowPower),
+            6 => Some(Self::OptionByteLoad),
+            7 => Some(Self::Unknown),
+            _ => None,
+        }
+    }
+}
+
+/// Problems a light knows about.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Faults(pub u16);
+
+impl Faults {
+    pub const NONE: Self = Self(0);
+    pub const LOW_VOLTAGE: Self = Self(1 << 0);
+    pub const OVER_TEMPERATURE: Self = Self(1 << 1);
+    pub const RADIO: Self = Self(1 << 2);
+    /// Flashing, having lost the controller.
+    pub const FAILSAFE: Self = Self(1 << 3);
+    /// A config change is waiting for confirmation.
+    pub const UNCONFIRMED_CONFIG: Self = Self(1 << 4);
+    /// The last reset wasn't asked for.
+    pub const UNEXPECTED_RESET: Self = Self(1 << 5);
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn set(&mut self, fault: Self, active: bool) {
+        if active {
+            self.0 |= fault.0;
+        } else {
+            self.0 &= !fault.0;
+        }
+    }
+
+    pub const fn contains(self, fault: Self) -> bool {
+        self.0 & fault.0 == fault.0
+    }
+
+    /// The faults of the measurements and of the reset cause.
+    pub fn from_health(supply_mv: u16, temperature_c: i8, reset_cause: ResetCause) -> Self {
+        let mut faults = Self::NONE;
+        faults.set(Self::LOW_VOLTAGE, supply_mv < LOW_VOLTAGE_MV);
+        faults.set(Self::OVER_TEMPERATURE, temperature_c > MAX_TEMPERATURE_C);
+        let unexpected = matches!(
+            reset_cause,
+            ResetCause::IndependentWatchdog | ResetCause::WindowWatchdog
+        );
+        faults.set(Self::UNEXPECTED_RESET, unexpected);
+
+        faults
+    }
+}
+
+/// VDDA from a VREFINT reading and its factory calibration, taken at 3.3 V.
+pub fn supply_mv(vrefint: u16, vrefint_cal: u16) -> u16 {
+    match vrefint {
+        0 => 0,
+        _ => (CAL_SUPPLY_MV * vrefint_cal as u32 / vrefint as u32) as u16,
+    }
+}
+
+/// VBAT from a reading of its channel, which is a third of it.
+pub fn vbat_mv(vbat: u16, supply_mv: u16) -> u16 {
+    (vbat as u32 * supply_mv as u32 * 3 / ADC_MAX) as u16
+}
+
+/// The temperature from a reading of the sensor, and its factory calibration at 30 and 130 °C.
+pub fn temperature_c(sensor: u16, supply_mv: u16, ts_cal1: u16, ts_cal2: u16) -> i8 {
+    if ts_cal2 <= ts_cal1 {
+        return i8::MAX;
+    }
+    // The calibration was taken at 3.3 V.
+    let sensor = sensor as i32 * supply_mv as i32 / CAL_SUPPLY_MV as i32;
+    let celsius = (sensor - ts_cal1 as i32) * 100 / (ts_cal2 - ts_cal1) as i32 + 30;
+
+    celsius.clamp(i8::MIN as i32, i8::MAX as i32) as i8
+}
+
+/// How often the receiver reports its status, unasked.
+pub const STATUS_INTERVAL_S: u64 = 15 * 60;
+const CAL_SUPPLY_MV: u32 = 3300;
+const ADC_MAX: u32 = 4095;
+/// Below it, the radio loses output power, and a brown out reset isn't far.
+const LOW_VOLTAGE_MV: u16 = 2000;
+/// Close to the top of the MCU's operating range.
+const MAX_TEMPERATURE_C: i8 = 80;
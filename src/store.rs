@@ -0,0 +1,459 @@
+use crc::{Crc, CRC_32_ISO_HDLC};
+use defmt::{info, warn};
+
+/// Where a [`ConfigStore`] keeps its records.
+///
+/// The storage is made of two [`Storage::PAGE_SIZE`]d pages, that must be erased (to `0xff`) before
+/// they can be written again. Writes are [`Storage::WRITE_SIZE`] (at most 8) aligned and every
+/// byte is written only once.
+pub trait Storage {
+    const PAGE_SIZE: u32;
+    const WRITE_SIZE: u32;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), StoreError>;
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), StoreError>;
+    fn erase_page(&mut self, page: u32) -> Result<(), StoreError>;
+}
+
+/// A [`Storage`] in RAM, e.g for host tests.
+pub struct MemStorage<const N: usize> {
+    bytes: [u8; N],
+}
+
+impl<const N: usize> MemStorage<N> {
+    /// Split into two pages of `N / 2` bytes.
+    pub fn new() -> Self {
+        Self { bytes: [0xff; N] }
+    }
+
+    /// The raw contents, e.g to corrupt them on purpose.
+    pub fn bytes_mut(&mut self) -> &mut [u8; N] {
+        &mut self.bytes
+    }
+}
+
+impl<const N: usize> Default for MemStorage<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Storage for MemStorage<N> {
+    const PAGE_SIZE: u32 = N as u32 / 2;
+    const WRITE_SIZE: u32 = 8;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), StoreError> {
+        let offset = offset as usize;
+        let src = self
+            .bytes
+            .get(offset..offset + bytes.len())
+            .ok_or(StoreError::Storage)?;
+        bytes.copy_from_slice(src);
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), StoreError> {
+        let offset = offset as usize;
+        let dst = self
+            .bytes
+            .get_mut(offset..offset + bytes.len())
+            .ok_or(StoreError::Storage)?;
+        // Like flash, writing can only clear bits.
+        for (d, s) in dst.iter_mut().zip(bytes) {
+            *d &= s;
+        }
+
+        Ok(())
+    }
+
+    fn erase_page(&mut self, page: u32) -> Result<(), StoreError> {
+        let start = (page * Self::PAGE_SIZE) as usize;
+        self.bytes
+            .get_mut(start..start + Self::PAGE_SIZE as usize)
+            .ok_or(StoreError::Storage)?
+            .fill(0xff);
+
+        Ok(())
+    }
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum StoreError {
+    Storage,
+    /// The value is longer than [`MAX_VALUE_LEN`].
+    TooLarge,
+    /// Even after compaction, there's no room for the record.
+    Full,
+}
+
+/// A small key/value store, spreading its writes over two pages of flash.
+///
+/// Records are appended to the active page, the last valid one of a key being its value. Once the
+/// page is full, the latest records are copied to the other page, which then becomes the active
+/// one. Every record has a CRC and a version of its value's format, so a corrupt or outdated record
+/// just reads as missing.
+pub struct ConfigStore<S> {
+    storage: S,
+    /// The active page.
+    page: u32,
+    sequence: u32,
+    /// Where the next record goes, in the active page.
+    end: u32,
+}
+
+impl<S: Storage> ConfigStore<S> {
+    pub fn new(mut storage: S) -> Self {
+        let headers = [
+            page_sequence(&mut storage, 0),
+            page_sequence(&mut storage, 1),
+        ];
+        let (page, sequence) = match headers {
+            [Some(a), Some(b)] if b > a => (1, b),
+            [Some(a), _] => (0, a),
+            [None, Some(b)] => (1, b),
+            [None, None] => {
+                info!("No config store found, formatting");
+                (0, 0)
+            }
+        };
+        let mut store = Self {
+            storage,
+            page,
+            sequence,
+            end: HEADER_LEN,
+        };
+        if headers == [None, None] {
+            if let Err(e) = store.format(0, 1) {
+                warn!("Failed to format the config store: {}", e);
+            }
+        }
+        store.end = store.find_end();
+
+        store
+    }
+
+    /// Read the value of `key` into `buf`, if it's there and in the format of `version`.
+    pub fn get<'b>(&mut self, key: u8, version: u8, buf: &'b mut [u8]) -> Option<&'b [u8]> {
+        let record = self.latest(key)?;
+        if record.version != version {
+            warn!(
+                "Config key {} has version {}, expected {}",
+                key, record.version, version
+            );
+            return None;
+        }
+        let value = buf.get_mut(..record.len as usize)?;
+        self.storage
+            .read(self.address(record.offset + HEADER_LEN), value)
+            .ok()?;
+
+        Some(value)
+    }
+
+    pub fn set(&mut self, key: u8, version: u8, value: &[u8]) -> Result<(), StoreError> {
+        if value.len() > MAX_VALUE_LEN || key == ERASED {
+            return Err(StoreError::TooLarge);
+        }
+        let mut current = [0; MAX_VALUE_LEN];
+        if self.get(key, version, &mut current) == Some(value) {
+            // Spare the flash.
+            return Ok(());
+        }
+
+        let len = record_len::<S>(value.len() as u32);
+        if self.end + len > S::PAGE_SIZE {
+            self.compact()?;
+            if self.end + len > S::PAGE_SIZE {
+                return Err(StoreError::Full);
+            }
+        }
+
+        let mut record = [ERASED; HEADER_LEN as usize + MAX_VALUE_LEN + 8];
+        record[0] = key;
+        record[1] = version;
+        record[2..4].copy_from_slice(&(value.len() as u16).to_le_bytes());
+        record[4..8].copy_from_slice(&crc(key, version, value).to_le_bytes());
+        record[8..8 + value.len()].copy_from_slice(value);
+        self.storage
+            .write(self.address(self.end), &record[..len as usize])?;
+        self.end += len;
+
+        Ok(())
+    }
+
+    /// Move the latest records to the other page.
+    fn compact(&mut self) -> Result<(), StoreError> {
+        let from = self.page;
+        let to = 1 - from;
+        info!("Compacting config store into page {}", to);
+        self.storage.erase_page(to)?;
+
+        let mut end = HEADER_LEN;
+        let mut offset = HEADER_LEN;
+        let mut buf = [0; HEADER_LEN as usize + MAX_VALUE_LEN + 8];
+        while let Some(record) = self.record_at(offset) {
+            let len = record_len::<S>(record.len as u32);
+            let latest = self.latest(record.key).map(|latest| latest.offset);
+            if record.valid && latest == Some(offset) {
+                let bytes = &mut buf[..len as usize];
+                self.storage.read(self.address(offset), bytes)?;
+                self.storage.write(to * S::PAGE_SIZE + end, bytes)?;
+                end += len;
+            }
+            offset += len;
+        }
+
+        // The header goes last, so the old page stays active if this is interrupted.
+        self.write_header(to, self.sequence + 1)?;
+        self.page = to;
+        self.sequence += 1;
+        self.end = end;
+
+        Ok(())
+    }
+
+    fn format(&mut self, page: u32, sequence: u32) -> Result<(), StoreError> {
+        self.storage.erase_page(page)?;
+        self.write_header(page, sequence)?;
+        self.page = page;
+        self.sequence = sequence;
+        self.end = HEADER_LEN;
+
+        Ok(())
+    }
+
+    fn write_header(&mut self, page: u32, sequence: u32) -> Result<(), StoreError> {
+        let mut header = [0; HEADER_LEN as usize];
+        header[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..].copy_from_slice(&sequence.to_le_bytes());
+
+        self.storage.write(page * S::PAGE_SIZE, &header)
+    }
+
+    /// The last valid record of `key`.
+    fn latest(&mut self, key: u8) -> Option<Record> {
+        let mut latest = None;
+        let mut offset = HEADER_LEN;
+        while let Some(record) = self.record_at(offset) {
+            if record.valid && record.key == key {
+                latest = Some(record);
+            }
+            offset += record_len::<S>(record.len as u32);
+        }
+
+        latest
+    }
+
+    /// Where the records end, or the end of the page if it's corrupt past some point.
+    fn find_end(&mut self) -> u32 {
+        let mut offset = HEADER_LEN;
+        while let Some(record) = self.record_at(offset) {
+            offset += record_len::<S>(record.len as u32);
+        }
+        if offset + HEADER_LEN <= S::PAGE_SIZE && !self.is_erased(offset) {
+            warn!("Config store corrupt at {}, will compact", offset);
+            return S::PAGE_SIZE;
+        }
+
+        offset
+    }
+
+    fn record_at(&mut self, offset: u32) -> Option<Record> {
+        if offset + HEADER_LEN > S::PAGE_SIZE {
+            return None;
+        }
+        let mut header = [0; HEADER_LEN as usize];
+        self.storage.read(self.address(offset), &mut header).ok()?;
+        let [key, version, l0, l1, c0, c1, c2, c3] = header;
+        let len = u16::from_le_bytes([l0, l1]);
+        if key == ERASED
+            || len as usize > MAX_VALUE_LEN
+            || offset + record_len::<S>(len as u32) > S::PAGE_SIZE
+        {
+            return None;
+        }
+
+        let mut value = [0; MAX_VALUE_LEN];
+        let value = &mut value[..len as usize];
+        self.storage
+            .read(self.address(offset + HEADER_LEN), value)
+            .ok()?;
+        let valid = crc(key, version, value) == u32::from_le_bytes([c0, c1, c2, c3]);
+        if !valid {
+            warn!("Corrupt config record for key {} at {}", key, offset);
+        }
+
+        Some(Record {
+            key,
+            version,
+            len,
+            offset,
+            valid,
+        })
+    }
+
+    fn is_erased(&mut self, offset: u32) -> bool {
+        let mut header = [0; HEADER_LEN as usize];
+        self.storage.read(self.address(offset), &mut header).is_ok() && header == [ERASED; 8]
+    }
+
+    fn address(&self, offset: u32) -> u32 {
+        self.page * S::PAGE_SIZE + offset
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Record {
+    key: u8,
+    version: u8,
+    len: u16,
+    offset: u32,
+    valid: bool,
+}
+
+fn page_sequence<S: Storage>(storage: &mut S, page: u32) -> Option<u32> {
+    let mut header = [0; HEADER_LEN as usize];
+    storage.read(page * S::PAGE_SIZE, &mut header).ok()?;
+    let [m0, m1, m2, m3, s0, s1, s2, s3] = header;
+
+    (u32::from_le_bytes([m0, m1, m2, m3]) == MAGIC).then(|| u32::from_le_bytes([s0, s1, s2, s3]))
+}
+
+/// Length of a record with a `value_len` bytes long value, padded to the write size.
+fn record_len<S: Storage>(value_len: u32) -> u32 {
+    (HEADER_LEN + value_len).next_multiple_of(S::WRITE_SIZE)
+}
+
+fn crc(key: u8, version: u8, value: &[u8]) -> u32 {
+    let mut digest = CRC.digest();
+    digest.update(&[key, version]);
+    digest.update(&(value.len() as u16).to_le_bytes());
+    digest.update(value);
+
+    digest.finalize()
+}
+
+pub const MAX_VALUE_LEN: usize = 64;
+/// Both the page header and record header length.
+const HEADER_LEN: u32 = 8;
+const MAGIC: u32 = 0x6c32_7463;
+const ERASED: u8 = 0xff;
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pages of 128 bytes, room for 7 records of 4 bytes long values.
+    type Mem = MemStorage<256>;
+
+    fn get(store: &mut ConfigStore<impl Storage>, key: u8) -> Option<[u8; 4]> {
+        let mut buf = [0; 4];
+        store.get(key, 1, &mut buf)?;
+
+        Some(buf)
+    }
+
+    fn reload(store: ConfigStore<Mem>) -> ConfigStore<Mem> {
+        ConfigStore::new(store.storage)
+    }
+
+    #[test]
+    fn rewrite() {
+        let mut store = ConfigStore::new(Mem::new());
+        assert_eq!(get(&mut store, 1), None);
+        store.set(1, 1, b"aaaa").unwrap();
+        store.set(2, 1, b"bbbb").unwrap();
+        store.set(1, 1, b"cccc").unwrap();
+
+        let mut store = reload(store);
+        assert_eq!(get(&mut store, 1), Some(*b"cccc"));
+        assert_eq!(get(&mut store, 2), Some(*b"bbbb"));
+        // Another format.
+        assert_eq!(store.get(1, 2, &mut [0; 4]), None);
+        assert_eq!(
+            store.set(1, 1, &[0; MAX_VALUE_LEN + 1]),
+            Err(StoreError::TooLarge)
+        );
+    }
+
+    #[test]
+    fn compaction() {
+        let mut store = ConfigStore::new(Mem::new());
+        store.set(2, 1, b"keep").unwrap();
+        for i in 0..20u32 {
+            store.set(1, 1, &i.to_le_bytes()).unwrap();
+            assert_eq!(get(&mut store, 2), Some(*b"keep"));
+        }
+        // Compacted back and forth between the pages.
+        assert_eq!((store.page, store.sequence), (1, 4));
+
+        let mut store = reload(store);
+        assert_eq!((store.page, store.sequence), (1, 4));
+        assert_eq!(get(&mut store, 1), Some(19u32.to_le_bytes()));
+        assert_eq!(get(&mut store, 2), Some(*b"keep"));
+    }
+
+    #[test]
+    fn corrupt() {
+        let mut store = ConfigStore::new(Mem::new());
+        store.set(1, 1, b"aaaa").unwrap();
+        store.set(1, 1, b"bbbb").unwrap();
+        // The value of the second record, after the page and record headers, and the first record.
+        store.storage.bytes_mut()[8 + 16 + 8] = b'x';
+
+        let mut store = reload(store);
+        assert_eq!(get(&mut store, 1), Some(*b"aaaa"));
+        // Dropped by the next compaction.
+        for _ in 0..5 {
+            store.set(2, 1, b"cccc").unwrap();
+            store.set(2, 1, b"dddd").unwrap();
+        }
+        assert_eq!(store.page, 1);
+        assert_eq!(get(&mut reload(store), 1), Some(*b"aaaa"));
+    }
+
+    /// Loses power right before writing a page header.
+    struct PowerLoss(Mem);
+
+    impl Storage for PowerLoss {
+        const PAGE_SIZE: u32 = Mem::PAGE_SIZE;
+        const WRITE_SIZE: u32 = Mem::WRITE_SIZE;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), StoreError> {
+            self.0.read(offset, bytes)
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), StoreError> {
+            if offset.is_multiple_of(Self::PAGE_SIZE) {
+                return Err(StoreError::Storage);
+            }
+
+            self.0.write(offset, bytes)
+        }
+
+        fn erase_page(&mut self, page: u32) -> Result<(), StoreError> {
+            self.0.erase_page(page)
+        }
+    }
+
+    #[test]
+    fn power_loss_during_compaction() {
+        let mut store = ConfigStore::new(Mem::new());
+        for i in 0..7u32 {
+            store.set(1, 1, &i.to_le_bytes()).unwrap();
+        }
+
+        let mut store = ConfigStore::new(PowerLoss(store.storage));
+        assert!(store.set(1, 1, b"lost").is_err());
+
+        // The old page is still the active one, and the next write compacts again.
+        let mut store = ConfigStore::new(store.storage.0);
+        assert_eq!(store.page, 0);
+        assert_eq!(get(&mut store, 1), Some(6u32.to_le_bytes()));
+        store.set(1, 1, b"next").unwrap();
+        assert_eq!(store.page, 1);
+        assert_eq!(get(&mut reload(store), 1), Some(*b"next"));
+    }
+}
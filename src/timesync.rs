@@ -0,0 +1,128 @@
+/// Wall-clock time in milliseconds since the UNIX epoch, derived from the monotonic uptime.
+///
+/// The clock is anchored at a known (epoch, uptime) pair and advances with the uptime, corrected
+/// by the estimated drift of the local oscillator.
+#[derive(defmt::Format, Clone, Copy)]
+pub struct Clock {
+    epoch_ms: u64,
+    uptime_ms: u64,
+    drift_ppm: i32,
+}
+
+impl Clock {
+    pub const fn new(epoch_ms: u64, uptime_ms: u64) -> Self {
+        Self {
+            epoch_ms,
+            uptime_ms,
+            drift_ppm: 0,
+        }
+    }
+
+    pub fn now(&self, uptime_ms: u64) -> u64 {
+        let elapsed = uptime_ms.saturating_sub(self.uptime_ms) as i64;
+        let correction = elapsed * self.drift_ppm as i64 / 1_000_000;
+
+        (self.epoch_ms as i64 + elapsed + correction).max(0) as u64
+    }
+
+    pub fn set(&mut self, epoch_ms: u64, uptime_ms: u64) {
+        self.epoch_ms = epoch_ms;
+        self.uptime_ms = uptime_ms;
+    }
+}
+
+/// Disciplines the local [`Clock`] to the time broadcast by the controller.
+pub struct TimeSync {
+    clock: Clock,
+    last_sync_ms: Option<u64>,
+    last_offset_ms: i64,
+}
+
+impl TimeSync {
+    pub const fn new(clock: Clock) -> Self {
+        Self {
+            clock,
+            last_sync_ms: None,
+            last_offset_ms: 0,
+        }
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Apply a time received from the controller at local uptime `uptime_ms`.
+    ///
+    /// `remote_ms` must already be compensated for the time on air of the message.
+    pub fn update(&mut self, uptime_ms: u64, remote_ms: u64) -> SyncStatus {
+        let offset = remote_ms as i64 - self.clock.now(uptime_ms) as i64;
+
+        if let Some(last_sync_ms) = self.last_sync_ms {
+            let interval = uptime_ms.saturating_sub(last_sync_ms) as i64;
+            // Large offsets are clock steps (e.g the controller got its time set), not drift.
+            if interval >= MIN_DRIFT_INTERVAL_MS && offset.abs() < MAX_DRIFT_OFFSET_MS {
+                // Half of the residual error per update, to filter out jitter in reception time.
+                let residual_ppm = offset * 1_000_000 / interval;
+                let drift_ppm = self.clock.drift_ppm as i64 + residual_ppm / 2;
+                self.clock.drift_ppm = drift_ppm.clamp(-MAX_DRIFT_PPM, MAX_DRIFT_PPM) as i32;
+            }
+        }
+
+        self.clock.set(remote_ms, uptime_ms);
+        self.last_sync_ms = Some(uptime_ms);
+        self.last_offset_ms = offset;
+
+        self.status(uptime_ms)
+    }
+
+    pub fn status(&self, uptime_ms: u64) -> SyncStatus {
+        let state = match self.last_sync_ms {
+            None => SyncState::Unsynced,
+            Some(last) if uptime_ms.saturating_sub(last) > SYNC_TIMEOUT_MS => SyncState::Holdover,
+            Some(_) => SyncState::Synced,
+        };
+
+        SyncStatus {
+            state,
+            offset_ms: self.last_offset_ms.clamp(i32::MIN as i64, i32::MAX as i64) as i32,
+            drift_ppm: self.clock.drift_ppm as i16,
+        }
+    }
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum SyncState {
+    /// No time received from the controller yet.
+    Unsynced = 0,
+    Synced = 1,
+    /// Synced in the past but no time received from the controller recently.
+    Holdover = 2,
+}
+
+impl SyncState {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Unsynced),
+            1 => Some(Self::Synced),
+            2 => Some(Self::Holdover),
+            _ => None,
+        }
+    }
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy)]
+pub struct SyncStatus {
+    pub state: SyncState,
+    /// The offset corrected by the last time sync.
+    pub offset_ms: i32,
+    /// Estimated drift of the local clock compared to the controller's.
+    pub drift_ppm: i16,
+}
+
+/// How often the controller broadcasts its time.
+pub const TIME_SYNC_INTERVAL_SECS: u64 = 60;
+const SYNC_TIMEOUT_MS: u64 = 5 * TIME_SYNC_INTERVAL_SECS * 1000;
+const MIN_DRIFT_INTERVAL_MS: i64 = 10_000;
+const MAX_DRIFT_OFFSET_MS: i64 = 1_000;
+const MAX_DRIFT_PPM: i64 = 500;